@@ -1,9 +1,15 @@
 use std::io::{self, Write};
 use std::net::UdpSocket;
 use std::str::FromStr;
-use clap::{command, Parser};
-use shared::requests::{AvailabilityRequest, BookRequest, CancelBookingRequest, ExtendBookingRequest, MonitorFacilityRequest, OffsetBookingRequest, RawRequest, RequestType};
-use shared::time::{Day, Hour, Minute, Time};
+use chrono::NaiveDate;
+use clap::Parser;
+use shared::facility::FacilityAttributes;
+use shared::protocol::{self, PROTOCOL_VERSION};
+use shared::{codec::DEFAULT_MAX_LENGTH, LengthEncoding};
+use shared::recurrence::{RecurrenceEnd, RecurrenceRule};
+use shared::responses::ResponseType;
+use shared::requests::{AdminAction, AdminRequest, AvailabilityRequest, BookRequest, CancelBookingRequest, ExtendBookingRequest, MonitorFacilityRequest, OffsetBookingRequest, RawRequest, RecurringBookRequest, RequestType, RetirePolicy, SearchFacilitiesRequest};
+use shared::time::{today, Day, Duration, Hour, Interval, Minute, Time};
 use socket::SenderReceiver;
 use uuid::Uuid;
 
@@ -24,7 +30,17 @@ struct Args {
     use_reliability: bool,
     /// The proportion of packets to duplicate (only if retries are enabled)
     #[arg(short, long, default_value_t = 0.0)]
-    duplicate_packet_rate: f64
+    duplicate_packet_rate: f64,
+    /// The protocol version to send requests in (falls back to an older one if the server requires it)
+    #[arg(short, long, default_value_t = PROTOCOL_VERSION)]
+    protocol_version: u8,
+    /// Whether to encode lengths as varints, allowing strings and lists longer than 65535 bytes
+    /// (up to the server's max message size, and replies up to --max-message-size)
+    #[arg(short, long)]
+    varint_lengths: bool,
+    /// The largest reply which can be received, in bytes, which also bounds the length of any string or list in it
+    #[arg(short, long, default_value_t = DEFAULT_MAX_LENGTH)]
+    max_message_size: usize
 }
 
 fn main() {
@@ -35,10 +51,20 @@ fn main() {
     println!("======================");
 
     let socket = UdpSocket::bind(args.addr).unwrap();
-    let mut sender_receiver = SenderReceiver::new(socket, args.use_reliability, args.duplicate_packet_rate);
+    let length_encoding = match args.varint_lengths {
+        true => LengthEncoding::Varint,
+        false => LengthEncoding::U16
+    };
+    let mut sender_receiver = SenderReceiver::new(
+        socket, 
+        args.use_reliability, 
+        args.duplicate_packet_rate, 
+        args.protocol_version, 
+        length_encoding
+    ).with_max_message_size(args.max_message_size);
 
     loop {  
-        let request = get_user_request();
+        let request = get_user_request(sender_receiver.version());
         println!("Request created: {:?}", request);
 
         let seconds_to_monitor = if let RequestType::Monitor(req) = &request.request_type {
@@ -50,7 +76,10 @@ fn main() {
         match sender_receiver.send(request, &args.server_addr) {
             Ok(response) => {
                 println!("--- Response ---");
-                println!("{}", response.message);
+                if let ResponseType::Error(error) = &response.response_type {
+                    print!("Error (code {}): ", error.code());
+                }
+                println!("{}", response.response_type);
                 println!("----------------");
             }
             Err(err) => {
@@ -68,12 +97,13 @@ fn main() {
     
 }
 
-fn get_user_request() -> RawRequest {
+/// Gets a request from the user, only offering what the given protocol version supports.
+fn get_user_request(version: u8) -> RawRequest {
     println!("Facility Booking System");
     println!("======================");
     
     let request_id = Uuid::new_v4();
-    let request_type = get_request_type();
+    let request_type = get_request_type(version);
 
     RawRequest {
         request_id,
@@ -81,7 +111,11 @@ fn get_user_request() -> RawRequest {
     }
 }
 
-fn get_request_type() -> RequestType {
+fn get_request_type(version: u8) -> RequestType {
+    let has_recurrence = protocol::has_recurrence(version);
+    let has_capacity = protocol::has_capacity(version);
+    let has_search = protocol::has_search(version);
+    let has_admin = protocol::has_admin(version);
     println!("Please select a request type:");
     println!("1. Check facility availability");
     println!("2. Book a facility");
@@ -89,19 +123,37 @@ fn get_request_type() -> RequestType {
     println!("4. Monitor a facility");
     println!("5. Cancel a booking");
     println!("6. Extend a booking");
+    if has_recurrence {
+        println!("7. Book a facility repeatedly");
+    }
+    if has_search {
+        println!("8. Search for facilities");
+    }
+    if has_admin {
+        println!("9. Administer facilities");
+    }
     
-    let choice = get_input_with_prompt("Enter your choice (1-6): ");
+    let max_choice = match (has_admin, has_search, has_recurrence) {
+        (true, _, _) => 9,
+        (false, true, _) => 8,
+        (false, false, true) => 7,
+        (false, false, false) => 6
+    };
+    let choice = get_input_with_prompt(&format!("Enter your choice (1-{max_choice}): "));
     
     match choice.trim() {
         "1" => RequestType::Availability(get_availability_request()),
-        "2" => RequestType::Book(get_book_request()),
-        "3" => RequestType::Offset(get_offset_booking_request()),
+        "2" => RequestType::Book(get_book_request(has_capacity)),
+        "3" => RequestType::Offset(get_offset_booking_request(has_recurrence)),
         "4" => RequestType::Monitor(get_monitor_facility_request()),
-        "5" => RequestType::Cancel(get_cancel_booking_request()),
+        "5" => RequestType::Cancel(get_cancel_booking_request(has_recurrence)),
         "6" => RequestType::Extend(get_extend_booking_request()),
+        "7" if has_recurrence => RequestType::RecurringBook(get_recurring_book_request(has_capacity)),
+        "8" if has_search => RequestType::SearchFacilities(get_search_facilities_request()),
+        "9" if has_admin => RequestType::Admin(get_admin_request()),
         _ => {
             println!("Invalid choice. Please try again.");
-            get_request_type()
+            get_request_type(version)
         }
     }
 }
@@ -111,59 +163,176 @@ fn get_availability_request() -> AvailabilityRequest {
     
     let facility_name = get_input_with_prompt("Enter facility name: ");
     
-    println!("Enter days to check (comma-separated, e.g., Mon,Tue,Wed):");
-    let days_input = get_input_with_prompt("Days: ");
-    
-    let days = days_input
-        .split(',')
-        .map(|day| day.trim())
-        .filter(|day| !day.is_empty())
-        .map(|day| Day::from_str(day).unwrap_or_else(|_| {
-            println!("Warning: Invalid day '{}', defaulting to Monday", day);
-            Day::Monday
-        }))
-        .collect();
+    println!("Enter the dates to check (YYYY-MM-DD, or a day like Mon for its next occurrence):");
+    let start_date = get_date_input("From: ");
+    let end_date = get_date_input("To: ");
+
+    println!("Enter days to include (comma-separated, e.g., Mon,Tue,Wed; leave empty for every day):");
+    let days = get_days_input("Days: ");
     
     AvailabilityRequest {
         facility_name,
+        start_date,
+        end_date,
         days,
     }
 }
 
-fn get_book_request() -> BookRequest {
+fn get_book_request(has_capacity: bool) -> BookRequest {
     println!("\n-- Booking a Facility --");
     
     let facility_name = get_input_with_prompt("Enter facility name: ");
     
-    println!("- Start time -");
-    let start_time = get_time_input();
-    println!("- End time -");
-    let end_time = get_time_input();
+    let interval = get_interval_input("Start time", "End time");
+
+    let seats = if has_capacity { get_seats_input() } else { 1 };
     
     BookRequest {
         facility_name,
-        start_time,
-        end_time,
+        interval,
+        seats,
+    }
+}
+
+fn get_recurring_book_request(has_capacity: bool) -> RecurringBookRequest {
+    println!("\n-- Booking a Facility Repeatedly --");
+    
+    let facility_name = get_input_with_prompt("Enter facility name: ");
+    
+    let interval = get_interval_input("First start time", "First end time");
+
+    println!("Enter days to repeat on (comma-separated, e.g., Mon,Wed; leave empty for the first day only):");
+    let days = get_days_input("Days: ");
+    let interval_weeks = get_number_input::<u8>("Repeat every how many weeks: ");
+
+    let end = loop {
+        let end_input = get_input_with_prompt("End after a date or a number of occurrences? (d/n): ");
+        match end_input.trim().to_lowercase().as_str() {
+            "d" => {
+                println!("Enter the last date (YYYY-MM-DD, or a day like Mon for its next occurrence):");
+                break RecurrenceEnd::Until(get_date_input("Until: "));
+            },
+            "n" => break RecurrenceEnd::Count(get_number_input::<u16>("Occurrences: ")),
+            _ => println!("Invalid choice. Please enter d or n.")
+        }
+    };
+
+    let seats = if has_capacity { get_seats_input() } else { 1 };
+    
+    RecurringBookRequest {
+        facility_name,
+        interval,
+        rule: RecurrenceRule {
+            days,
+            interval_weeks,
+            end
+        },
+        seats,
+    }
+}
+
+fn get_search_facilities_request() -> SearchFacilitiesRequest {
+    println!("\n-- Searching for Facilities --");
+    println!("Leave any filter empty to match every facility.");
+
+    let min_capacity = get_optional_number_input::<u16>("Fewest seats: ").unwrap_or(0);
+    let building = Some(get_input_with_prompt("Building: ")).filter(|building| !building.is_empty());
+    let floor = get_optional_number_input::<i16>("Floor: ");
+    let equipment = get_input_with_prompt("Equipment (comma-separated, e.g., projector,whiteboard): ")
+        .split(',')
+        .map(|tag| tag.trim().to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    let accessible = get_yes_no_input("Step-free access only? (y/n): ");
+    let free_during = get_yes_no_input("Only facilities free at a given time? (y/n): ")
+        .then(|| get_interval_input("Start time", "End time"));
+
+    SearchFacilitiesRequest {
+        min_capacity,
+        building,
+        floor,
+        equipment,
+        accessible,
+        free_during,
+    }
+}
+
+fn get_admin_request() -> AdminRequest {
+    println!("\n-- Administering Facilities --");
+
+    let admin_token = get_input_with_prompt("Enter admin token: ");
+    AdminRequest {
+        admin_token,
+        action: get_admin_action(),
+    }
+}
+
+fn get_admin_action() -> AdminAction {
+    println!("1. Create a facility");
+    println!("2. Rename a facility");
+    println!("3. Retire a facility");
+    println!("4. List facilities");
+
+    match get_input_with_prompt("Enter your choice (1-4): ").as_str() {
+        "1" => {
+            let facility_name = get_input_with_prompt("Enter facility name: ");
+            let capacity = get_seats_input();
+            let building = get_input_with_prompt("Building: ");
+            let floor = get_optional_number_input::<i16>("Floor (leave empty for 0): ").unwrap_or(0);
+            let equipment = get_input_with_prompt("Equipment (comma-separated, e.g., projector,whiteboard): ")
+                .split(',')
+                .map(|tag| tag.trim().to_string())
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            let accessible = get_yes_no_input("Step-free access? (y/n): ");
+            let turnaround_buffer = get_duration_input("Enter time to leave free between bookings (H:MM, e.g., 0:15): ");
+            AdminAction::CreateFacility {
+                facility_name,
+                attributes: FacilityAttributes { capacity, building, floor, equipment, accessible },
+                turnaround_buffer,
+            }
+        },
+        "2" => AdminAction::RenameFacility {
+            facility_name: get_input_with_prompt("Enter facility name: "),
+            new_name: get_input_with_prompt("Enter new name: "),
+        },
+        "3" => {
+            let facility_name = get_input_with_prompt("Enter facility name: ");
+            println!("What should happen to its bookings?");
+            println!("1. Don't retire it if it has any");
+            println!("2. Cancel them");
+            println!("3. Move them to another facility");
+            let policy = loop {
+                match get_input_with_prompt("Enter your choice (1-3): ").as_str() {
+                    "1" => break RetirePolicy::Reject,
+                    "2" => break RetirePolicy::Cancel,
+                    "3" => break RetirePolicy::MoveTo { facility_name: get_input_with_prompt("Enter facility to move them to: ") },
+                    _ => println!("Invalid choice. Please try again.")
+                }
+            };
+            AdminAction::RetireFacility { facility_name, policy }
+        },
+        "4" => AdminAction::ListFacilities,
+        _ => {
+            println!("Invalid choice. Please try again.");
+            get_admin_action()
+        }
     }
 }
 
-fn get_offset_booking_request() -> OffsetBookingRequest {
+fn get_offset_booking_request(has_recurrence: bool) -> OffsetBookingRequest {
     println!("\n-- Modifying a Booking --");
     
     let booking_id = get_uuid_input("Enter booking ID: ");
     
-    println!("Enter time offset:");
-    let offset_hours = get_number_input::<Hour>("Hours: ");
-    let offset_min = get_number_input::<Minute>("Minutes: ");
-    
-    let negative_input = get_input_with_prompt("Move booking earlier? (y/n): ");
-    let negative = negative_input.trim().to_lowercase() == "y";
+    let offset = get_duration_input("Enter time offset (H:MM, e.g., 1:30; negative to move earlier): ");
+
+    let whole_series = has_recurrence && get_yes_no_input("Offset every occurrence, if it's a recurring booking? (y/n): ");
     
     OffsetBookingRequest {
         booking_id,
-        offset_hours,
-        offset_min,
-        negative,
+        offset,
+        whole_series,
     }
 }
 
@@ -179,13 +348,15 @@ fn get_monitor_facility_request() -> MonitorFacilityRequest {
     }
 }
 
-fn get_cancel_booking_request() -> CancelBookingRequest {
+fn get_cancel_booking_request(has_recurrence: bool) -> CancelBookingRequest {
     println!("\n-- Cancelling a Booking --");
     
     let booking_id = get_uuid_input("Enter booking ID to cancel: ");
+    let whole_series = has_recurrence && get_yes_no_input("Cancel every occurrence, if it's a recurring booking? (y/n): ");
     
     CancelBookingRequest {
         booking_id,
+        whole_series,
     }
 }
 
@@ -194,14 +365,11 @@ fn get_extend_booking_request() -> ExtendBookingRequest {
     
     let booking_id = get_uuid_input("Enter booking ID: ");
     
-    println!("Enter extension time:");
-    let extend_hours = get_number_input::<Hour>("Hours: ");
-    let extend_min = get_number_input::<Minute>("Minutes: ");
+    let extension = get_duration_input("Enter extension time (H:MM, e.g., 0:45; negative to shorten): ");
     
     ExtendBookingRequest {
         booking_id,
-        extend_hours,
-        extend_min,
+        extension,
     }
 }
 
@@ -226,6 +394,55 @@ fn get_number_input<T: FromStr>(prompt: &str) -> T {
     }
 }
 
+/// Gets a number, or `None` if left empty.
+fn get_optional_number_input<T: FromStr>(prompt: &str) -> Option<T> {
+    loop {
+        let input = get_input_with_prompt(prompt);
+        if input.is_empty() {
+            return None;
+        }
+        match input.parse::<T>() {
+            Ok(value) => return Some(value),
+            Err(_) => println!("Invalid input. Please enter a valid number."),
+        }
+    }
+}
+
+/// Gets the number of seats to book, defaulting to 1 if left empty.
+fn get_seats_input() -> u16 {
+    get_optional_number_input("Enter number of seats (leave empty for 1): ").unwrap_or(1)
+}
+
+fn get_yes_no_input(prompt: &str) -> bool {
+    let input = get_input_with_prompt(prompt);
+    input.trim().to_lowercase() == "y"
+}
+
+/// Gets comma-separated days, defaulting any invalid ones to Monday.
+fn get_days_input(prompt: &str) -> Vec<Day> {
+    let days_input = get_input_with_prompt(prompt);
+    
+    days_input
+        .split(',')
+        .map(|day| day.trim())
+        .filter(|day| !day.is_empty())
+        .map(|day| Day::from_str(day).unwrap_or_else(|_| {
+            println!("Warning: Invalid day '{}', defaulting to Monday", day);
+            Day::Monday
+        }))
+        .collect()
+}
+
+fn get_duration_input(prompt: &str) -> Duration {
+    loop {
+        let input = get_input_with_prompt(prompt);
+        match Duration::from_str(&input) {
+            Ok(duration) => return duration,
+            Err(err) => println!("Invalid duration ({err}). Please use H:MM format."),
+        }
+    }
+}
+
 fn get_uuid_input(prompt: &str) -> Uuid {
     loop {
         let input = get_input_with_prompt(prompt);
@@ -236,16 +453,39 @@ fn get_uuid_input(prompt: &str) -> Uuid {
     }
 }
 
-fn get_time_input() -> Time {
-    // First get the day
-    println!("Enter day (e.g., Mon, Tue, Wed):");
-    let day = loop {
-        let day_str = get_input_with_prompt("Day: ");
-        match Day::from_str(&day_str) {
-            Ok(day) => break day,
-            Err(_) => println!("Invalid day format. Please try again."),
+/// Gets a date, either as YYYY-MM-DD or as a day, which is taken to be its next occurrence.
+fn get_date_input(prompt: &str) -> NaiveDate {
+    loop {
+        let date_str = get_input_with_prompt(prompt);
+        if let Ok(date) = NaiveDate::from_str(&date_str) {
+            return date;
         }
-    };
+        if let Ok(day) = Day::from_str(&date_str) {
+            return day.next_occurrence(today());
+        }
+        println!("Invalid date format. Please use YYYY-MM-DD or a day (e.g., Mon).");
+    }
+}
+
+/// Gets a start and end time, until the end isn't before the start.
+fn get_interval_input(start_label: &str, end_label: &str) -> Interval {
+    loop {
+        println!("- {start_label} -");
+        let start_time = get_time_input();
+        println!("- {end_label} -");
+        let end_time = get_time_input();
+
+        match Interval::new(start_time, end_time) {
+            Ok(interval) => return interval,
+            Err(err) => println!("Invalid times ({err}). Please try again."),
+        }
+    }
+}
+
+fn get_time_input() -> Time {
+    // First get the date
+    println!("Enter date (YYYY-MM-DD, or a day like Mon for its next occurrence):");
+    let date = get_date_input("Date: ");
     
     // Then get the time (hours and minutes)
     loop {
@@ -259,7 +499,7 @@ fn get_time_input() -> Time {
                 if let Ok(hour) = hour {
                     if let Ok(minute) = minute {
                         return Time { 
-                            day, 
+                            date, 
                             hour,
                             minute 
                         };
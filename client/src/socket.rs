@@ -1,22 +1,49 @@
-use std::{error::Error, io::ErrorKind, net::UdpSocket, thread::sleep, time::{Duration, SystemTime}};
+use std::{error::Error as _, io::ErrorKind, mem, net::{SocketAddr, UdpSocket}, thread::sleep, time::{Duration, SystemTime}};
 use rand::{rngs::ThreadRng, Rng};
-use shared::{requests::RawRequest, responses::RawResponse, Byteable};
+use uuid::Uuid;
+use shared::{codec::DEFAULT_MAX_LENGTH, error::Error, fragment::{self, Reassembler, Received}, protocol::{self, Header, UpgradeRequired, FLAG_UPGRADE_REQUIRED}, requests::RawRequest, responses::RawResponse, Byteable, LengthEncoding};
 
 const BUF_SIZE: usize = u16::MAX as usize;
 const TIMEOUT_MS: u64 = 500;
 const MAX_RETRIES: usize = 10;
 
-/// Wraps a `UdpSocket` and provides (de)serialization and (if enabled) retries.
+/// Wraps a `UdpSocket` and provides (de)serialization, fragmentation and (if enabled) retries.
 pub struct SenderReceiver {
     socket: UdpSocket,
     rng: ThreadRng,
     use_reliability: bool,
-    duplicate_packet_rate: f64
+    duplicate_packet_rate: f64,
+    version: u8,
+    length_encoding: LengthEncoding,
+    reassembler: Reassembler,
+    /// The largest reply which can be received, in bytes, which is also the longest length prefix accepted.
+    max_message_size: usize
+}
+
+/// A decoded reply from the server.
+enum Reply {
+    Response(RawResponse),
+    UpgradeRequired(UpgradeRequired)
+}
+
+impl Reply {
+    fn request_id(&self) -> Uuid {
+        match self {
+            Reply::Response(response) => response.request_id,
+            Reply::UpgradeRequired(upgrade) => upgrade.request_id
+        }
+    }
 }
 
 impl SenderReceiver {
-    /// Create the `SenderReceiver`.
-    pub fn new(socket: UdpSocket, use_reliability: bool, duplicate_packet_rate: f64) -> Self {
+    /// Create the `SenderReceiver`, which sends requests at the given protocol version and length encoding.
+    pub fn new(
+        socket: UdpSocket, 
+        use_reliability: bool, 
+        duplicate_packet_rate: f64, 
+        version: u8, 
+        length_encoding: LengthEncoding
+    ) -> Self {
         socket
             .set_read_timeout(Some(Duration::from_millis(TIMEOUT_MS)))
             .expect("Should not have issues setting timeout");
@@ -24,21 +51,85 @@ impl SenderReceiver {
             socket,
             rng: rand::rng(),
             use_reliability,
-            duplicate_packet_rate
+            duplicate_packet_rate,
+            version,
+            length_encoding,
+            reassembler: Reassembler::new(),
+            max_message_size: DEFAULT_MAX_LENGTH
         }
     }
 
+    /// Accepts replies up to the size, and strings and lists in them up to that length (`DEFAULT_MAX_LENGTH` otherwise).
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self.reassembler = mem::take(&mut self.reassembler).with_max_message_size(max_message_size);
+        self
+    }
+
+    /// The protocol version requests are currently sent in.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
     /// Send a message and receive a response.
-    pub fn send(&mut self, request: RawRequest, addr: &String) -> Result<RawResponse, String> {
+    /// 
+    /// If the server doesn't support our protocol version, switches to the newest version
+    /// supported by both sides (if any) and sends it again.
+    pub fn send(&mut self, request: RawRequest, addr: &String) -> Result<RawResponse, Error> {
+        match self.send_once(&request, addr)? {
+            Reply::Response(response) => Ok(response),
+            Reply::UpgradeRequired(upgrade) => {
+                let unsupported = Error::UnsupportedVersion {
+                    min_version: upgrade.min_version,
+                    max_version: upgrade.max_version
+                };
+                let version = upgrade.negotiate().ok_or(unsupported.clone())?;
+                println!("Server doesn't support protocol version {}; switching to version {version}", self.version);
+                self.version = version;
+
+                match self.send_once(&request, addr)? {
+                    Reply::Response(response) => Ok(response),
+                    Reply::UpgradeRequired(_) => Err(unsupported)
+                }
+            }
+        }
+    }
+
+    /// Send a message and receive the server's reply.
+    /// 
+    /// If reliability is on and a timeout occurs partway through receiving a fragmented reply,
+    /// only the missing fragments are asked for, instead of sending the whole request again.
+    fn send_once(&mut self, request: &RawRequest, addr: &String) -> Result<Reply, Error> {
         let request_id = request.request_id;
-        let request_bytes = request.to_bytes();
+        let header = Header::new(self.version).with_length_encoding(self.length_encoding);
+        let request_bytes = protocol::encode_message(header, request)
+            .map_err(|reason| Error::EncodeError { reason })?;
+        let datagrams = fragment::split(request_bytes)
+            .map_err(|reason| Error::EncodeError { reason })?;
         let mut recv_buffer = vec![0; BUF_SIZE];
+        self.reassembler.clear(); // fragments of earlier replies are no use now
 
         if self.use_reliability {
             for retry in 0..MAX_RETRIES {
-                self.socket
-                    .send_to(&request_bytes, &addr)
-                    .map_err(|err| format!("Error while sending request on retry {retry}: {err} (source: {:?})", err.source()))?;
+                let missing = self.reassembler.missing();
+                if retry == 0 || missing.is_empty() {
+                    self.send_datagrams(&datagrams, addr)
+                        .map_err(|err| Error::Io { 
+                            reason: format!("Error while sending request on retry {retry}: {err} (source: {:?})", err.source())
+                        })?;
+                }
+                else {
+                    for (source_addr, fragment_request) in missing {
+                        println!("Asking for {} missing fragments of the reply...", fragment_request.missing.len());
+                        let request_bytes = fragment::encode_fragment_request(self.version, &fragment_request)
+                            .map_err(|reason| Error::EncodeError { reason })?;
+                        self.socket
+                            .send_to(&request_bytes, source_addr)
+                            .map_err(|err| Error::Io { 
+                                reason: format!("Error while asking for missing fragments on retry {retry}: {err} (source: {:?})", err.source())
+                            })?;
+                    }
+                }
 
                 let roll = self.rng.random_range(0.0..1.0);
                 if roll < self.duplicate_packet_rate {
@@ -48,15 +139,22 @@ impl SenderReceiver {
                 
                 loop {
                     match self.socket.recv_from(&mut recv_buffer) {
-                        Ok(ok) => {
-                            let response = RawResponse::from_bytes(&mut recv_buffer)?;
+                        Ok((size, source_addr)) => {
+                            let reply = match self.receive_reply(&recv_buffer[..size], source_addr) {
+                                Ok(Some(reply)) => reply,
+                                Ok(None) => continue,
+                                Err(err) => {
+                                    println!("Dropping unreadable reply ({err}); continuing...");
+                                    continue;
+                                }
+                            };
 
-                            if response.request_id != request_id {
-                                println!("Response ID {} doesn't match request ID {}; continuing...", response.request_id, request_id);
+                            if reply.request_id() != request_id {
+                                println!("Response ID {} doesn't match request ID {}; continuing...", reply.request_id(), request_id);
                                 continue;
                             }
 
-                            return Ok(response);
+                            return Ok(reply);
                         },
                         Err(err) => {
                             if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock {
@@ -66,32 +164,52 @@ impl SenderReceiver {
                                     println!("Attempt {}: Failed to send packet; waiting {}ms before retrying", retry+1, backoff_ms);
                                     sleep(backoff);
                                 }
+                                break;
                             }
                             else {
-                                return Err(format!("Got a non-timeout error while receiving message: {err} (source: {:?})", err.source()));
+                                return Err(Error::Io { 
+                                    reason: format!("Got a non-timeout error while receiving message: {err} (source: {:?})", err.source())
+                                });
                             }
                         }
                     }
                 }
             }
-            return Err(format!("Timeout occurred; maxed out at {} retries", MAX_RETRIES));
+            Err(Error::Timeout { attempts: MAX_RETRIES as u8 })
         }
         else {
-            self.socket
-                .send_to(&request_bytes, addr)
-                .map_err(|err| format!("Error while sending request: {err} (source: {:?})", err.source()))?;
-            match self.socket.recv(&mut recv_buffer) {
-                Ok(ok) => {
-                    let response = RawResponse::from_bytes(&mut recv_buffer)?;
-                    return Ok(response);
-                },
-                Err(err) => {
-                    return Err(format!("Got an error while receiving message: {err}, (source: {:?})", err.source()));
+            self.send_datagrams(&datagrams, addr)
+                .map_err(|err| Error::Io { 
+                    reason: format!("Error while sending request: {err} (source: {:?})", err.source())
+                })?;
+            loop {
+                match self.socket.recv_from(&mut recv_buffer) {
+                    Ok((size, source_addr)) => {
+                        if let Some(reply) = self.receive_reply(&recv_buffer[..size], source_addr)? {
+                            return Ok(reply);
+                        }
+                    },
+                    Err(err) if err.kind() == ErrorKind::TimedOut || err.kind() == ErrorKind::WouldBlock => {
+                        return Err(Error::Timeout { attempts: 1 });
+                    },
+                    Err(err) => {
+                        return Err(Error::Io { 
+                            reason: format!("Got an error while receiving message: {err}, (source: {:?})", err.source())
+                        });
+                    }
                 }
             }
         }
     }
 
+    /// Sends each of the datagrams of a message.
+    fn send_datagrams(&self, datagrams: &[Vec<u8>], addr: &String) -> std::io::Result<()> {
+        for datagram in datagrams {
+            self.socket.send_to(datagram, addr)?;
+        }
+        Ok(())
+    }
+
     /// Monitors messages from `addr` and prints them for the specified number of seconds.
     /// 
     /// Call this after sending a monitor request.
@@ -111,13 +229,18 @@ impl SenderReceiver {
         while SystemTime::now() < expiry_time {
             match self.socket.recv_from(&mut recv_buffer) {
                 Ok((size, source_addr)) => {
-                    match RawResponse::from_bytes(&mut recv_buffer) {
-                        Ok(response) => {
+                    match self.receive_reply(&recv_buffer[..size], source_addr) {
+                        Ok(None) => {},
+                        Ok(Some(Reply::UpgradeRequired(upgrade))) => {
+                            println!("------");
+                            println!("Got an upgrade required message (server supports versions {}-{})", upgrade.min_version, upgrade.max_version);
+                        },
+                        Ok(Some(Reply::Response(response))) => {
                             println!("------");
                             if &source_addr.to_string() != addr {
                                 println!("NOTE: Following message came from an unexpected address ({source_addr})");
                             }
-                            println!("{}", response.message);
+                            println!("{}", response.response_type);
                         },
                         Err(err) => {
                             println!("------");
@@ -142,4 +265,43 @@ impl SenderReceiver {
         println!("Ending monitoring...");
         println!("------");
     }
-}
\ No newline at end of file
+
+    /// Handles a datagram from the server, returning the reply once it's complete.
+    /// 
+    /// Returns `None` if it's a fragment of a reply which is still missing fragments,
+    /// or anything else which isn't a reply.
+    /// 
+    /// Errors if the datagram or reply isn't decodable, or is in a protocol version we don't support.
+    fn receive_reply(&mut self, datagram: &[u8], source_addr: SocketAddr) -> Result<Option<Reply>, Error> {
+        match self.reassembler.receive(source_addr, datagram) {
+            Ok(Received::Message(message)) => self.decode_reply(&message).map(Some),
+            Ok(Received::Incomplete) | Ok(Received::FragmentRequest(_)) => Ok(None),
+            Err(reason) => Err(Error::DecodeError { reason })
+        }
+    }
+
+    /// Decodes a reply from the server, checking its header and checksum.
+    /// 
+    /// Errors if the reply isn't decodable, or is in a protocol version we don't support.
+    fn decode_reply(&self, data: &[u8]) -> Result<Reply, Error> {
+        let (header, mut reader) = protocol::decode_header(data)
+            .map_err(|reason| Error::DecodeError { reason })?;
+        reader.set_max_length(self.max_message_size);
+
+        if header.has_flag(FLAG_UPGRADE_REQUIRED) {
+            let upgrade = UpgradeRequired::decode(&mut reader)
+                .map_err(|reason| Error::DecodeError { reason })?;
+            return Ok(Reply::UpgradeRequired(upgrade));
+        }
+        if !protocol::is_supported_version(header.version) {
+            return Err(Error::UnsupportedVersion {
+                min_version: protocol::MIN_PROTOCOL_VERSION,
+                max_version: protocol::PROTOCOL_VERSION
+            });
+        }
+
+        let response = protocol::decode_body(&header, &mut reader)
+            .map_err(|reason| Error::DecodeError { reason })?;
+        Ok(Reply::Response(response))
+    }
+}
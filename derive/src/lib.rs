@@ -1,50 +1,335 @@
 use proc_macro::TokenStream;
-use quote::quote;
-use syn::{parse_macro_input, DeriveInput};
+use proc_macro2::TokenStream as TokenStream2;
+use quote::{format_ident, quote};
+use syn::{parse_macro_input, parse_quote, spanned::Spanned, DataEnum, DeriveInput, Expr, Field, Fields, Generics, Ident, LitInt, LitStr, Path};
 
-/// Derives `Byteable` for a struct whose fields are all `Byteable`.
-#[proc_macro_derive(ByteableDerive)]
+/// Derives `Byteable` for a struct or enum whose fields are all `Byteable`.
+///
+/// The generated code refers to the `shared` crate by path, so it doesn't need `Byteable` to be in scope.
+/// Structs can have named, unnamed or no fields. For generic types, every type parameter must be `Byteable`.
+///
+/// Enums are encoded as a `u8` discriminant followed by the variant's fields.
+/// Discriminants are assigned in declaration order starting from 0,
+/// and can be set explicitly with `#[byteable(tag = N)]`; following variants continue counting from there.
+///
+/// ## Field attributes
+/// - `#[byteable(skip)]`: the field isn't encoded, and is decoded as `Default::default()`.
+/// - `#[byteable(default = path)]`: for skipped or versioned fields, decode as `path()` instead of `Default::default()`.
+/// - `#[byteable(len = "u8")]`: encode the field's length prefixes as `u8`, `u16`, `u32` or `varint`,
+///   regardless of the message's length encoding.
+/// - `#[byteable(with = path)]`: encode and decode the field with `path::encode(&field, writer)` and `path::decode(reader)`.
+/// - `#[byteable(since_version = N)]`: the field is only encoded in protocol version `N` onwards,
+///   where `N` is a `u8` literal or constant (eg. `protocol::CAPACITY_VERSION`), and decoded as its default in older versions. Such fields must come after all unversioned fields,
+///   so older versions can still decode everything before them.
+#[proc_macro_derive(ByteableDerive, attributes(byteable))]
 pub fn derive_byteable(input: TokenStream) -> TokenStream {
     let input = parse_macro_input!(input as DeriveInput);
-    let name = &input.ident;
 
-    let fields = if let syn::Data::Struct(data) = &input.data {
-        &data.fields
-    } else {
-        panic!("Byteable can only be derived for structs");
+    let expanded = match &input.data {
+        syn::Data::Struct(data) => derive_struct(&input, &data.fields),
+        syn::Data::Enum(data) => derive_enum(&input, data),
+        syn::Data::Union(_) => Err(syn::Error::new(input.span(), "Byteable cannot be derived for unions")),
     };
 
-    let field_names: Vec<_> = fields.iter().map(|f| f.ident.as_ref().unwrap()).collect();
+    match expanded {
+        Ok(tokens) => TokenStream::from(tokens),
+        Err(err) => TokenStream::from(err.to_compile_error()),
+    }
+}
 
-    let from_bytes_fields = field_names.iter().map(|name| {
-        quote! {
-            let #name = Byteable::from_bytes(data)?;
-        }
-    });
+fn derive_struct(input: &DeriveInput, fields: &Fields) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = with_byteable_bounds(&input.generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+
+    let fields = FieldsCodec::new(fields)?;
+    let pattern = fields.pattern(quote!(Self));
+    let decode_fields = &fields.decode;
+    let encode_fields = &fields.encode;
+
+    Ok(quote! {
+        impl #impl_generics ::shared::Byteable for #name #ty_generics #where_clause {
+            fn decode(reader: &mut ::shared::Reader<'_>) -> Result<Self, String> {
+                #(#decode_fields)*
+
+                Ok(#pattern)
+            }
 
-    let to_bytes_fields = field_names.iter().map(|name| {
-        quote! {
-            bytes.extend(self.#name.to_bytes());
+            fn encode(&self, writer: &mut ::shared::Writer) -> Result<(), String> {
+                let #pattern = self;
+                #(#encode_fields)*
+                Ok(())
+            }
         }
-    });
+    })
+}
+
+fn derive_enum(input: &DeriveInput, data: &DataEnum) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let generics = with_byteable_bounds(&input.generics);
+    let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
+    let tags = variant_tags(data)?;
+
+    let mut decode_arms = Vec::new();
+    let mut encode_arms = Vec::new();
+
+    for (variant, tag) in data.variants.iter().zip(tags) {
+        let variant_name = &variant.ident;
+        let fields = FieldsCodec::new(&variant.fields)?;
+        let pattern = fields.pattern(quote!(Self::#variant_name));
+        let decode_fields = &fields.decode;
+        let encode_fields = &fields.encode;
+
+        decode_arms.push(quote! {
+            #tag => {
+                #(#decode_fields)*
+                #pattern
+            },
+        });
+        encode_arms.push(quote! {
+            #pattern => {
+                writer.write_u8(#tag);
+                #(#encode_fields)*
+            },
+        });
+    }
 
-    let expanded = quote! {
-        impl Byteable for #name {
-            fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-                #(#from_bytes_fields)*
+    let unknown_tag_message = format!("Unknown {name} discriminant: {{other}}");
 
-                Ok(Self {
-                    #(#field_names),*
-                })
+    Ok(quote! {
+        impl #impl_generics ::shared::Byteable for #name #ty_generics #where_clause {
+            fn decode(reader: &mut ::shared::Reader<'_>) -> Result<Self, String> {
+                let discriminant = reader.read_u8()?;
+                let val = match discriminant {
+                    #(#decode_arms)*
+                    other => return Err(format!(#unknown_tag_message)),
+                };
+                Ok(val)
             }
 
-            fn to_bytes(self) -> Vec<u8> {
-                let mut bytes = Vec::new();
-                #(#to_bytes_fields)*
-                bytes
+            fn encode(&self, writer: &mut ::shared::Writer) -> Result<(), String> {
+                match self {
+                    #(#encode_arms)*
+                }
+                Ok(())
             }
         }
-    };
+    })
+}
+
+/// Adds a `Byteable` bound to every type parameter.
+fn with_byteable_bounds(generics: &Generics) -> Generics {
+    let mut generics = generics.clone();
+    for param in generics.type_params_mut() {
+        param.bounds.push(parse_quote!(::shared::Byteable));
+    }
+    generics
+}
+
+/// The generated code for decoding and encoding a struct's or variant's fields.
+///
+/// Each field is bound to a variable; decoding assigns them, and encoding reads them from a pattern match on `self`.
+struct FieldsCodec<'a> {
+    fields: &'a Fields,
+    bindings: Vec<Ident>,
+    decode: Vec<TokenStream2>,
+    encode: Vec<TokenStream2>
+}
+
+impl<'a> FieldsCodec<'a> {
+    fn new(fields: &'a Fields) -> syn::Result<Self> {
+        let mut codec = Self {
+            fields,
+            bindings: Vec::new(),
+            decode: Vec::new(),
+            encode: Vec::new()
+        };
+        let mut versioned_field_seen = false;
+
+        for (i, field) in fields.iter().enumerate() {
+            let attrs = FieldAttrs::parse(field)?;
+            if attrs.since_version.is_some() {
+                versioned_field_seen = true;
+            } else if versioned_field_seen && !attrs.skip {
+                return Err(syn::Error::new(field.span(), "Fields without `since_version` must come before all fields with it"));
+            }
+
+            let binding = match &field.ident {
+                Some(ident) => ident.clone(),
+                None => format_ident!("field_{i}"),
+            };
+            codec.decode.push(attrs.decode(&binding));
+            codec.encode.push(attrs.encode(&binding));
+            codec.bindings.push(binding);
+        }
+
+        Ok(codec)
+    }
+
+    /// A pattern or expression of `path` with each field bound to its variable.
+    fn pattern(&self, path: TokenStream2) -> TokenStream2 {
+        let bindings = &self.bindings;
+        match self.fields {
+            Fields::Unit => path,
+            Fields::Unnamed(_) => quote!(#path(#(#bindings),*)),
+            Fields::Named(_) => quote!(#path { #(#bindings),* }),
+        }
+    }
+}
 
-    TokenStream::from(expanded)
+/// The `#[byteable(...)]` attributes of a field.
+#[derive(Default)]
+struct FieldAttrs {
+    skip: bool,
+    default: Option<Path>,
+    len: Option<TokenStream2>,
+    with: Option<Path>,
+    since_version: Option<Expr>
+}
+
+impl FieldAttrs {
+    /// Parses and validates the attributes of the field.
+    fn parse(field: &Field) -> syn::Result<Self> {
+        let mut attrs = Self::default();
+        let mut has_default = false;
+
+        for attr in field.attrs.iter().filter(|attr| attr.path().is_ident("byteable")) {
+            attr.parse_nested_meta(|meta| {
+                if meta.path.is_ident("skip") {
+                    attrs.skip = true;
+                } else if meta.path.is_ident("default") {
+                    has_default = true;
+                    if meta.input.peek(syn::Token![=]) {
+                        attrs.default = Some(meta.value()?.parse()?);
+                    }
+                } else if meta.path.is_ident("len") {
+                    let lit: LitStr = meta.value()?.parse()?;
+                    attrs.len = Some(match lit.value().as_str() {
+                        "u8" => quote!(::shared::LengthEncoding::U8),
+                        "u16" => quote!(::shared::LengthEncoding::U16),
+                        "u32" => quote!(::shared::LengthEncoding::U32),
+                        "varint" => quote!(::shared::LengthEncoding::Varint),
+                        _ => return Err(syn::Error::new(lit.span(), "Expected `len` to be one of \"u8\", \"u16\", \"u32\" or \"varint\"")),
+                    });
+                } else if meta.path.is_ident("with") {
+                    attrs.with = Some(meta.value()?.parse()?);
+                } else if meta.path.is_ident("since_version") {
+                    attrs.since_version = Some(meta.value()?.parse()?);
+                } else {
+                    return Err(meta.error(
+                        "Unsupported byteable attribute; expected `skip`, `default`, `len = \"..\"`, `with = path` or `since_version = N`"
+                    ));
+                }
+                Ok(())
+            })?;
+        }
+
+        if attrs.skip && (attrs.len.is_some() || attrs.with.is_some() || attrs.since_version.is_some()) {
+            return Err(syn::Error::new(field.span(), "Skipped fields can't have `len`, `with` or `since_version`"));
+        }
+        if has_default && !attrs.skip && attrs.since_version.is_none() {
+            return Err(syn::Error::new(field.span(), "`default` only applies to fields with `skip` or `since_version`"));
+        }
+
+        Ok(attrs)
+    }
+
+    /// The value of the field when it isn't on the wire.
+    fn default_value(&self) -> TokenStream2 {
+        match &self.default {
+            Some(path) => quote!(#path()),
+            None => quote!(::core::default::Default::default()),
+        }
+    }
+
+    /// A statement decoding the field from `reader` into `binding`.
+    fn decode(&self, binding: &Ident) -> TokenStream2 {
+        if self.skip {
+            let default = self.default_value();
+            return quote!(let #binding = #default;);
+        }
+
+        let mut decode = match &self.with {
+            Some(path) => quote!(#path::decode(reader)),
+            None => quote!(::shared::Byteable::decode(reader)),
+        };
+        if let Some(len) = &self.len {
+            decode = quote!(reader.with_length_encoding(#len, |reader| #decode));
+        }
+
+        match &self.since_version {
+            Some(version) => {
+                let default = self.default_value();
+                quote! {
+                    let #binding = match reader.version() >= (#version) {
+                        true => #decode?,
+                        false => #default,
+                    };
+                }
+            },
+            None => quote!(let #binding = #decode?;),
+        }
+    }
+
+    /// A statement encoding the field from `binding`, a reference to it, onto `writer`.
+    fn encode(&self, binding: &Ident) -> TokenStream2 {
+        if self.skip {
+            return quote!(let _ = #binding;);
+        }
+
+        let mut encode = match &self.with {
+            Some(path) => quote!(#path::encode(#binding, writer)),
+            None => quote!(::shared::Byteable::encode(#binding, writer)),
+        };
+        if let Some(len) = &self.len {
+            encode = quote!(writer.with_length_encoding(#len, |writer| #encode));
+        }
+
+        match &self.since_version {
+            Some(version) => quote! {
+                if writer.version() >= (#version) {
+                    #encode?;
+                }
+            },
+            None => quote!(#encode?;),
+        }
+    }
+}
+
+/// Resolves the discriminant of each variant, erroring on overflow or duplicates.
+fn variant_tags(data: &DataEnum) -> syn::Result<Vec<u8>> {
+    let mut tags: Vec<u8> = Vec::new();
+    let mut next_tag: u16 = 0;
+
+    for variant in &data.variants {
+        let tag = match explicit_tag(&variant.attrs)? {
+            Some(tag) => tag,
+            None => u8::try_from(next_tag)
+                .map_err(|_| syn::Error::new(variant.span(), "Ran out of discriminants; an enum can have at most 256 variants"))?,
+        };
+        if tags.contains(&tag) {
+            return Err(syn::Error::new(variant.span(), format!("Discriminant {tag} is used by more than 1 variant")));
+        }
+        tags.push(tag);
+        next_tag = tag as u16 + 1;
+    }
+
+    Ok(tags)
+}
+
+/// Parses `#[byteable(tag = N)]`, if present.
+fn explicit_tag(attrs: &[syn::Attribute]) -> syn::Result<Option<u8>> {
+    let mut tag = None;
+    for attr in attrs.iter().filter(|attr| attr.path().is_ident("byteable")) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("tag") {
+                let lit: LitInt = meta.value()?.parse()?;
+                tag = Some(lit.base10_parse::<u8>()?);
+                Ok(())
+            } else {
+                Err(meta.error("Unsupported byteable attribute; expected `tag = N`"))
+            }
+        })?;
+    }
+    Ok(tag)
 }
@@ -0,0 +1,61 @@
+//! Benchmarks of a facility's bookings, checking overlaps and looking bookings up with `BOOKINGS` of them.
+//!
+//! Run with `cargo bench -p server --bench facilities`.
+use std::hint::black_box;
+use chrono::NaiveDate;
+use criterion::{criterion_group, criterion_main, Criterion};
+use server::facilities::{Booking, BookingId, Facility};
+use shared::time::{Duration, Interval, Time};
+
+/// The number of bookings in the facility.
+const BOOKINGS: i32 = 100_000;
+
+/// The interval of the nth slot, which is an hour long, starting every 2 hours from 2026-01-01.
+fn slot(n: i32) -> Interval {
+    let start = Time::midnight(NaiveDate::from_ymd_opt(2026, 1, 1).unwrap())
+        .checked_add(Duration::from_minutes(n * 120))
+        .unwrap();
+    let end = start.checked_add(Duration::from_minutes(60)).unwrap();
+    Interval::new(start, end).unwrap()
+}
+
+/// A facility with a booking in each of the first `BOOKINGS` slots, along with their IDs in order.
+fn booked_facility() -> (Facility, Vec<BookingId>) {
+    let mut facility = Facility::new("MR1".into());
+    let booking_ids = (0..BOOKINGS)
+        .map(|n| facility.add_new_booking(Booking::new(slot(n)).unwrap()).unwrap())
+        .collect();
+    (facility, booking_ids)
+}
+
+fn bench_facilities(c: &mut Criterion) {
+    let (mut facility, booking_ids) = booked_facility();
+    let middle = BOOKINGS / 2;
+    let middle_id = booking_ids[middle as usize];
+    let gap = slot(middle).checked_add(Duration::from_minutes(60)).unwrap();
+
+    c.bench_function("overlap check (conflicting)", |b| {
+        b.iter(|| facility.add_new_booking(black_box(Booking::new(slot(middle)).unwrap())).unwrap_err())
+    });
+    c.bench_function("overlap check (free), then cancel", |b| {
+        b.iter(|| {
+            let booking_id = facility.add_new_booking(black_box(Booking::new(gap.clone()).unwrap())).unwrap();
+            facility.remove_booking(&booking_id).unwrap()
+        })
+    });
+    c.bench_function("lookup by ID", |b| {
+        b.iter(|| facility.get_booking_details(black_box(&middle_id)).unwrap().unwrap().series_id())
+    });
+    c.bench_function("availability for a date", |b| {
+        b.iter(|| facility.get_availabilities(black_box(slot(middle).start().date)).unwrap())
+    });
+    c.bench_function("offset there and back", |b| {
+        b.iter(|| {
+            facility.offset_booking(middle_id, black_box(Duration::from_minutes(30))).unwrap();
+            facility.offset_booking(middle_id, black_box(Duration::from_minutes(-30))).unwrap();
+        })
+    });
+}
+
+criterion_group!(benches, bench_facilities);
+criterion_main!(benches);
@@ -0,0 +1,356 @@
+use std::{collections::{BTreeMap, HashSet}, path::{Path, PathBuf}, str::FromStr};
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use shared::{fragment::{MAX_FRAGMENTS, MAX_FRAGMENT_DATA}, time::{Day, Duration, Interval, Time}};
+use tracing_subscriber::filter::LevelFilter;
+use crate::{facilities::Facility, opening_hours::OpeningHours, socket::SocketSettings, store::Backend};
+
+/// The default config, which config files are merged over.
+const DEFAULT_CONFIG: &str = include_str!("../config.toml");
+
+/// The database file in the storage directory, for the sqlite backend.
+const SQLITE_FILE: &str = "bookings.sqlite3";
+
+/// The prefix of environment variables which override settings.
+const ENV_PREFIX: &str = "BOOKING_SERVER_";
+
+/// The server's settings, loaded from the default config, a config file, and environment variables, in that order.
+///
+/// See `config.toml` for what each setting does, and which can be reloaded while running.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub network: NetworkConfig,
+    pub reliability: ReliabilityConfig,
+    pub dedup: DedupConfig,
+    pub faults: FaultsConfig,
+    pub logging: LoggingConfig,
+    pub storage: StorageConfig,
+    #[serde(default)]
+    pub admin: AdminConfig,
+    #[serde(default)]
+    pub opening_hours: BTreeMap<String, BTreeMap<String, Vec<String>>>,
+    #[serde(default)]
+    pub blackouts: BTreeMap<String, BlackoutConfig>,
+    #[serde(default)]
+    pub facilities: Vec<FacilityConfig>
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NetworkConfig {
+    pub addr: String,
+    pub receive_buffer_size: usize,
+    pub max_message_size: usize
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ReliabilityConfig {
+    pub enabled: bool
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DedupConfig {
+    pub capacity: usize,
+    pub ttl_seconds: u64,
+    /// Whether responses to requests which changed something are kept in storage, if there is one.
+    pub persist: bool
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FaultsConfig {
+    pub packet_drop_rate: f64,
+    pub send_drop_rate: f64
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct LoggingConfig {
+    pub level: String
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct StorageConfig {
+    /// Empty to not keep changes.
+    pub dir: PathBuf,
+    pub snapshot_interval: usize,
+    pub sync: bool,
+    pub backend: BackendConfig
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendConfig {
+    Memory,
+    Sqlite
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AdminConfig {
+    pub token: Option<String>
+}
+
+/// A period when facilities are closed, from and until times formatted as `YYYY-MM-DD H:MM`.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlackoutConfig {
+    pub from: String,
+    pub until: String
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FacilityConfig {
+    pub name: String,
+    #[serde(default = "one_seat")]
+    pub capacity: u16,
+    #[serde(default)]
+    pub building: String,
+    #[serde(default)]
+    pub floor: i16,
+    #[serde(default)]
+    pub equipment: Vec<String>,
+    #[serde(default)]
+    pub step_free_access: bool,
+    /// Formatted as `H:MM`.
+    pub turnaround_buffer: Option<String>,
+    /// The name of an entry in `Config::opening_hours`, or `None` if it's always open.
+    pub opening_hours: Option<String>,
+    /// The names of entries in `Config::blackouts`.
+    #[serde(default)]
+    pub blackouts: Vec<String>
+}
+
+fn one_seat() -> u16 {
+    1
+}
+
+/// The settings which can be changed without restarting the server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadableSettings {
+    pub log_level: LevelFilter,
+    pub socket: SocketSettings,
+    pub admin_token: Option<String>
+}
+
+impl Config {
+    /// Loads the default config, merges the file over it if given, then applies overrides from environment variables.
+    ///
+    /// Errors if the file can't be read, or the result isn't a valid config.
+    pub fn load(path: Option<&Path>) -> Result<Config, String> {
+        let mut table: toml::Table = DEFAULT_CONFIG
+            .parse()
+            .map_err(|err| format!("Default config is invalid: {err}"))?;
+        if let Some(path) = path {
+            let file = std::fs::read_to_string(path)
+                .map_err(|err| format!("Unable to read config file {}: {err}", path.display()))?;
+            let overrides: toml::Table = file
+                .parse()
+                .map_err(|err| format!("Unable to parse config file {}: {err}", path.display()))?;
+            merge(&mut table, overrides);
+        }
+        for (name, value) in std::env::vars() {
+            if let Some(setting) = name.strip_prefix(ENV_PREFIX) {
+                apply_env_override(&mut table, setting, &value)
+                    .map_err(|err| format!("Invalid override {name}: {err}"))?;
+            }
+        }
+
+        let config: Config = toml::Value::Table(table)
+            .try_into()
+            .map_err(|err| format!("Invalid config: {}", err.to_string().trim_end()))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Errors with the first setting which is out of range, or facility which can't be built.
+    fn validate(&self) -> Result<(), String> {
+        if self.network.receive_buffer_size == 0 || self.network.receive_buffer_size > usize::from(u16::MAX) {
+            return Err(format!("network.receive_buffer_size must be between 1 and {} (got {})", u16::MAX, self.network.receive_buffer_size));
+        }
+        let max_message_size = usize::from(MAX_FRAGMENTS) * MAX_FRAGMENT_DATA;
+        if self.network.max_message_size == 0 || self.network.max_message_size > max_message_size {
+            return Err(format!(
+                "network.max_message_size must be between 1 and {max_message_size} (got {})",
+                self.network.max_message_size
+            ));
+        }
+        for (name, rate) in [("packet_drop_rate", self.faults.packet_drop_rate), ("send_drop_rate", self.faults.send_drop_rate)] {
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format!("faults.{name} must be between 0 and 1 (got {rate})"));
+            }
+        }
+        if self.storage.backend == BackendConfig::Sqlite && self.storage.dir.as_os_str().is_empty() {
+            return Err("storage.dir must be set to use the sqlite backend".into());
+        }
+        LevelFilter::from_str(&self.logging.level)
+            .map_err(|_| format!("logging.level must be one of off, error, warn, info, debug or trace (got {})", self.logging.level))?;
+        self.facilities()?;
+        Ok(())
+    }
+
+    /// Builds the facilities the server starts with.
+    ///
+    /// Errors if any have the same name, or refer to opening hours or blackouts which don't exist or are invalid.
+    pub fn facilities(&self) -> Result<Vec<Facility>, String> {
+        let mut names = HashSet::new();
+        self.facilities
+            .iter()
+            .map(|facility| {
+                if !names.insert(&facility.name) {
+                    return Err(format!("Facility {} is listed more than once", facility.name));
+                }
+                self.build_facility(facility)
+                    .map_err(|err| format!("Facility {}: {err}", facility.name))
+            })
+            .collect()
+    }
+
+    fn build_facility(&self, config: &FacilityConfig) -> Result<Facility, String> {
+        if config.name.trim().is_empty() {
+            return Err("Name can't be empty".into());
+        }
+        if config.capacity == 0 {
+            return Err("Capacity must be at least 1".into());
+        }
+        let equipment: Vec<_> = config.equipment
+            .iter()
+            .map(String::as_str)
+            .collect();
+        let mut facility = Facility::new(config.name.clone())
+            .with_capacity(config.capacity)
+            .with_location(&config.building, config.floor)
+            .with_equipment(&equipment);
+        if config.step_free_access {
+            facility = facility.with_step_free_access();
+        }
+
+        if let Some(turnaround_buffer) = &config.turnaround_buffer {
+            let turnaround_buffer = Duration::from_str(turnaround_buffer)?;
+            if turnaround_buffer.is_negative() {
+                return Err(format!("Turnaround buffer ({turnaround_buffer}) is negative"));
+            }
+            facility = facility.with_turnaround_buffer(turnaround_buffer);
+        }
+        if let Some(name) = &config.opening_hours {
+            let days = self.opening_hours
+                .get(name)
+                .ok_or_else(|| format!("No opening hours named {name}"))?;
+            let opening_hours = parse_opening_hours(days)
+                .map_err(|err| format!("Opening hours {name}: {err}"))?;
+            facility = facility.with_opening_hours(opening_hours);
+        }
+        for name in &config.blackouts {
+            let blackout = self.blackouts
+                .get(name)
+                .ok_or_else(|| format!("No blackout named {name}"))?;
+            let blackout = Interval::new(parse_time(&blackout.from)?, parse_time(&blackout.until)?)
+                .map_err(|err| format!("Blackout {name}: {err}"))?;
+            facility = facility.with_blackout(blackout);
+        }
+        Ok(facility)
+    }
+
+    /// The kind of store facilities keep their bookings in.
+    pub fn backend(&self) -> Backend {
+        match self.storage.backend {
+            BackendConfig::Memory => Backend::Memory,
+            BackendConfig::Sqlite => Backend::Sqlite { path: self.storage.dir.join(SQLITE_FILE), sync: self.storage.sync }
+        }
+    }
+
+    /// The settings which can be changed without restarting.
+    pub fn reloadable(&self) -> ReloadableSettings {
+        ReloadableSettings {
+            log_level: LevelFilter::from_str(&self.logging.level).expect("Log level should be validated"),
+            socket: SocketSettings {
+                packet_drop_rate: self.faults.packet_drop_rate,
+                send_drop_rate: self.faults.send_drop_rate,
+                log_capacity: self.dedup.capacity,
+                log_ttl: std::time::Duration::from_secs(self.dedup.ttl_seconds)
+            },
+            admin_token: self.admin.token.clone()
+        }
+    }
+
+    /// Returns if any settings which need a restart to change differ from the other config's.
+    pub fn needs_restart(&self, other: &Config) -> bool {
+        self.network != other.network
+            || self.reliability != other.reliability
+            || self.storage != other.storage
+            || self.dedup.persist != other.dedup.persist
+            || self.opening_hours != other.opening_hours
+            || self.blackouts != other.blackouts
+            || self.facilities != other.facilities
+    }
+}
+
+/// Merges the overrides into the table; tables are merged key by key, and any other values are replaced.
+fn merge(table: &mut toml::Table, overrides: toml::Table) {
+    for (key, value) in overrides {
+        match (table.get_mut(&key), value) {
+            (Some(toml::Value::Table(existing)), toml::Value::Table(value)) => merge(existing, value),
+            (_, value) => {
+                table.insert(key, value);
+            }
+        }
+    }
+}
+
+/// Sets the setting at the path (table names and a key, separated by `__`) to the value,
+/// read as TOML, or as a string if it isn't valid TOML.
+///
+/// Errors if the path goes through a value which isn't a table.
+fn apply_env_override(table: &mut toml::Table, path: &str, value: &str) -> Result<(), String> {
+    let path = path.to_lowercase();
+    let mut keys: Vec<_> = path.split("__").collect();
+    let key = keys.pop().unwrap_or_default();
+
+    let mut table = table;
+    for name in keys {
+        table = table
+            .entry(name)
+            .or_insert_with(|| toml::Value::Table(toml::Table::new()))
+            .as_table_mut()
+            .ok_or_else(|| format!("{name} isn't a table"))?;
+    }
+    let value = format!("value = {value}")
+        .parse::<toml::Table>()
+        .ok()
+        .and_then(|mut parsed| parsed.remove("value"))
+        .unwrap_or_else(|| toml::Value::String(value.into()));
+    table.insert(key.into(), value);
+    Ok(())
+}
+
+/// Parses each day's `H:MM-H:MM` windows.
+fn parse_opening_hours(days: &BTreeMap<String, Vec<String>>) -> Result<OpeningHours, String> {
+    let mut opening_hours = OpeningHours::closed();
+    for (day, windows) in days {
+        let day = Day::from_str(day).map_err(|_| format!("{day} isn't a day of the week"))?;
+        for window in windows {
+            let (from, until) = window
+                .split_once('-')
+                .ok_or_else(|| format!("Expected H:MM-H:MM (got {window})"))?;
+            let (from, until) = (Duration::from_str(from.trim())?, Duration::from_str(until.trim())?);
+            let day_length = Duration::from_days(1).expect("A day should be in range");
+            if !(Duration::ZERO <= from && from <= until && until <= day_length) {
+                return Err(format!("Window {window} on {day} must be in order within the day"));
+            }
+            opening_hours = opening_hours.with_hours(day, from, until);
+        }
+    }
+    Ok(opening_hours)
+}
+
+/// Parses a time formatted as `YYYY-MM-DD H:MM`.
+fn parse_time(time: &str) -> Result<Time, String> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+        .map(Time::from_datetime)
+        .map_err(|err| format!("Expected YYYY-MM-DD H:MM (got {time}): {err}"))
+}
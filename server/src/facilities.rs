@@ -1,252 +1,621 @@
-use shared::time::{Day, Hour, Minute, Time};
+use std::collections::BTreeMap;
+use chrono::NaiveDate;
+use derive::ByteableDerive;
+use shared::{error::Error, facility::FacilityAttributes, responses::Slot, time::{Duration, Interval}};
 use uuid::Uuid;
+use crate::{opening_hours::OpeningHours, store::{BookingStore, MemoryStore, StoreError}};
+
+/// The most days after the date a booking starts on that it can end on,
+/// as storing and checking a booking takes time for each date it falls on.
+pub const MAX_BOOKING_DAYS: i64 = 31;
 
 pub struct Facility {
     pub name: String,
-    bookings: Vec<(BookingId, Booking)>
+    bookings: Box<dyn BookingStore>,
+    attributes: FacilityAttributes,
+    /// The time which must be left free between consecutive bookings.
+    turnaround_buffer: Duration,
+    opening_hours: OpeningHours,
+    /// One-off periods when the facility is closed, eg. for maintenance or holidays.
+    blackouts: Vec<Interval>
 }
 
 impl Facility {
-    /// Create a new facility.
+    /// Create a new facility, with the default attributes (so 1 seat) and no turnaround buffer, which is always open.
+    /// 
+    /// Its bookings are kept in memory.
     pub fn new(name: String) -> Self {
         Self {
             name,
-            bookings: Vec::new()
+            bookings: Box::new(MemoryStore::new()),
+            attributes: FacilityAttributes::default(),
+            turnaround_buffer: Duration::ZERO,
+            opening_hours: OpeningHours::always(),
+            blackouts: Vec::new()
         }
     }
 
-    /// Add a new booking for the facility.
+    /// Keeps the bookings in the store instead, taking the ones it already has as the facility's.
+    pub fn with_store(mut self, store: Box<dyn BookingStore>) -> Self {
+        self.bookings = store;
+        self
+    }
+
+    /// Returns the store the bookings are kept in, eg. to give it to another definition of the facility.
+    pub fn into_store(self) -> Box<dyn BookingStore> {
+        self.bookings
+    }
+
+    /// Sets the number of seats, which bookings can take at the same time.
     /// 
-    /// Errors if the booking overlaps with current ones.
-    pub fn add_new_booking(&mut self, new_booking: Booking) -> Result<BookingId, String> {
-        if self.bookings
+    /// Panics if it's 0.
+    pub fn with_capacity(mut self, capacity: u16) -> Self {
+        assert!(capacity > 0, "Facility should have at least 1 seat");
+        self.attributes.capacity = capacity;
+        self
+    }
+
+    /// Sets all the attributes at once.
+    /// 
+    /// Panics if the capacity is 0.
+    pub fn with_attributes(mut self, attributes: FacilityAttributes) -> Self {
+        assert!(attributes.capacity > 0, "Facility should have at least 1 seat");
+        self.attributes = attributes;
+        self
+    }
+
+    /// Sets the building and floor the facility is on.
+    pub fn with_location(mut self, building: &str, floor: i16) -> Self {
+        self.attributes.building = building.into();
+        self.attributes.floor = floor;
+        self
+    }
+
+    /// Sets the tags for the equipment in the facility.
+    pub fn with_equipment(mut self, equipment: &[&str]) -> Self {
+        self.attributes.equipment = equipment
             .iter()
-            .any(|(_, booking)| booking.overlaps(&new_booking))
-        {
-            return Err("New booking overlaps with at least 1 current booking".into());
-        }
+            .map(|tag| tag.to_string())
+            .collect();
+        self
+    }
+
+    /// Marks the facility as having step-free access.
+    pub fn with_step_free_access(mut self) -> Self {
+        self.attributes.accessible = true;
+        self
+    }
+
+    pub fn attributes(&self) -> &FacilityAttributes {
+        &self.attributes
+    }
+
+    /// Sets the time which must be left free between consecutive bookings.
+    pub fn with_turnaround_buffer(mut self, turnaround_buffer: Duration) -> Self {
+        self.turnaround_buffer = turnaround_buffer;
+        self
+    }
+
+    pub fn turnaround_buffer(&self) -> Duration {
+        self.turnaround_buffer
+    }
+
+    /// Sets the hours the facility is open each week.
+    pub fn with_opening_hours(mut self, opening_hours: OpeningHours) -> Self {
+        self.opening_hours = opening_hours;
+        self
+    }
+
+    /// Closes the facility for the interval, regardless of its opening hours.
+    pub fn with_blackout(mut self, blackout: Interval) -> Self {
+        self.blackouts.push(blackout);
+        self
+    }
+
+    /// Add a new booking for the facility.
+    /// 
+    /// Errors if the facility is closed during the booking, or doesn't have enough seats free throughout it
+    /// (counting those taken by current bookings within the turnaround buffer of it).
+    pub fn add_new_booking(&mut self, new_booking: Booking) -> Result<BookingId, Error> {
+        self.check_bookable(&new_booking, &[])?;
         let new_id = Uuid::new_v4();
-        self.bookings.push((new_id.clone(), new_booking));
+        self.bookings.insert(new_id, new_booking)?;
         Ok(new_id)
     }
 
     /// Add a booking with the given ID.
     /// 
-    /// Errors if the ID already exists or there's overlap with current bookings.
-    pub fn add_booking_with_id(&mut self, booking_id: BookingId, booking: Booking) -> Result<(), String> {
-        if self.bookings
-            .iter()
-            .any(|(id, _)| id == &booking_id) 
-        {
-            return Err(format!("Booking {booking_id} already exists"));
+    /// Errors if the ID already exists, the facility is closed during the booking, or doesn't have enough seats free
+    /// throughout it (counting those taken by current bookings within the turnaround buffer of it).
+    pub fn add_booking_with_id(&mut self, booking_id: BookingId, booking: Booking) -> Result<(), Error> {
+        if self.bookings.get(&booking_id)?.is_some() {
+            return Err(Error::DuplicateBookingId { booking_id });
         }
-        if self.bookings
-            .iter()
-            .any(|(_, cur_booking)| cur_booking.overlaps(&booking))
-        {
-            return Err(format!("New booking ({booking:?}) overlaps with at least 1 current booking"));
+        self.check_bookable(&booking, &[])?;
+        self.bookings.insert(booking_id, booking)?;
+        Ok(())
+    }
+
+    /// Add bookings with the given IDs, eg. moved from another facility.
+    /// 
+    /// Either every booking is added, or none are; errors as `add_booking_with_id` for the first which can't be added.
+    pub fn add_bookings_with_ids(&mut self, bookings: Vec<(BookingId, Booking)>) -> Result<(), Error> {
+        let mut added = Vec::with_capacity(bookings.len());
+        for (booking_id, booking) in bookings {
+            if let Err(err) = self.add_booking_with_id(booking_id, booking) {
+                for added_id in &added {
+                    self.bookings.remove(added_id)?;
+                }
+                return Err(err);
+            }
+            added.push(booking_id);
         }
-        self.bookings.push((booking_id, booking));
         Ok(())
     }
 
-    /// Returns the booking details of a given booking ID, if it exists.
-    pub fn get_booking_details(&self, booking_id: &BookingId) -> Option<&(Uuid, Booking)> {
-        self.bookings
-            .iter()
-            .find(|(id, _)| id == booking_id)
+    /// Add a booking with the given ID without checking it, eg. when restoring bookings which were checked before.
+    /// 
+    /// Replaces any booking with the same ID.
+    pub fn restore_booking(&mut self, booking_id: BookingId, booking: Booking) -> Result<(), StoreError> {
+        self.bookings.insert(booking_id, booking)
     }
 
-    /// Remove the booking given by its ID.
+    /// Add the occurrences of a recurring booking, as a new series.
     /// 
-    /// Errors if the booking ID doesn't exist.
-    pub fn remove_booking(&mut self, booking_id: &BookingId) -> Result<Booking, String> {
-        if let Some(pos) = self.bookings
-            .iter()
-            .position(|(id, _)| id == booking_id)
-        {
-            let booking = self.bookings.remove(pos);
-            return Ok(booking.1);
+    /// Either every occurrence is added, or none are; errors if the facility is closed during any occurrence
+    /// or has fewer seats than it takes, or with every occurrence that conflicts with current bookings, if any.
+    pub fn add_recurring_bookings(&mut self, occurrences: Vec<Booking>) -> Result<(SeriesId, Vec<BookingId>), Error> {
+        for occurrence in &occurrences {
+            self.check_open(occurrence)?;
+            self.check_capacity(occurrence)?;
         }
-        Err(format!("Booking {booking_id} could not be found"))
+        let mut conflicts = Vec::new();
+        for occurrence in &occurrences {
+            if let Some((conflicting, _)) = self.find_conflict(occurrence, &[])? {
+                conflicts.push((occurrence.interval.start().date, conflicting));
+            }
+        }
+        if !conflicts.is_empty() {
+            return Err(Error::RecurrenceConflict { conflicts });
+        }
+
+        let series_id = Uuid::new_v4();
+        let mut booking_ids = Vec::with_capacity(occurrences.len());
+        for occurrence in occurrences {
+            match self.add_new_booking(occurrence.in_series(series_id)) {
+                Ok(booking_id) => booking_ids.push(booking_id),
+                Err(err) => { // occurrences overlap each other, so undo the ones added so far
+                    for booking_id in &booking_ids {
+                        self.remove_booking(booking_id)?;
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok((series_id, booking_ids))
     }
 
-    /// Get the available times for the day, as a string.
-    pub fn get_availabilities(&self, day: Day) -> String {
-        let mut day_bookings: Vec<&Booking> = self.bookings
+    /// The intervals the facility is open on the date, in order, leaving out blackout periods.
+    fn open_intervals(&self, date: NaiveDate) -> Vec<Interval> {
+        self.blackouts
             .iter()
-            .filter_map(|(_, booking)| {
-                if booking.start_time.day == day && booking.end_time.day == day {
-                    Some(booking)
-                } else {
-                    None
-                }
+            .fold(self.opening_hours.on(date), |open, blackout| {
+                open
+                    .iter()
+                    .flat_map(|interval| interval.difference(blackout))
+                    .collect()
             })
-            .collect();
+    }
 
-        day_bookings.sort();
-        
-        let day_start = Time {
-            day: day.clone(),
-            hour: Hour::new(0).unwrap(),
-            minute: Minute::new(0).unwrap(),
-        };
-        let day_end = Time {
-            day: day.clone(),
-            hour: Hour::new(23).unwrap(),
-            minute: Minute::new(59).unwrap(),
-        };
-        
-        let mut open_slots = Vec::new();
-        let mut current_time = day_start;
-        
-        for booking in day_bookings {
-            if current_time < booking.start_time {
-                open_slots.push((current_time, booking.start_time.clone()));
+    /// Errors with the first part of the booking when the facility is closed, if any.
+    fn check_open(&self, booking: &Booking) -> Result<(), Error> {
+        let closed = booking
+            .dates()
+            .flat_map(|date| self.open_intervals(date))
+            .fold(vec![booking.interval.clone()], |closed, open| {
+                closed
+                    .iter()
+                    .flat_map(|interval| interval.difference(&open))
+                    .collect()
+            });
+        match closed.into_iter().next() {
+            Some(closed) => Err(Error::FacilityClosed { closed }),
+            None => Ok(())
+        }
+    }
+
+    /// Errors if the booking takes more seats than the facility has.
+    fn check_capacity(&self, booking: &Booking) -> Result<(), Error> {
+        if booking.seats > self.attributes.capacity {
+            return Err(Error::InsufficientCapacity { requested: booking.seats, available: self.attributes.capacity });
+        }
+        Ok(())
+    }
+
+    /// Returns if the booking could be added, as the facility is open and has enough seats free throughout it.
+    pub fn is_bookable(&self, booking: &Booking) -> Result<bool, StoreError> {
+        if self.check_open(booking).is_err() || self.check_capacity(booking).is_err() {
+            return Ok(false);
+        }
+        Ok(self.find_conflict(booking, &[])?.is_none())
+    }
+
+    /// Errors if the facility is closed during the booking, doesn't have enough seats,
+    /// or it conflicts with a current booking other than those being replaced.
+    fn check_bookable(&self, booking: &Booking, replacing: &[BookingId]) -> Result<(), Error> {
+        self.check_open(booking)?;
+        self.check_capacity(booking)?;
+        match self.find_conflict(booking, replacing)? {
+            Some((_, err)) => Err(err),
+            None => Ok(())
+        }
+    }
+
+    /// Returns the ID of a current booking (other than those being replaced) which takes the seats the given booking needs,
+    /// or failing that does so within the turnaround buffer of it, along with the error for it, if any.
+    /// 
+    /// The booking must not take more seats than the facility has.
+    fn find_conflict(
+        &self,
+        booking: &Booking,
+        replacing: &[BookingId]
+    ) -> Result<Option<(BookingId, Error)>, StoreError> {
+        for buffered in [false, true] {
+            let Some((busiest, occupied)) = self.occupancy(&booking.interval, buffered, replacing)?
+                .into_iter()
+                .max_by_key(|(_, occupied)| *occupied)
+            else {
+                return Ok(None);
+            };
+            if occupied + booking.seats <= self.attributes.capacity {
+                continue;
             }
-            current_time = booking.end_time.clone();
+
+            let search = match buffered {
+                true => self.padded_interval(&busiest),
+                false => busiest
+            };
+            let Some((conflicting, _)) = self.bookings
+                .overlapping(&search)?
+                .into_iter()
+                .find(|(id, _)| !replacing.contains(id))
+            else {
+                return Ok(None);
+            };
+            let err = match (buffered, self.attributes.capacity) {
+                (false, 1) => Error::Overlap { conflicting },
+                (false, _) => Error::InsufficientCapacity {
+                    requested: booking.seats,
+                    available: self.attributes.capacity.saturating_sub(occupied)
+                },
+                (true, _) => Error::TurnaroundConflict { conflicting, buffer: self.turnaround_buffer }
+            };
+            return Ok(Some((conflicting, err)));
         }
-        if current_time <= day_end {
-            open_slots.push((current_time, day_end));
+        Ok(None)
+    }
+
+    /// The seats taken during each part of the interval, in order, by current bookings other than those being replaced;
+    /// if `buffered`, bookings also take their seats for the turnaround buffer either side of them.
+    fn occupancy(
+        &self,
+        interval: &Interval,
+        buffered: bool,
+        replacing: &[BookingId]
+    ) -> Result<Vec<(Interval, u16)>, StoreError> {
+        let search = match buffered {
+            true => self.padded_interval(interval),
+            false => interval.clone()
+        };
+
+        // the change in seats taken at each time
+        let mut changes = BTreeMap::from([(interval.start().clone(), 0), (interval.end().clone(), 0)]);
+        for (_, booking) in self.bookings.overlapping(&search)?.into_iter().filter(|(id, _)| !replacing.contains(id)) {
+            let booked = match buffered {
+                true => self.padded_interval(&booking.interval),
+                false => booking.interval.clone()
+            };
+            if let Some(booked) = booked.intersection(interval) {
+                *changes.entry(booked.start().clone()).or_insert(0) += i32::from(booking.seats);
+                *changes.entry(booked.end().clone()).or_insert(0) -= i32::from(booking.seats);
+            }
         }
 
-        let mut result = String::new();
-        
-        for (i, (start, end)) in open_slots.iter().enumerate() {
-            result.push_str(&format!("{}. {} - {}\n", i + 1, start, end));
+        let changes: Vec<_> = changes.into_iter().collect();
+        let mut occupied = 0;
+        Ok(
+            changes
+                .windows(2)
+                .map(|window| {
+                    let [(start, change), (end, _)] = window else { unreachable!("Windows should have 2 items") };
+                    occupied += change;
+                    let interval = Interval::new(start.clone(), end.clone()).expect("Changes should be in order");
+                    (interval, u16::try_from(occupied).unwrap_or(u16::MAX))
+                })
+                .collect()
+        )
+    }
+
+    /// Returns the interval along with the turnaround buffer either side of it,
+    /// which no other booking may overlap with.
+    fn padded_interval(&self, interval: &Interval) -> Interval {
+        interval
+            .checked_expand(self.turnaround_buffer)
+            .unwrap_or_else(|| interval.clone()) // only out of range near the ends of time, where there's nothing to buffer
+    }
+
+    /// Returns every booking, in no particular order.
+    pub fn bookings(&self) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        self.bookings.all()
+    }
+
+    pub fn booking_count(&self) -> Result<usize, StoreError> {
+        self.bookings.count()
+    }
+
+    /// Returns the booking details of a given booking ID, if it exists.
+    pub fn get_booking_details(&self, booking_id: &BookingId) -> Result<Option<Booking>, StoreError> {
+        self.bookings.get(booking_id)
+    }
+
+    /// Remove the booking given by its ID.
+    /// 
+    /// Errors if the booking ID doesn't exist.
+    pub fn remove_booking(&mut self, booking_id: &BookingId) -> Result<Booking, Error> {
+        self.bookings
+            .remove(booking_id)?
+            .ok_or(Error::BookingNotFound { booking_id: *booking_id })
+    }
+
+    /// Remove every booking, returning them, eg. as the facility is retired.
+    pub fn remove_all(&mut self) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        let bookings = self.bookings.all()?;
+        for (booking_id, _) in &bookings {
+            self.bookings.remove(booking_id)?;
         }
-        
-        result
+        Ok(bookings)
+    }
+
+    /// Returns every occurrence of the series, in order.
+    pub fn get_series(&self, series_id: &SeriesId) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        self.bookings.series(series_id)
     }
 
-    /// Offset the booking by given hours and minutes.
+    /// Remove every occurrence of the series, returning them in order.
     /// 
-    /// Errors if the booking ID doesn't exist, the offsetted booking overlaps with current ones, 
-    /// or the offset pushes the booking into a different day.
-    pub fn offset_booking(
-        &mut self, 
-        booking_id: BookingId, 
-        hours: Hour, 
-        minutes: Minute, 
-        negative: bool
-    ) -> Result<(), String> 
-    {
-        let booking = self.remove_booking(&booking_id)?;
-        let mut offset_booking = booking.clone();
-        
-        if let Err(err) = offset_booking.offset(hours, minutes, negative) {
-            self.add_booking_with_id(booking_id, booking)?;
-            return Err(err);
+    /// Errors if the series has no occurrences.
+    pub fn remove_series(&mut self, series_id: &SeriesId) -> Result<Vec<(BookingId, Booking)>, Error> {
+        let series_ids: Vec<_> = self.get_series(series_id)?
+            .into_iter()
+            .map(|(booking_id, _)| booking_id)
+            .collect();
+        if series_ids.is_empty() {
+            return Err(Error::BookingNotFound { booking_id: *series_id });
         }
-        else if let Err(err) = self.add_booking_with_id(booking_id, offset_booking) {
-            self.add_booking_with_id(booking_id, booking)?;
-            return Err(err);
+        let mut removed = Vec::with_capacity(series_ids.len());
+        for booking_id in series_ids {
+            if let Some(occurrence) = self.bookings.remove(&booking_id)? {
+                removed.push((booking_id, occurrence));
+            }
         }
-        Ok(())
+        Ok(removed)
     }
 
-    /// Extends the booking by offsetting the end time forward.
+    /// Get the times with seats free on the date, as slots in order, along with the number of seats free.
     /// 
-    /// Errors if the booking ID doesn't exist, the extended booking overlaps with current ones,
-    /// or the extension pushes the booking into a different day.
-    pub fn extend_booking(
-        &mut self, 
-        booking_id: BookingId, 
-        hours: Hour, 
-        minutes: Minute, 
-    ) -> Result<(), String> {
-        let booking = self.remove_booking(&booking_id)?;
-        let mut extended_booking = booking.clone();
+    /// Only times the facility is open are available, which may run up to (but not including) the next date's 00:00.
+    /// Bookings spanning several days only take seats in the part which falls on the date,
+    /// and also take them for the turnaround buffer either side of them.
+    pub fn get_availabilities(&self, date: NaiveDate) -> Result<Vec<Slot>, StoreError> {
+        let mut occupancy = Vec::new();
+        for open in self.open_intervals(date) {
+            occupancy.extend(self.occupancy(&open, true, &[])?);
+        }
+        let slots = occupancy
+            .into_iter()
+            .filter(|(_, occupied)| *occupied < self.attributes.capacity)
+            .fold(Vec::new(), |mut slots: Vec<Slot>, (interval, occupied)| {
+                let free_seats = self.attributes.capacity - occupied;
+                match slots.last_mut() {
+                    Some(last) if last.free_seats == free_seats && last.interval.end() == interval.start() => {
+                        last.interval = last.interval.union(&interval).expect("Touching intervals should join");
+                    },
+                    _ => slots.push(Slot { interval, free_seats })
+                }
+                slots
+            });
+        Ok(slots)
+    }
+
+    /// Offset the booking by the given duration.
+    /// 
+    /// Errors if the booking ID doesn't exist, the facility is closed during the offsetted booking,
+    /// it conflicts with current ones, or it's offsetted out of range.
+    pub fn offset_booking(&mut self, booking_id: BookingId, offset: Duration) -> Result<(), Error> {
+        let mut offset_booking = self.get_booking_details(&booking_id)?
+            .ok_or(Error::BookingNotFound { booking_id })?;
+        offset_booking.offset(offset)?;
+        self.replace(booking_id, offset_booking)
+    }
+
+    /// Offset every occurrence of the series by the given duration, returning the offsetted occurrences in order.
+    /// 
+    /// Either every occurrence is offsetted, or none are; errors if the series has no occurrences,
+    /// the facility is closed during an offsetted occurrence, it conflicts with current bookings,
+    /// or it's offsetted out of range.
+    pub fn offset_series(&mut self, series_id: SeriesId, offset: Duration) -> Result<Vec<(BookingId, Booking)>, Error> {
+        let offset_series = self.get_series(&series_id)?
+            .into_iter()
+            .map(|(booking_id, mut occurrence)| {
+                occurrence.offset(offset)?;
+                Ok((booking_id, occurrence))
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        if offset_series.is_empty() {
+            return Err(Error::BookingNotFound { booking_id: series_id });
+        }
 
-        if let Err(err) = extended_booking.extend(hours, minutes) {
-            self.add_booking_with_id(booking_id, booking)?;
-            return Err(err);
+        let series_ids: Vec<_> = offset_series
+            .iter()
+            .map(|(booking_id, _)| *booking_id)
+            .collect();
+        for (_, occurrence) in &offset_series {
+            self.check_bookable(occurrence, &series_ids)?;
         }
-        else if let Err(err) = self.add_booking_with_id(booking_id, extended_booking) {
-            self.add_booking_with_id(booking_id, booking)?;
-            return Err(err);
+        for (booking_id, occurrence) in &offset_series {
+            self.bookings.insert(*booking_id, occurrence.clone())?;
         }
+        Ok(offset_series)
+    }
+
+    /// Extends the booking by moving its end time by the given duration, which shortens it if negative.
+    /// 
+    /// Errors if the booking ID doesn't exist, the facility is closed during the extended booking,
+    /// it conflicts with current ones, or it would no longer end after it starts.
+    pub fn extend_booking(&mut self, booking_id: BookingId, extension: Duration) -> Result<(), Error> {
+        let mut extended_booking = self.get_booking_details(&booking_id)?
+            .ok_or(Error::BookingNotFound { booking_id })?;
+        extended_booking.extend(extension)?;
+        self.replace(booking_id, extended_booking)
+    }
+
+    /// Replaces the booking with the given one, in place.
+    /// 
+    /// Errors if the facility is closed during the new booking, or it conflicts with other current bookings.
+    fn replace(&mut self, booking_id: BookingId, booking: Booking) -> Result<(), Error> {
+        self.check_bookable(&booking, &[booking_id])?;
+        self.bookings.insert(booking_id, booking)?;
         Ok(())
     }
 }
 
+/// Returns every date the interval falls on, in order.
+///
+/// As it doesn't include its end time, an interval ending at midnight doesn't fall on the next date.
+pub(crate) fn dates(interval: &Interval) -> impl Iterator<Item = NaiveDate> + '_ {
+    let last_minute = interval
+        .end()
+        .checked_add(Duration::from_minutes(-1))
+        .unwrap_or_else(|| interval.end().clone());
+    interval.start().date
+        .iter_days()
+        .take_while(move |date| date <= &last_minute.date)
+}
+
 /// The booking ID, which is just a Uuid (which is just 16 bytes).
 pub type BookingId = Uuid;
 
-/// A booking, marked by a start and end time.
-/// 
-/// As a rule, all bookings must start and end on the same day.
-#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+/// The ID of a recurring booking, shared by all its occurrences.
+pub type SeriesId = Uuid;
+
+/// The ID of a facility, which stays the same if it's renamed, and isn't reused once it's retired.
+pub type FacilityId = u32;
+
+/// A booking, marked by the interval of time it's for.
+///
+/// Bookings may span several days, eg. running past midnight.
+#[derive(ByteableDerive, Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
 pub struct Booking {
-    start_time: Time,
-    end_time: Time
+    interval: Interval,
+    /// The number of the facility's seats it takes.
+    seats: u16,
+    /// The recurring booking this is an occurrence of, if any.
+    series_id: Option<SeriesId>
 }
 
 impl Booking {
-    /// Create the booking.
+    /// Create the booking, for 1 seat.
     /// 
-    /// Errors if `start_time` is equal or after `end_time`.
-    pub fn new(start_time: Time, end_time: Time) -> Result<Self, String> {
-        if start_time >= end_time {
-            return Err(format!("Start time ({start_time:?}) is equal or after end time ({end_time:?})"));
-        }
-        if start_time.day != end_time.day {
-            return Err(format!("Start time day ({}) must match end time day ({})", start_time.day, end_time.day));
-        }
+    /// Errors if the interval is empty, or ends over `MAX_BOOKING_DAYS` after the date it starts on.
+    pub fn new(interval: Interval) -> Result<Self, Error> {
+        check_interval(&interval)?;
         Ok(
-            Self { start_time, end_time }
+            Self { interval, seats: 1, series_id: None }
         )
     }
 
-    /// Returns the start and end times of the booking.
-    pub fn time(&self) -> (&Time, &Time) {
-        (&self.start_time, &self.end_time)
+    /// Sets the number of the facility's seats the booking takes.
+    /// 
+    /// Errors if it's 0.
+    pub fn with_seats(self, seats: u16) -> Result<Self, Error> {
+        if seats == 0 {
+            return Err(Error::InvalidSeats { reason: "Bookings must take at least 1 seat".into() });
+        }
+        Ok(Self { seats, ..self })
     }
 
-    /// Returns if the 2 bookings overlap.
-    pub fn overlaps(&self, other_booking: &Booking) -> bool {
-        (self.start_time <= other_booking.start_time && self.end_time >= other_booking.start_time)
-        || (other_booking.start_time <= self.start_time && other_booking.end_time >= self.start_time)
+    /// Makes the booking an occurrence of the series.
+    pub fn in_series(self, series_id: SeriesId) -> Self {
+        Self {
+            series_id: Some(series_id),
+            ..self
+        }
     }
 
-    /// Offsets the booking by the given time. 
-    /// 
-    /// Errors if the offset pushes the booking into another day.
-    pub fn offset(
-        &mut self, 
-        hours: Hour,
-        minutes: Minute,
-        negative: bool
-    ) -> Result<(), String> {
-        let cur_day = self.start_time.day.clone();
-
-        self.start_time.offset(hours, minutes, negative);
-        self.end_time.offset(hours, minutes, negative);
+    pub fn interval(&self) -> &Interval {
+        &self.interval
+    }
 
-        if self.start_time.day != cur_day || self.end_time.day != cur_day {
-            self.start_time.offset(hours, minutes, !negative);
-            self.end_time.offset(hours, minutes, !negative);
-            return Err("Offset pushes the booking into another day".into());
-        }
+    /// Returns the number of the facility's seats the booking takes.
+    pub fn seats(&self) -> u16 {
+        self.seats
+    }
 
-        Ok(())
+    /// Returns the recurring booking this is an occurrence of, if any.
+    pub fn series_id(&self) -> Option<SeriesId> {
+        self.series_id
     }
 
-    /// Extends the booking by the given time. 
+    /// Returns every date the booking falls on, in order.
     /// 
-    /// Errors if the extension pushes the booking into another day.
-    pub fn extend(
-        &mut self, 
-        hours: Hour,
-        minutes: Minute,
-    ) -> Result<(), String> {
-        self.end_time.offset(hours, minutes, false);
+    /// As it doesn't include its end time, a booking ending at midnight doesn't fall on the next date.
+    pub fn dates(&self) -> impl Iterator<Item = NaiveDate> + '_ {
+        dates(&self.interval)
+    }
 
-        if self.end_time.day != self.start_time.day {
-            self.end_time.offset(hours, minutes, true);
-            return Err("Offset pushes the booking into another day".into());
-        }
+    /// Returns if the 2 bookings overlap; bookings which only touch, eg. one ending at 10:00 and another starting then, don't.
+    pub fn overlaps(&self, other_booking: &Booking) -> bool {
+        self.interval.overlaps(&other_booking.interval)
+    }
 
+    /// Offsets the booking by the given duration, which may move it into other days.
+    /// 
+    /// Errors if it's offsetted out of range, or would then end over `MAX_BOOKING_DAYS` after the date it starts on.
+    pub fn offset(&mut self, offset: Duration) -> Result<(), Error> {
+        let interval = self.interval
+            .checked_add(offset)
+            .ok_or_else(|| Error::InvalidTime { reason: format!("Offsetting by {offset} is out of range") })?;
+        check_interval(&interval)?;
+        self.interval = interval;
+        Ok(())
+    }
+
+    /// Extends the booking by the given duration, which may run it into following days, or shorten it if negative.
+    /// 
+    /// Errors if it would no longer end after it starts, or would end over `MAX_BOOKING_DAYS` after the date it starts on.
+    pub fn extend(&mut self, extension: Duration) -> Result<(), Error> {
+        let end_time = self.interval
+            .end()
+            .checked_add(extension)
+            .ok_or_else(|| Error::InvalidTime { reason: format!("Extending by {extension} is out of range") })?;
+        let interval = Interval::new(self.interval.start().clone(), end_time)
+            .map_err(|reason| Error::InvalidTime { reason })?;
+        self.interval = Booking::new(interval)?.interval;
         Ok(())
     }
 }
+
+/// Checks a booking's interval isn't empty, and doesn't end over `MAX_BOOKING_DAYS` after the date it starts on.
+fn check_interval(interval: &Interval) -> Result<(), Error> {
+    if interval.is_empty() {
+        return Err(Error::InvalidTime {
+            reason: format!("Start time ({}) is equal to end time ({})", interval.start(), interval.end())
+        });
+    }
+    if (interval.end().date - interval.start().date).num_days() > MAX_BOOKING_DAYS {
+        return Err(Error::InvalidTime {
+            reason: format!("Bookings can't end over {MAX_BOOKING_DAYS} days after the date they start on")
+        });
+    }
+    Ok(())
+}
@@ -1,52 +1,229 @@
-use std::net::SocketAddr;
-use chrono::{DateTime, Duration, Utc};
-use shared::{requests::{AvailabilityRequest, BookRequest, CancelBookingRequest, ExtendBookingRequest, MonitorFacilityRequest, OffsetBookingRequest, RawRequest, RequestType}, responses::RawResponse, time::Day};
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, mem, net::SocketAddr, sync::mpsc::Receiver, time::Instant};
+use chrono::{DateTime, NaiveDate, TimeDelta, Utc};
+use subtle::ConstantTimeEq;
+use shared::{error::Error, facility::{FacilityAttributes, FacilitySummary}, protocol::Header, requests::{AdminAction, AdminRequest, AvailabilityRequest, BookRequest, CancelBookingRequest, ExtendBookingRequest, MonitorFacilityRequest, OffsetBookingRequest, RawRequest, RecurringBookRequest, RequestType, RetirePolicy, SearchFacilitiesRequest}, responses::{DayAvailability, RawResponse, ResponseType}, time::Duration};
 use uuid::Uuid;
-use crate::{facilities::{Booking, Facility}, socket::SenderReceiver};
+use crate::{config::ReloadableSettings, facilities::{Booking, BookingId, Facility, FacilityId}, socket::SenderReceiver, storage::{Mutation, Recovered, Snapshot, Storage, StoredFacility, StoredResponse}, store::{Backend, StoreError, Stores}};
+
+/// The most days availability can be checked for in one request.
+const MAX_AVAILABILITY_DAYS: i64 = 31;
+
+/// The most occurrences a recurring booking can have.
+const MAX_OCCURRENCES: usize = 100;
 
 /// Handles messages.
 pub struct Handler {
     sender_receiver: SenderReceiver,
-    facilities: Vec<Facility>,
-    monitoring_addresses: Vec<(SocketAddr, String, DateTime<Utc>)>, // note: String is the facility name, DateTime is the expiry date
+    /// The facilities by ID, so in the order they were created.
+    facilities: BTreeMap<FacilityId, Facility>,
+    /// The ID the next created facility gets.
+    next_facility_id: FacilityId,
+    /// The facility each booking is for, so bookings can be found without searching every facility.
+    booking_facilities: HashMap<BookingId, FacilityId>,
+    monitoring_addresses: Vec<MonitoringAddress>,
+    /// The token admin requests must give, or `None` to reject them all.
+    admin_token: Option<String>,
+    /// Settings reloaded while running, which are applied before handling the next message.
+    reloads: Option<Receiver<ReloadableSettings>>,
+    /// Where changes are kept so they survive a restart, or `None` to only keep them in memory.
+    storage: Option<Storage>,
+    /// The backend facilities' booking stores are opened from.
+    stores: Stores,
+    /// The names facilities were retired or renamed from, so facilities the server starts with under them aren't
+    /// brought back.
+    retired_names: BTreeSet<String>,
+    /// The mutations made while handling the current request, logged along with its response.
+    pending: Vec<Mutation>,
+    /// The monitor updates queued while handling the current request, sent once its mutations are logged.
+    updates: Vec<(RawResponse, SocketAddr, Header)>,
+}
+
+/// An address monitoring a facility.
+struct MonitoringAddress {
+    addr: SocketAddr,
+    facility_name: String,
+    expiry: DateTime<Utc>,
+    /// The header to send updates with, matching the monitor request's version and encoding.
+    reply_header: Header
 }
 
 impl Handler {
-    /// Instantiate the handler.
-    pub fn new(sender_receiver: SenderReceiver) -> Self {
-        let facilities = vec![ // initial facilities
-            Facility::new("MR1".into()),
-            Facility::new("MR2".into()),
-            Facility::new("MR3".into()),
-            Facility::new("MR4".into()),
-            Facility::new("MR5".into()),
-        ];
+    /// Instantiate the handler, with the facilities it starts with.
+    pub fn new(sender_receiver: SenderReceiver, facilities: Vec<Facility>) -> Self {
+        let facilities: BTreeMap<_, _> = (0..).zip(facilities).collect();
+        let next_facility_id = facilities.len() as FacilityId;
         let monitoring_addresses = Vec::new();
         Self {
             sender_receiver,
             facilities,
+            next_facility_id,
+            booking_facilities: HashMap::new(),
             monitoring_addresses,
+            admin_token: None,
+            reloads: None,
+            storage: None,
+            stores: Stores::Memory,
+            retired_names: BTreeSet::new(),
+            pending: Vec::new(),
+            updates: Vec::new(),
+        }
+    }
+
+    /// Accepts admin requests which give the token.
+    pub fn with_admin_token(mut self, admin_token: String) -> Self {
+        self.admin_token = Some(admin_token);
+        self
+    }
+
+    /// Applies settings sent to the receiver before handling each message.
+    pub fn with_reloads(mut self, reloads: Receiver<ReloadableSettings>) -> Self {
+        self.reloads = Some(reloads);
+        self
+    }
+
+    /// Keeps facilities' bookings in stores opened from the backend, taking any bookings it already has.
+    /// 
+    /// Should be called before `with_storage`, so restored facilities' bookings are loaded from and kept in it.
+    /// 
+    /// Errors if the backend can't be connected to, or read.
+    pub fn with_backend(mut self, backend: Backend) -> Result<Self, String> {
+        self.stores = backend.connect().map_err(|err| err.to_string())?;
+        for (facility_id, facility) in mem::take(&mut self.facilities) {
+            self.facilities.insert(facility_id, facility.with_store(self.stores.open(facility_id)));
+        }
+        self.booking_facilities = self.find_booking_facilities().map_err(|err| err.to_string())?;
+        Ok(self)
+    }
+
+    /// Returns the facility each booking in the stores is for.
+    fn find_booking_facilities(&self) -> Result<HashMap<BookingId, FacilityId>, StoreError> {
+        let mut booking_facilities = HashMap::new();
+        for (&facility_id, facility) in &self.facilities {
+            for (booking_id, _) in facility.bookings()? {
+                booking_facilities.insert(booking_id, facility_id);
+            }
+        }
+        Ok(booking_facilities)
+    }
+
+    /// Restores the facilities and bookings recovered from the storage, and logs every later change to it.
+    /// 
+    /// Bookings are loaded from the stores if they have them as of a mutation still in the storage, replaying only
+    /// the mutations logged after it; otherwise, the stores are cleared, and they're restored from the snapshot.
+    /// Facilities the handler started with are matched to restored ones by name, keeping their definition
+    /// (eg. opening hours) and taking the restored bookings. Ones which weren't stored yet are added to the storage,
+    /// unless they were renamed or retired; restored ones which the handler didn't start with are kept, always open.
+    /// Cached responses kept in the storage are cached again, unless they've expired since.
+    /// 
+    /// Errors if the stores have bookings as of a mutation after the last in the storage, the recovered mutations
+    /// don't apply to the snapshot, or the storage or stores can't be written.
+    pub fn with_storage(mut self, storage: Storage, recovered: Recovered) -> Result<Self, String> {
+        let started_with = mem::take(&mut self.facilities);
+        let snapshot = recovered.snapshot.unwrap_or_default();
+        let mut mutations = recovered.mutations;
+        let last = snapshot.sequence + mutations.len() as u64;
+        let loaded = match self.stores.sequence() {
+            Some(sequence) if sequence > last => {
+                return Err(format!("Booking stores have changes up to mutation {sequence}, but storage only has up to {last}"));
+            },
+            Some(sequence) if sequence < snapshot.sequence => {
+                tracing::warn!(
+                    "Booking stores only have changes up to mutation {sequence}, before the snapshot ({}); restoring them from it",
+                    snapshot.sequence
+                );
+                None
+            },
+            sequence => sequence
+        };
+        self.stores.begin().map_err(|err| err.to_string())?;
+        if loaded.is_none() {
+            self.stores.clear().map_err(|err| err.to_string())?;
+        }
+
+        self.next_facility_id = snapshot.next_facility_id;
+        self.retired_names = snapshot.retired_names.into_iter().collect();
+        for stored in snapshot.facilities {
+            let mut facility = Facility::new(stored.facility_name)
+                .with_attributes(stored.attributes)
+                .with_turnaround_buffer(stored.turnaround_buffer)
+                .with_store(self.stores.open(stored.facility_id));
+            if loaded.is_none() {
+                for (booking_id, booking) in stored.bookings {
+                    facility.restore_booking(booking_id, booking).map_err(|err| err.to_string())?;
+                }
+            }
+            self.facilities.insert(stored.facility_id, facility);
+        }
+
+        // the stores already have the bookings as of the mutation they were loaded at, so only facilities are changed
+        let applied = loaded.unwrap_or(snapshot.sequence);
+        let unapplied = mutations.split_off((applied - snapshot.sequence) as usize);
+        for (index, mutation) in mutations.into_iter().enumerate() {
+            self.replay(mutation, false)
+                .map_err(|err| format!("Unable to replay mutation {}: {err}", snapshot.sequence + index as u64 + 1))?;
+        }
+        self.booking_facilities = self.find_booking_facilities().map_err(|err| err.to_string())?;
+        if let Some(sequence) = loaded {
+            tracing::info!("Loaded bookings from the stores as of mutation {sequence}, replaying {} after it", unapplied.len());
         }
+        for (index, mutation) in unapplied.into_iter().enumerate() {
+            self.replay(mutation, true)
+                .map_err(|err| format!("Unable to replay mutation {}: {err}", applied + index as u64 + 1))?;
+        }
+        for stored in snapshot.responses.into_iter().chain(recovered.responses) {
+            if let Some((request, response_bytes, cached)) = stored.restore() {
+                self.sender_receiver.restore_cached(request, response_bytes, cached);
+            }
+        }
+
+        self.storage = Some(storage);
+        for facility in started_with.into_values() {
+            match self.find_facility_id(&facility.name) {
+                Ok(facility_id) => {
+                    let restored = self.facilities
+                        .remove(&facility_id)
+                        .expect("Found facility should exist");
+                    self.facilities.insert(facility_id, facility.with_store(restored.into_store()));
+                },
+                Err(_) if self.retired_names.contains(&facility.name) => {
+                    tracing::warn!("Not adding facility {}, as it was renamed or retired", facility.name);
+                },
+                Err(_) => {
+                    let mutation = Mutation::CreateFacility {
+                        facility_id: self.next_facility_id,
+                        facility_name: facility.name.clone(),
+                        attributes: facility.attributes().clone(),
+                        turnaround_buffer: facility.turnaround_buffer()
+                    };
+                    self.add_facility(facility);
+                    self.record(mutation);
+                }
+            }
+        }
+        self.try_commit(None)?;
+        tracing::info!(
+            "Restored {} facilities with {} bookings and {} cached responses from storage",
+            self.facilities.len(),
+            self.booking_facilities.len(),
+            self.sender_receiver.cached_responses().count()
+        );
+        Ok(self)
     }
 
     /// Infinitely receives and handles messages.
     pub fn run(&mut self) {
         loop {
             match self.sender_receiver.receive() { 
-                Ok((req, source_addr)) => {
-                    let response = self.handle_message(req, &source_addr);
-                    match response {
-                        Ok(res) => {
-                            match self.sender_receiver.send(&res, &source_addr) {
-                                Ok(ok) => {
-                                    tracing::debug!("Successfully sent response to {}", source_addr);
-                                },
-                                Err(err) => {
-                                    tracing::warn!("Error sending response to {}: {}", source_addr, err);
-                                }
-                            }
+                Ok((req, source_addr, header)) => {
+                    self.apply_reloads();
+                    let response = self.handle_message(req, &source_addr, header.reply());
+                    match self.respond(&response, &source_addr, header.reply()) {
+                        Ok(_) => {
+                            tracing::debug!("Successfully sent response to {}", source_addr);
                         },
-                        Err(err) => tracing::warn!("Error while handling message: {err}")
+                        Err(err) => {
+                            tracing::warn!("Error sending response to {}: {}", source_addr, err);
+                        }
                     }
                 },
                 Err(err) => tracing::warn!("Error receiving message: {err}")
@@ -54,10 +231,70 @@ impl Handler {
         }
     }
 
-    /// Handles a message, returning the response as bytes.
-    pub fn handle_message(&mut self, req: RawRequest, source_addr: &SocketAddr) -> Result<RawResponse, String> 
+    /// Sends the response to a request, once the mutations made while handling it are logged along with it,
+    /// then sends the monitor updates queued while handling it.
+    /// 
+    /// The response is logged with the mutations if it's cached, so a retry after a restart gets it from the cache
+    /// instead of making them again.
+    fn respond(&mut self, response: &RawResponse, addr: &SocketAddr, header: Header) -> Result<(), Error> {
+        let response_bytes = self.sender_receiver.encode(response, addr, header);
+        let mut stored = None;
+        if let Ok(response_bytes) = &response_bytes {
+            self.sender_receiver.cache(*addr, response.request_id, response_bytes);
+            if !self.pending.is_empty() && self.sender_receiver.caches_responses() {
+                stored = Some(StoredResponse::new((*addr, response.request_id), response_bytes.clone(), Instant::now()));
+            }
+        }
+        self.commit(stored);
+        self.send_updates();
+        self.sender_receiver.send_encoded(response_bytes?, addr, response)
+    }
+
+    /// Applies the latest reloaded settings, if any have been sent since the last message.
+    fn apply_reloads(&mut self) {
+        let Some(settings) = self.reloads
+            .as_ref()
+            .and_then(|reloads| reloads.try_iter().last())
+        else {
+            return;
+        };
+        tracing::info!("Applying reloaded settings (admin requests {})", if settings.admin_token.is_some() { "enabled" } else { "disabled" });
+        self.sender_receiver.apply(settings.socket);
+        self.admin_token = settings.admin_token;
+    }
+
+    /// Handles a message, returning the response.
+    /// 
+    /// `reply_header` is the header responses to the source address should be sent with.
+    /// 
+    /// Its writes to the booking stores are made in a transaction, which is rolled back if it fails,
+    /// or otherwise committed once its mutations are logged (see `try_commit`).
+    pub fn handle_message(&mut self, req: RawRequest, source_addr: &SocketAddr, reply_header: Header) -> RawResponse
     {
-        let result = match req.request_type {
+        let result = self.stores
+            .begin()
+            .map_err(Error::from)
+            .and_then(|_| self.handle_request(req.request_type, source_addr, reply_header));
+        if result.is_err() {
+            if let Err(err) = self.stores.rollback() {
+                tracing::error!("Unable to roll back failed request's writes to the booking stores: {err}");
+            }
+        }
+        let response_type = result.unwrap_or_else(ResponseType::Error);
+        RawResponse {
+            request_id: req.request_id,
+            response_type
+        }
+    }
+
+    /// Handles a request by its type.
+    fn handle_request(
+        &mut self,
+        request_type: RequestType,
+        source_addr: &SocketAddr,
+        reply_header: Header
+    ) -> Result<ResponseType, Error> {
+        match request_type {
             RequestType::Availability(req) => {
                 self.handle_availability_request(req)
             },
@@ -74,202 +311,767 @@ impl Handler {
                 self.handle_extend_request(req)
             },
             RequestType::Monitor(req) => {
-                self.handle_monitor_request(req, source_addr)
+                self.handle_monitor_request(req, source_addr, reply_header)
             },
-        };
-        let response = match result {
-            Ok(message) => {
-                RawResponse {
-                    request_id: req.request_id,
-                    is_error: false,
-                    message
-                }
+            RequestType::RecurringBook(req) => {
+                self.handle_recurring_booking_request(req)
             },
-            Err(err) => {
-                RawResponse {
-                    request_id: req.request_id,
-                    is_error: true,
-                    message: err
-                }
-            }
-        };
-        Ok(response)
+            RequestType::SearchFacilities(req) => {
+                self.handle_search_request(req)
+            },
+            RequestType::Admin(req) => {
+                self.handle_admin_request(req, source_addr)
+            },
+        }
     }
 
     /// Handles request for availabilities.
-    fn handle_availability_request(&self, mut req: AvailabilityRequest) -> Result<String, String> {
+    /// 
+    /// Errors if the date range is backwards or longer than `MAX_AVAILABILITY_DAYS`.
+    fn handle_availability_request(&self, req: AvailabilityRequest) -> Result<ResponseType, Error> {
+        if req.end_date < req.start_date {
+            return Err(Error::InvalidTime {
+                reason: format!("Start date ({}) is after end date ({})", req.start_date, req.end_date)
+            });
+        }
+        if (req.end_date - req.start_date).num_days() >= MAX_AVAILABILITY_DAYS {
+            return Err(Error::InvalidTime {
+                reason: format!("Can only check availability for up to {MAX_AVAILABILITY_DAYS} days at once")
+            });
+        }
+
         match self.facilities
-            .iter()
+            .values()
             .find(|&facility| facility.name == req.facility_name)
         {
             Some(facility) => {
-                req.days.sort();
-                req.days.dedup(); // in case >1 of the same day
-                let availabilities = req.days
-                    .into_iter()
-                    .map(|day| format!("-----\n {}\n -----\n", facility.get_availabilities(day)))
-                    .collect();
-                return Ok(availabilities);
+                let availabilities = req.dates()
+                    .map(|date| Ok(DayAvailability {
+                        date,
+                        slots: facility.get_availabilities(date)?
+                    }))
+                    .collect::<Result<_, StoreError>>()?;
+                Ok(ResponseType::Availability {
+                    facility_name: req.facility_name,
+                    availabilities,
+                    turnaround_buffer: facility.turnaround_buffer()
+                })
             },
             None => {
-                return Err("No such facility found".to_string());
+                Err(Error::FacilityNotFound { facility_name: req.facility_name })
+            }
+        }
+    }
+
+    /// Finds the facilities matching the search, in order.
+    /// 
+    /// Errors if `free_during` is empty.
+    fn handle_search_request(&self, req: SearchFacilitiesRequest) -> Result<ResponseType, Error> {
+        let booking = req.free_during
+            .clone()
+            .map(|interval| Booking::new(interval).and_then(|booking| booking.with_seats(req.min_capacity.max(1))))
+            .transpose()?;
+
+        let mut facilities = Vec::new();
+        for facility in self.facilities.values().filter(|facility| req.matches(facility.attributes())) {
+            if let Some(booking) = &booking {
+                if !facility.is_bookable(booking)? {
+                    continue;
+                }
             }
+            facilities.push((facility.name.clone(), facility.attributes().clone()));
         }
+        Ok(ResponseType::SearchResults { facilities })
     }
 
     /// Attempts to add a new booking.
     /// 
-    /// If successful, also sends a message to monitoring addresses for updated availability on the affected day.
-    fn handle_booking_request(&mut self, req: BookRequest) -> Result<String, String> {
+    /// If successful, also sends a message to monitoring addresses for updated availability on the affected days.
+    fn handle_booking_request(&mut self, req: BookRequest) -> Result<ResponseType, Error> {
         match self.facilities
             .iter_mut()
-            .find(|facility| facility.name == req.facility_name)
+            .find(|(_, facility)| facility.name == req.facility_name)
         {
-            Some(facility) => {
-                let booking_day = req.start_time.day;
-                let new_booking = Booking::new(req.start_time, req.end_time)?;
-                let new_id = facility.add_new_booking(new_booking)?;
+            Some((&facility_id, facility)) => {
+                let new_booking = Booking::new(req.interval)?.with_seats(req.seats)?;
+                let booking_dates: Vec<_> = new_booking.dates().collect();
+                let new_id = facility.add_new_booking(new_booking.clone())?;
+                self.booking_facilities.insert(new_id, facility_id);
+                self.record(Mutation::Book { facility_id, bookings: vec![(new_id, new_booking)] });
 
-                self.send_monitor_message(&req.facility_name, booking_day);
+                self.send_monitor_messages(&req.facility_name, booking_dates);
 
-                return Ok(format!("Successfully added new booking with ID: {new_id}"));
+                Ok(ResponseType::BookingCreated { booking_id: new_id })
             },
             None => {
-                return Err("No such facility found".to_string());
+                Err(Error::FacilityNotFound { facility_name: req.facility_name })
             }
         }
     }
 
-    /// Attempts to offset a booking.
+    /// Attempts to add every occurrence of a recurring booking.
     /// 
-    /// If successful, also sends a message to monitoring addresses for updated availability on the affected day.
-    fn handle_offset_request(&mut self, req: OffsetBookingRequest) -> Result<String, String> {
-        for facility in &mut self.facilities {
-            if let Some(&(ref id, ref booking)) = facility.get_booking_details(&req.booking_id) {
-                let booking_day = booking.time().0.day;
-                let facility_name = facility.name.clone();
-                facility.offset_booking(
-                    req.booking_id, 
-                    req.offset_hours, 
-                    req.offset_min, 
-                    req.negative
-                )?;
-                self.send_monitor_message(&facility_name, booking_day);
-                return Ok(format!("Facility {facility_name} successfully offsetted"));
+    /// Errors if the rule has no occurrences or more than `MAX_OCCURRENCES`,
+    /// or with every conflicting occurrence if any overlap with current bookings.
+    /// 
+    /// If successful, also sends a message to monitoring addresses for updated availability on each affected day.
+    fn handle_recurring_booking_request(&mut self, req: RecurringBookRequest) -> Result<ResponseType, Error> {
+        if req.rule.interval_weeks == 0 {
+            return Err(Error::InvalidTime { reason: "Recurrence interval must be at least 1 week".into() });
+        }
+        Booking::new(req.interval.clone())?.with_seats(req.seats)?; // the interval and seats must be valid for every occurrence
+
+        let start_date = req.interval.start().date;
+        let dates: Vec<NaiveDate> = req.rule
+            .dates(start_date)
+            .take(MAX_OCCURRENCES + 1)
+            .collect();
+        if dates.is_empty() {
+            return Err(Error::InvalidTime { reason: "Recurrence has no occurrences".into() });
+        }
+        if dates.len() > MAX_OCCURRENCES {
+            return Err(Error::InvalidTime {
+                reason: format!("Recurring bookings can have at most {MAX_OCCURRENCES} occurrences")
+            });
+        }
+
+        match self.facilities
+            .iter_mut()
+            .find(|(_, facility)| facility.name == req.facility_name)
+        {
+            Some((&facility_id, facility)) => {
+                let occurrences = dates
+                    .iter()
+                    .map(|&date| {
+                        Duration::from_days((date - start_date).num_days() as i32)
+                            .and_then(|offset| req.interval.checked_add(offset))
+                            .ok_or_else(|| Error::InvalidTime { reason: format!("Occurrence on {date} is out of range") })
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let bookings = occurrences
+                    .iter()
+                    .map(|interval| Booking::new(interval.clone())?.with_seats(req.seats))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let booking_dates: Vec<_> = bookings
+                    .iter()
+                    .flat_map(|booking| booking.dates())
+                    .collect();
+                let (series_id, booking_ids) = facility.add_recurring_bookings(bookings)?;
+                let bookings = booking_ids
+                    .iter()
+                    .filter_map(|booking_id| {
+                        facility.get_booking_details(booking_id)
+                            .map(|booking| booking.map(|booking| (*booking_id, booking)))
+                            .transpose()
+                    })
+                    .collect::<Result<_, StoreError>>()?;
+                self.booking_facilities.extend(booking_ids.iter().map(|booking_id| (*booking_id, facility_id)));
+                self.record(Mutation::Book { facility_id, bookings });
+
+                self.send_monitor_messages(&req.facility_name, booking_dates);
+
+                let occurrences = booking_ids
+                    .into_iter()
+                    .zip(occurrences)
+                    .collect();
+                Ok(ResponseType::RecurringBookingCreated { series_id, occurrences })
+            },
+            None => {
+                Err(Error::FacilityNotFound { facility_name: req.facility_name })
             }
         }
-        Err(format!("No booking ID {} found in any facility", req.booking_id))
+    }
+
+    /// Attempts to offset a booking, or every occurrence of its series if `whole_series` is set.
+    /// 
+    /// If successful, also sends a message to monitoring addresses for updated availability on the affected days.
+    fn handle_offset_request(&mut self, req: OffsetBookingRequest) -> Result<ResponseType, Error> {
+        let facility = self.find_booking_facility(&req.booking_id)?;
+        let booking = facility.get_booking_details(&req.booking_id)?
+            .ok_or(Error::BookingNotFound { booking_id: req.booking_id })?;
+        let mut booking_dates: Vec<_> = booking.dates().collect();
+        let facility_name = facility.name.clone();
+
+        if let (true, Some(series_id)) = (req.whole_series, booking.series_id()) {
+            let mut booking_dates: Vec<_> = facility.get_series(&series_id)?
+                .iter()
+                .flat_map(|(_, occurrence)| occurrence.dates())
+                .collect();
+            let offset_series = facility.offset_series(series_id, req.offset)?;
+            booking_dates.extend(offset_series.iter().flat_map(|(_, occurrence)| occurrence.dates()));
+            let booking_ids = offset_series
+                .iter()
+                .map(|(booking_id, _)| *booking_id)
+                .collect();
+            self.record(Mutation::Offset { booking_ids, offset: req.offset });
+            self.send_monitor_messages(&facility_name, booking_dates);
+            return Ok(ResponseType::SeriesOffset { series_id, occurrences: offset_series.len() as u16 });
+        }
+
+        facility.offset_booking(req.booking_id, req.offset)?;
+        if let Some(booking) = facility.get_booking_details(&req.booking_id)? {
+            booking_dates.extend(booking.dates());
+        }
+        self.record(Mutation::Offset { booking_ids: vec![req.booking_id], offset: req.offset });
+        self.send_monitor_messages(&facility_name, booking_dates);
+        Ok(ResponseType::BookingOffset { booking_id: req.booking_id })
     }
 
     /// Attempts to extend a booking.
     /// 
-    /// If successful, also sends a message to monitoring addresses for updated availability on the affected day.
-    fn handle_extend_request(&mut self, req: ExtendBookingRequest) -> Result<String, String> {
-        for facility in &mut self.facilities {
-            if let Some((_, booking)) = facility.get_booking_details(&req.booking_id) {
-                let booking_day = booking.time().0.day;
-                let facility_name = facility.name.clone();
-
-                facility.extend_booking(
-                    req.booking_id, 
-                    req.extend_hours, 
-                    req.extend_min,
-                )?;
-
-                self.send_monitor_message(&facility_name, booking_day);
-                return Ok(format!("Facility {facility_name} successfully extended"));
+    /// If successful, also sends a message to monitoring addresses for updated availability on the affected days.
+    fn handle_extend_request(&mut self, req: ExtendBookingRequest) -> Result<ResponseType, Error> {
+        let facility = self.find_booking_facility(&req.booking_id)?;
+        let booking = facility.get_booking_details(&req.booking_id)?
+            .ok_or(Error::BookingNotFound { booking_id: req.booking_id })?;
+        let mut booking_dates: Vec<_> = booking.dates().collect();
+        let facility_name = facility.name.clone();
+
+        facility.extend_booking(req.booking_id, req.extension)?;
+        if let Some(booking) = facility.get_booking_details(&req.booking_id)? {
+            booking_dates.extend(booking.dates());
+        }
+        self.record(Mutation::Extend { booking_id: req.booking_id, extension: req.extension });
+
+        self.send_monitor_messages(&facility_name, booking_dates);
+        Ok(ResponseType::BookingExtended { booking_id: req.booking_id })
+    }
+
+    /// Attempts to cancel a booking, or every occurrence of its series if `whole_series` is set.
+    /// 
+    /// If successful, also sends a message to monitoring addresses for updated availability on the affected days.
+    fn handle_cancel_request(&mut self, req: CancelBookingRequest) -> Result<ResponseType, Error> {
+        let facility = self.find_booking_facility(&req.booking_id)?;
+        let booking = facility.get_booking_details(&req.booking_id)?
+            .ok_or(Error::BookingNotFound { booking_id: req.booking_id })?;
+        let booking_dates: Vec<_> = booking.dates().collect();
+        let facility_name = facility.name.clone();
+
+        if let (true, Some(series_id)) = (req.whole_series, booking.series_id()) {
+            let series = facility.remove_series(&series_id)?;
+            for (booking_id, _) in &series {
+                self.booking_facilities.remove(booking_id);
             }
+            let booking_ids = series
+                .iter()
+                .map(|(booking_id, _)| *booking_id)
+                .collect();
+            self.record(Mutation::Cancel { booking_ids });
+            let booking_dates: Vec<_> = series
+                .iter()
+                .flat_map(|(_, occurrence)| occurrence.dates())
+                .collect();
+            self.send_monitor_messages(&facility_name, booking_dates);
+            return Ok(ResponseType::SeriesCancelled { series_id, occurrences: series.len() as u16 });
         }
-        Err(format!("No booking ID {} found in any facility", req.booking_id))
+
+        facility.remove_booking(&req.booking_id)?;
+        self.booking_facilities.remove(&req.booking_id);
+        self.record(Mutation::Cancel { booking_ids: vec![req.booking_id] });
+
+        self.send_monitor_messages(&facility_name, booking_dates);
+        Ok(ResponseType::BookingCancelled { booking_id: req.booking_id })
+    }
+
+    /// Returns the facility the booking is for.
+    /// 
+    /// Errors if the booking ID doesn't exist.
+    fn find_booking_facility(&mut self, booking_id: &BookingId) -> Result<&mut Facility, Error> {
+        let facility_id = self.booking_facilities
+            .get(booking_id)
+            .ok_or(Error::BookingNotFound { booking_id: *booking_id })?;
+        Ok(self.facilities
+            .get_mut(facility_id)
+            .expect("Bookings should only be for current facilities"))
     }
 
-    /// Attempts to cancel a booking.
+    /// Returns the ID of the facility with the name.
     /// 
-    /// If successful, also sends a message to monitoring addresses for updated availability on the affected day.
-    fn handle_cancel_request(&mut self, req: CancelBookingRequest) -> Result<String, String> {
-        for facility in &mut self.facilities {
-            if let Some((_, booking)) = facility.get_booking_details(&req.booking_id) {
-                let booking_day = booking.time().0.day;
-                let facility_name= facility.name.clone();
+    /// Errors if there's no such facility.
+    fn find_facility_id(&self, facility_name: &str) -> Result<FacilityId, Error> {
+        self.facilities
+            .iter()
+            .find(|(_, facility)| facility.name == facility_name)
+            .map(|(&facility_id, _)| facility_id)
+            .ok_or_else(|| Error::FacilityNotFound { facility_name: facility_name.into() })
+    }
 
-                facility.remove_booking(&req.booking_id)?;
+    /// Checks the admin token, then handles the admin action.
+    /// 
+    /// Errors if the server has no admin token, or the request's doesn't match it.
+    /// Tokens are compared in constant time, so how long it takes doesn't tell how much of one matched.
+    fn handle_admin_request(&mut self, req: AdminRequest, source_addr: &SocketAddr) -> Result<ResponseType, Error> {
+        let authorized = self.admin_token
+            .as_ref()
+            .is_some_and(|admin_token| admin_token.as_bytes().ct_eq(req.admin_token.as_bytes()).into());
+        if !authorized {
+            tracing::warn!("Rejected admin request from {source_addr} with a missing or wrong token");
+            return Err(Error::Unauthorized);
+        }
+        tracing::info!("Handling admin request from {source_addr}: {:?}", req.action);
 
-                self.send_monitor_message(&facility_name, booking_day);
-                return Ok(format!("Booking {} successfully cancelled", req.booking_id));
+        match req.action {
+            AdminAction::CreateFacility { facility_name, attributes, turnaround_buffer } => {
+                self.create_facility(facility_name, attributes, turnaround_buffer)
+            },
+            AdminAction::RenameFacility { facility_name, new_name } => {
+                self.rename_facility(facility_name, new_name)
+            },
+            AdminAction::RetireFacility { facility_name, policy } => {
+                self.retire_facility(facility_name, policy)
+            },
+            AdminAction::ListFacilities => {
+                let facilities = self.facilities
+                    .values()
+                    .map(|facility| Ok(FacilitySummary {
+                        facility_name: facility.name.clone(),
+                        attributes: facility.attributes().clone(),
+                        turnaround_buffer: facility.turnaround_buffer(),
+                        bookings: facility.booking_count()? as u32
+                    }))
+                    .collect::<Result<_, StoreError>>()?;
+                Ok(ResponseType::FacilityList { facilities })
+            },
+        }
+    }
+
+    /// Adds a facility, which is always open.
+    /// 
+    /// Errors if the name is empty or taken, it has no seats, or the turnaround buffer is negative.
+    fn create_facility(
+        &mut self,
+        facility_name: String,
+        attributes: FacilityAttributes,
+        turnaround_buffer: Duration
+    ) -> Result<ResponseType, Error> {
+        self.check_new_name(&facility_name)?;
+        if attributes.capacity == 0 {
+            return Err(Error::InvalidSeats { reason: "Facilities must have at least 1 seat".into() });
+        }
+        if turnaround_buffer.is_negative() {
+            return Err(Error::InvalidTime { reason: format!("Turnaround buffer ({turnaround_buffer}) is negative") });
+        }
+
+        let facility = Facility::new(facility_name.clone())
+            .with_attributes(attributes.clone())
+            .with_turnaround_buffer(turnaround_buffer);
+        let facility_id = self.add_facility(facility);
+        self.retired_names.remove(&facility_name);
+        self.record(Mutation::CreateFacility {
+            facility_id,
+            facility_name: facility_name.clone(),
+            attributes,
+            turnaround_buffer
+        });
+        Ok(ResponseType::FacilityCreated { facility_name })
+    }
+
+    /// Renames a facility, keeping its bookings, and moves its monitoring addresses over to the new name.
+    /// 
+    /// Errors if the facility doesn't exist, or the new name is empty or taken.
+    /// 
+    /// If successful, also tells monitoring addresses about the new name.
+    fn rename_facility(&mut self, facility_name: String, new_name: String) -> Result<ResponseType, Error> {
+        let facility_id = self.find_facility_id(&facility_name)?;
+        self.check_new_name(&new_name)?;
+
+        if let Some(facility) = self.facilities.get_mut(&facility_id) {
+            facility.name = new_name.clone();
+        }
+        self.retired_names.remove(&new_name);
+        self.retired_names.insert(facility_name.clone());
+        self.record(Mutation::RenameFacility { facility_id, new_name: new_name.clone() });
+        for monitor in &mut self.monitoring_addresses {
+            if monitor.facility_name == facility_name {
+                monitor.facility_name = new_name.clone();
+            }
+        }
+
+        let response_type = ResponseType::FacilityRenamed { facility_name, new_name: new_name.clone() };
+        self.notify_monitors(&new_name, response_type.clone());
+        Ok(response_type)
+    }
+
+    /// Removes a facility, rejecting, cancelling or moving its bookings by the policy.
+    /// 
+    /// Errors if the facility doesn't exist, it has bookings and the policy is to reject,
+    /// or they can't all be moved to the other facility (as it doesn't exist, is the same one, or is closed or taken
+    /// during any of them).
+    /// 
+    /// If successful, also tells monitoring addresses it was retired and stops them monitoring it,
+    /// and sends a message to those monitoring the facility bookings were moved to for updated availability.
+    fn retire_facility(&mut self, facility_name: String, policy: RetirePolicy) -> Result<ResponseType, Error> {
+        let facility_id = self.find_facility_id(&facility_name)?;
+        let facility = &self.facilities[&facility_id];
+        let bookings = facility.booking_count()? as u32;
+
+        let mut moved_to = None;
+        let (cancelled, moved) = match policy {
+            RetirePolicy::Reject if bookings > 0 => {
+                return Err(Error::FacilityHasBookings { facility_name, bookings });
+            },
+            RetirePolicy::Reject => (0, 0),
+            RetirePolicy::Cancel => (bookings, 0),
+            RetirePolicy::MoveTo { facility_name: target_name } => {
+                let target_id = self.find_facility_id(&target_name)?;
+                if target_id == facility_id {
+                    return Err(Error::Unspecified {
+                        message: format!("Can't move bookings to {target_name}, as it's the facility being retired")
+                    });
+                }
+                let mut moving = facility.bookings()?;
+                moving.sort_by(|(_, a), (_, b)| a.cmp(b));
+                if let Some(target) = self.facilities.get_mut(&target_id) {
+                    target.add_bookings_with_ids(moving.clone())?;
+                }
+                moved_to = Some((target_id, target_name, moving));
+                (0, bookings)
+            }
+        };
+
+        // the facility's store can outlive it (eg. in a database file), so its bookings are taken out of it too
+        self.facilities
+            .get_mut(&facility_id)
+            .expect("Found facility should exist")
+            .remove_all()?;
+        self.facilities.remove(&facility_id);
+        self.booking_facilities.retain(|_, booking_facility_id| *booking_facility_id != facility_id);
+        if let Some((target_id, target_name, moving)) = &moved_to {
+            self.booking_facilities.extend(moving.iter().map(|(booking_id, _)| (*booking_id, *target_id)));
+            let moved_dates: Vec<_> = moving
+                .iter()
+                .flat_map(|(_, booking)| booking.dates())
+                .collect();
+            self.send_monitor_messages(target_name, moved_dates);
+        }
+        self.retired_names.insert(facility_name.clone());
+        self.record(Mutation::RetireFacility { facility_id, moved_to: moved_to.map(|(target_id, ..)| target_id) });
+
+        let response_type = ResponseType::FacilityRetired { facility_name: facility_name.clone(), cancelled, moved };
+        self.notify_monitors(&facility_name, response_type.clone());
+        self.monitoring_addresses.retain(|monitor| monitor.facility_name != facility_name);
+        Ok(response_type)
+    }
+
+    /// Adds the facility with the next ID, keeping its bookings in a store opened from the backend, returning the ID.
+    fn add_facility(&mut self, facility: Facility) -> FacilityId {
+        let facility_id = self.next_facility_id;
+        self.facilities.insert(facility_id, facility.with_store(self.stores.open(facility_id)));
+        self.next_facility_id += 1;
+        facility_id
+    }
+
+    /// Queues the mutation, which has been applied, to be logged to the storage if there is one (see `commit`).
+    fn record(&mut self, mutation: Mutation) {
+        if self.storage.is_some() {
+            self.pending.push(mutation);
+        }
+    }
+
+    /// Logs the queued mutations, the last along with the response to the request which made them if given,
+    /// and commits the request's writes to the booking stores.
+    /// 
+    /// Panics if they can't be logged, as carrying on would acknowledge changes which are lost on restart,
+    /// or the stores can't commit, as they'd miss changes which are logged; they're replayed into them on restart.
+    fn commit(&mut self, response: Option<StoredResponse>) {
+        if let Err(err) = self.try_commit(response) {
+            panic!("Unable to keep change in storage: {err}");
+        }
+    }
+
+    /// Logs the queued mutations, the last along with the response to the request which made them if given,
+    /// then commits the booking stores along with the last one's sequence number, and takes a snapshot if one is due.
+    /// 
+    /// Errors if they can't be logged, or the stores can't commit; failing to take a snapshot is only logged,
+    /// as the mutations are still in the log.
+    fn try_commit(&mut self, mut response: Option<StoredResponse>) -> Result<(), String> {
+        let Some(storage) = &mut self.storage else {
+            return self.stores.commit(None).map_err(|err| err.to_string());
+        };
+        let pending = mem::take(&mut self.pending);
+        let last = pending.len().saturating_sub(1);
+        let appended = !pending.is_empty();
+        for (index, mutation) in pending.into_iter().enumerate() {
+            storage.append(mutation, if index == last { response.take() } else { None })?;
+        }
+        self.stores.commit(Some(storage.sequence())).map_err(|err| err.to_string())?;
+        if !appended || !storage.needs_snapshot() {
+            return Ok(());
+        }
+
+        let sequence = storage.sequence();
+        let snapshot = match self.snapshot(sequence) {
+            Ok(snapshot) => snapshot,
+            Err(err) => {
+                tracing::error!("Unable to take snapshot: {err}");
+                return Ok(());
+            }
+        };
+        if let Some(storage) = &mut self.storage {
+            if let Err(err) = storage.write_snapshot(&snapshot) {
+                tracing::error!("Unable to take snapshot: {err}");
             }
         }
-        Err(format!("No booking with ID {} found", req.booking_id))
+        Ok(())
+    }
+
+    /// Returns the facilities and their bookings, as of the mutation with the sequence number.
+    /// 
+    /// Errors if the bookings can't be read from the stores.
+    fn snapshot(&self, sequence: u64) -> Result<Snapshot, StoreError> {
+        let facilities = self.facilities
+            .iter()
+            .map(|(&facility_id, facility)| Ok(StoredFacility {
+                facility_id,
+                facility_name: facility.name.clone(),
+                attributes: facility.attributes().clone(),
+                turnaround_buffer: facility.turnaround_buffer(),
+                bookings: facility.bookings()?
+            }))
+            .collect::<Result<_, StoreError>>()?;
+        let responses = match self.storage.as_ref().is_some_and(Storage::keeps_responses) {
+            true => self.sender_receiver
+                .cached_responses()
+                .map(|(request, response_bytes, cached)| StoredResponse::new(request, response_bytes.to_vec(), cached))
+                .collect(),
+            false => Vec::new()
+        };
+        Ok(Snapshot {
+            sequence,
+            next_facility_id: self.next_facility_id,
+            facilities,
+            retired_names: self.retired_names.iter().cloned().collect(),
+            responses
+        })
+    }
+
+    /// Applies a logged mutation again, without checking it, as it was checked before being logged.
+    /// 
+    /// Unless `with_bookings` is set, only its changes to facilities are applied, as the stores already have its bookings.
+    /// 
+    /// Errors if it refers to a facility or booking which doesn't exist, so the log doesn't follow on from the snapshot,
+    /// or the stores fail.
+    fn replay(&mut self, mutation: Mutation, with_bookings: bool) -> Result<(), String> {
+        match mutation {
+            Mutation::Book { .. } | Mutation::Offset { .. } | Mutation::Extend { .. } | Mutation::Cancel { .. }
+                if !with_bookings => {},
+            Mutation::Book { facility_id, bookings } => {
+                let facility = self.facilities
+                    .get_mut(&facility_id)
+                    .ok_or_else(|| format!("Facility {facility_id} doesn't exist"))?;
+                for (booking_id, booking) in bookings {
+                    facility.restore_booking(booking_id, booking).map_err(|err| err.to_string())?;
+                    self.booking_facilities.insert(booking_id, facility_id);
+                }
+            },
+            Mutation::Offset { booking_ids, offset } => {
+                for booking_id in booking_ids {
+                    self.replay_change(booking_id, |booking| booking.offset(offset))?;
+                }
+            },
+            Mutation::Extend { booking_id, extension } => {
+                self.replay_change(booking_id, |booking| booking.extend(extension))?;
+            },
+            Mutation::Cancel { booking_ids } => {
+                for booking_id in booking_ids {
+                    self.find_booking_facility(&booking_id)
+                        .and_then(|facility| facility.remove_booking(&booking_id))
+                        .map_err(|err| err.to_string())?;
+                    self.booking_facilities.remove(&booking_id);
+                }
+            },
+            Mutation::CreateFacility { facility_id, facility_name, attributes, turnaround_buffer } => {
+                self.retired_names.remove(&facility_name);
+                let facility = Facility::new(facility_name)
+                    .with_attributes(attributes)
+                    .with_turnaround_buffer(turnaround_buffer)
+                    .with_store(self.stores.open(facility_id));
+                self.facilities.insert(facility_id, facility);
+                self.next_facility_id = self.next_facility_id.max(facility_id + 1);
+            },
+            Mutation::RenameFacility { facility_id, new_name } => {
+                let facility = self.facilities
+                    .get_mut(&facility_id)
+                    .ok_or_else(|| format!("Facility {facility_id} doesn't exist"))?;
+                self.retired_names.remove(&new_name);
+                self.retired_names.insert(mem::replace(&mut facility.name, new_name));
+            },
+            Mutation::RetireFacility { facility_id, moved_to } => {
+                let mut facility = self.facilities
+                    .remove(&facility_id)
+                    .ok_or_else(|| format!("Facility {facility_id} doesn't exist"))?;
+                let removed = match with_bookings {
+                    true => facility.remove_all().map_err(|err| err.to_string())?,
+                    false => Vec::new()
+                };
+                if let Some(target_id) = moved_to {
+                    let target = self.facilities
+                        .get_mut(&target_id)
+                        .ok_or_else(|| format!("Facility {target_id} doesn't exist"))?;
+                    for (booking_id, booking) in removed {
+                        target.restore_booking(booking_id, booking).map_err(|err| err.to_string())?;
+                        self.booking_facilities.insert(booking_id, target_id);
+                    }
+                } else {
+                    for (booking_id, _) in removed {
+                        self.booking_facilities.remove(&booking_id);
+                    }
+                }
+                self.retired_names.insert(facility.name);
+            },
+        }
+        Ok(())
+    }
+
+    /// Changes a booking in place without checking it, as when replaying a mutation.
+    /// 
+    /// Errors if the booking doesn't exist, or the change fails.
+    fn replay_change(
+        &mut self,
+        booking_id: BookingId,
+        change: impl FnOnce(&mut Booking) -> Result<(), Error>
+    ) -> Result<(), String> {
+        let facility = self.find_booking_facility(&booking_id).map_err(|err| err.to_string())?;
+        let mut booking = facility.get_booking_details(&booking_id)
+            .map_err(|err| err.to_string())?
+            .expect("Bookings should be in their facility");
+        change(&mut booking).map_err(|err| err.to_string())?;
+        facility.restore_booking(booking_id, booking).map_err(|err| err.to_string())
+    }
+
+    /// Errors if the name is empty, or a facility already has it.
+    fn check_new_name(&self, facility_name: &str) -> Result<(), Error> {
+        if facility_name.trim().is_empty() {
+            return Err(Error::Unspecified { message: "Facility names can't be empty".into() });
+        }
+        match self.find_facility_id(facility_name) {
+            Ok(_) => Err(Error::FacilityExists { facility_name: facility_name.into() }),
+            Err(_) => Ok(())
+        }
     }
 
     /// Attempts to register a monitoring address.
-    fn handle_monitor_request(&mut self, req: MonitorFacilityRequest, source_addr: &SocketAddr) -> Result<String, String> {
+    fn handle_monitor_request(&mut self, req: MonitorFacilityRequest, source_addr: &SocketAddr, reply_header: Header) -> Result<ResponseType, Error> {
         match self.facilities
-            .iter()
+            .values()
             .find(|&facility| facility.name == req.facility_name)
         {
-            Some(facility) => {
-                let expiry = Utc::now() + Duration::seconds(req.seconds_to_monitor.into());
-                self.monitoring_addresses.push((
-                    source_addr.clone(), 
-                    req.facility_name.clone(), 
-                    expiry
-                ));
-                return Ok(format!("Successfully registered {source_addr} for monitoring facility {}", req.facility_name));
+            Some(_) => {
+                let expiry = Utc::now() + TimeDelta::seconds(req.seconds_to_monitor.into());
+                self.monitoring_addresses.push(MonitoringAddress {
+                    addr: *source_addr,
+                    facility_name: req.facility_name.clone(),
+                    expiry,
+                    reply_header
+                });
+                tracing::debug!("Registered {source_addr} for monitoring facility {}", req.facility_name);
+                Ok(ResponseType::MonitorRegistered {
+                    facility_name: req.facility_name,
+                    seconds_to_monitor: req.seconds_to_monitor
+                })
             },
             None => {
-                return Err(format!("No facility {} found", req.facility_name));
+                Err(Error::FacilityNotFound { facility_name: req.facility_name })
             }
         }
     }
     
-    /// Send a message to all addresses monitoring the given facility, 
-    /// with the availability for the updated day.
+    /// Send a message to all addresses monitoring the given facility for each updated date,
+    /// once per date even if it's given several times.
     /// 
-    /// Also filters out any expired monitoring addresses.
+    /// Does nothing if no address is monitoring the facility, without working out any availability.
+    fn send_monitor_messages(
+        &mut self, 
+        facility_name: &String,
+        updated_dates: impl IntoIterator<Item = NaiveDate>
+    ) {
+        let now = Utc::now();
+        if !self.monitoring_addresses
+            .iter()
+            .any(|monitor| &monitor.facility_name == facility_name && monitor.expiry > now)
+        {
+            return;
+        }
+        for date in updated_dates.into_iter().collect::<BTreeSet<_>>() {
+            self.send_monitor_message(facility_name, date);
+        }
+    }
+
+    /// Send a message to all addresses monitoring the given facility, 
+    /// with the availability for the updated date.
     fn send_monitor_message(
         &mut self, 
         facility_name: &String,
-        updated_day: Day
+        updated_date: NaiveDate
     ) {
+        if let Some(facility) = self.facilities
+            .values()
+            .find(|&f| &f.name == facility_name)
+        {   
+            let slots = match facility.get_availabilities(updated_date) {
+                Ok(slots) => slots,
+                Err(err) => {
+                    tracing::warn!("Not sending monitor message for facility {facility_name} on {updated_date}: {err}");
+                    return;
+                }
+            };
+            let response_type = ResponseType::MonitorUpdate {
+                facility_name: facility_name.clone(),
+                availability: DayAvailability {
+                    date: updated_date,
+                    slots
+                }
+            };
+            self.notify_monitors(facility_name, response_type);
+        }
+    }
+
+    /// Queue a response to all addresses monitoring the given facility, to be sent by `send_updates`.
+    /// Addresses monitoring in protocol versions which don't have the response are skipped.
+    /// 
+    /// Also filters out any expired monitoring addresses.
+    fn notify_monitors(&mut self, facility_name: &String, response_type: ResponseType) {
         let old_len = self.monitoring_addresses.len();
         self.monitoring_addresses
-            .retain(|(_, _, expiry)| expiry > &Utc::now());
+            .retain(|monitor| monitor.expiry > Utc::now());
         tracing::trace!("Evicted {} expired monitoring addresses", old_len - self.monitoring_addresses.len());
 
-        if let Some(facility) = self.facilities
+        tracing::trace!("Sending monitor message for facility {facility_name}");
+
+        let response = RawResponse {
+            request_id: Uuid::new_v4(), // not a response to any request, so never cached
+            response_type
+        };
+
+        let since_version = response.response_type.since_version();
+        let relevant_addresses = self.monitoring_addresses
             .iter()
-            .find(|&f| &f.name == facility_name)
-        {   
-            tracing::trace!("Sending monitor message for facility {facility_name}");
-
-            let availabilities = facility.get_availabilities(updated_day);
-            let monitoring_message = format!("-----\n A booking was updated on {updated_day}; new availabilities:\n {availabilities}\n -----");
-            let response = RawResponse {
-                request_id: Uuid::new_v4(), // doesn't really matter I think
-                is_error: false,
-                message: monitoring_message
-            };
+            .filter(|monitor| &monitor.facility_name == facility_name)
+            .filter(|monitor| {
+                let supported = monitor.reply_header.version >= since_version;
+                if !supported {
+                    tracing::debug!("Skipped {} as its protocol version {} is before {since_version}", monitor.addr, monitor.reply_header.version);
+                }
+                supported
+            })
+            .collect::<Vec<_>>();
 
-            let relevant_addresses = self.monitoring_addresses
-                .iter()
-                .filter(|(_, name, _)| name == facility_name)
-                .collect::<Vec<_>>();
+        tracing::trace!("Found {} addresses monitoring {facility_name}", relevant_addresses.len());
 
-            tracing::trace!("Found {} addresses monitoring MR1", relevant_addresses.len());
+        relevant_addresses
+            .iter()
+            .for_each(|MonitoringAddress { addr, facility_name, expiry, reply_header }| {
+                tracing::debug!("Queued {addr} a monitoring message for facility {facility_name} (expiry: {expiry})");
+                self.updates.push((response.clone(), *addr, *reply_header));
+            });
+    }
 
-            relevant_addresses
-                .iter()
-                .filter(|(_, name, _)| name == facility_name)
-                .for_each(|(addr, facility_name, expiry)| {
-                    match self.sender_receiver.send(&response, &addr) {
-                        Ok(ok) => {
-                            tracing::debug!("Sent {addr} a monitoring message for facility {facility_name} (expiry: {expiry})");
-                        },
-                        Err(err) => {
-                            tracing::warn!("Error while sending monitoring message to {addr}: {err}");
-                        }
-                    }
-                });
+    /// Send the monitor updates queued while handling the current request.
+    fn send_updates(&mut self) {
+        for (response, addr, reply_header) in mem::take(&mut self.updates) {
+            if let Err(err) = self.sender_receiver.send_unsolicited(&response, &addr, reply_header) {
+                tracing::warn!("Error while sending monitoring message to {addr}: {err}");
+            }
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,10 @@
+//! The server's facilities and message handling, shared by the server binary and its benchmarks.
+
+pub mod config;
+pub mod facilities;
+pub mod handler;
+mod log;
+pub mod opening_hours;
+pub mod socket;
+pub mod storage;
+pub mod store;
@@ -1,38 +1,93 @@
-use std::collections::VecDeque;
+use std::{collections::{HashMap, VecDeque}, net::SocketAddr, time::{Duration, Instant}};
 use uuid::Uuid;
 
-const MAX_LOG_LENGTH: usize = 50;
+/// A request, by the address it came from and its ID, as IDs are only unique to each client.
+pub type RequestKey = (SocketAddr, Uuid);
 
-/// Caches previous requests.
+/// Caches the responses to previous requests, so retried requests aren't handled again.
 pub struct Log {
-    log: VecDeque<(Uuid, Vec<u8>)>
+    responses: HashMap<RequestKey, (Vec<u8>, Instant)>,
+    /// Every response's request and when it was inserted, oldest first, for evicting them in order.
+    ///
+    /// Responses inserted again under the same request are also left here under their old time, and skipped on eviction.
+    order: VecDeque<(RequestKey, Instant)>,
+    /// The most responses kept.
+    capacity: usize,
+    /// How long responses are kept for.
+    ttl: Duration
 }
 
 impl Log {
-    pub fn new() -> Self {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
         Self {
-            log: VecDeque::new()
+            responses: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+            ttl
         }
     }
 
+    /// Changes how many responses are kept and for how long, evicting any over the new limits.
+    pub fn set_limits(&mut self, capacity: usize, ttl: Duration) {
+        self.capacity = capacity;
+        self.ttl = ttl;
+        self.evict();
+    }
+
+    /// Returns if responses are kept at all, which they aren't with a capacity of 0.
+    pub fn is_enabled(&self) -> bool {
+        self.capacity > 0
+    }
+
     /// Returns the last response's data for a request.
-    /// 
-    /// Returns `None` if the request wasn't found.
-    pub fn check(&mut self, request_id: &Uuid) -> Option<&Vec<u8>> {
-        self.log
+    ///
+    /// Returns `None` if the request wasn't found, or its response has expired.
+    pub fn check(&mut self, request: &RequestKey) -> Option<&Vec<u8>> {
+        self.evict();
+        self.responses
+            .get(request)
+            .map(|(response, _)| response)
+    }
+
+    /// Inserts a response under the request.
+    ///
+    /// Evicts the oldest responses if the log is over capacity.
+    pub fn insert(&mut self, request: RequestKey, response: &[u8]) {
+        self.insert_at(request, response.to_vec(), Instant::now());
+    }
+
+    /// Inserts a response under the request as of when it was first inserted, eg. when restoring it after a restart.
+    ///
+    /// Responses should be inserted in the order they were first inserted.
+    pub fn insert_at(&mut self, request: RequestKey, response: Vec<u8>, inserted: Instant) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.responses.insert(request, (response, inserted));
+        self.order.push_back((request, inserted));
+        self.evict();
+    }
+
+    /// Returns every response, along with its request and when it was inserted, oldest first.
+    pub fn responses(&self) -> impl Iterator<Item = (RequestKey, &[u8], Instant)> {
+        self.order
             .iter()
-            .find(|(id, _)| id == request_id)
-            .map(|(_, response)| response)
+            .filter_map(|(request, inserted)| match self.responses.get(request) {
+                Some((response, current)) if current == inserted => Some((*request, response.as_slice(), *inserted)),
+                _ => None
+            })
     }
 
-    /// Inserts a response under the request ID.
-    /// 
-    /// Pops the oldest record if the log has reached capacity.
-    pub fn insert(&mut self, request_id: &Uuid, response: &Vec<u8>) {
-        if self.log.len() >= MAX_LOG_LENGTH {
-            self.log.pop_front();
+    /// Evicts expired responses, then the oldest responses until the log is within capacity.
+    fn evict(&mut self) {
+        while let Some((request, inserted)) = self.order.front() {
+            if inserted.elapsed() < self.ttl && self.responses.len() <= self.capacity {
+                break;
+            }
+            if self.responses.get(request).is_some_and(|(_, current)| current == inserted) {
+                self.responses.remove(request);
+            }
+            self.order.pop_front();
         }
-        self.log.push_back((request_id.clone(), response.clone()));
     }
 }
-
@@ -1,41 +1,170 @@
-use std::net::UdpSocket;
+use std::{net::UdpSocket, path::PathBuf, process::ExitCode, sync::mpsc::{self, Sender}};
 use clap::Parser;
-use handler::Handler;
-use socket::SenderReceiver;
-use tracing::Level;
-
-mod facilities;
-mod handler;
-mod log;
-mod socket;
+use server::{config::{Config, ReloadableSettings}, handler::Handler, socket::SenderReceiver, storage::Storage};
+use signal_hook::{consts::SIGHUP, iterator::Signals};
+use tracing_subscriber::{filter::LevelFilter, fmt, layer::SubscriberExt, reload, util::SubscriberInitExt, Registry};
 
 /// The server for the project.
+///
+/// Settings come from the config file (see `server/config.toml` for the defaults), then environment variables,
+/// then these flags.
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// The address to bind to
-    #[arg(short, long, default_value_t = String::from("0.0.0.0:34524"))]
-    addr: String,
-    /// Whether to enable response caching (DEFAULTS TO FALSE)
+    /// The config file to merge over the default config
+    #[arg(short, long)]
+    config: Option<PathBuf>,
+    /// Validate the config and exit, without starting the server
+    #[arg(long)]
+    check_config: bool,
+    /// The address to bind to (overrides network.addr)
+    #[arg(short, long)]
+    addr: Option<String>,
+    /// Whether to enable response caching (overrides reliability.enabled)
     #[arg(short, long)]
     use_reliability: bool,
-    /// The proportion of packets to intentionally drop
-    #[arg(short, long, default_value_t = 0.0)]
-    packet_drop_rate: f64,
+    /// The proportion of packets to intentionally drop (overrides faults.packet_drop_rate)
+    #[arg(short, long)]
+    packet_drop_rate: Option<f64>,
+    /// The token admin requests must give (overrides admin.token)
+    #[arg(long)]
+    admin_token: Option<String>,
+}
+
+impl Args {
+    /// Loads the config, with these flags overriding it.
+    fn load_config(&self) -> Result<Config, String> {
+        let mut config = Config::load(self.config.as_deref())?;
+        if let Some(addr) = &self.addr {
+            config.network.addr = addr.clone();
+        }
+        if self.use_reliability {
+            config.reliability.enabled = true;
+        }
+        if let Some(packet_drop_rate) = self.packet_drop_rate {
+            if !(0.0..=1.0).contains(&packet_drop_rate) {
+                return Err(format!("Packet drop rate must be between 0 and 1 (got {packet_drop_rate})"));
+            }
+            config.faults.packet_drop_rate = packet_drop_rate;
+        }
+        if let Some(admin_token) = &self.admin_token {
+            config.admin.token = Some(admin_token.clone());
+        }
+        Ok(config)
+    }
 }
 
-fn main() {
+fn main() -> ExitCode {
     let args = Args::parse();
 
-    tracing_subscriber::fmt()
-        .with_max_level(Level::TRACE)
+    let config = match args.load_config() {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if args.check_config {
+        println!("Config is valid, with {} facilities", config.facilities.len());
+        return ExitCode::SUCCESS;
+    }
+    let settings = config.reloadable();
+
+    let (level_filter, level_handle) = reload::Layer::new(settings.log_level);
+    tracing_subscriber::registry()
+        .with(level_filter)
+        .with(fmt::layer())
         .init();
 
-    tracing::info!("Server arguments: {args:?}");
+    tracing::info!(
+        "Loaded config from {}: {:?}, {:?}, {:?}",
+        args.config.as_ref().map_or("defaults".into(), |path| path.display().to_string()),
+        config.network,
+        config.reliability,
+        settings.socket
+    );
+    tracing::info!("Admin requests are {}", if settings.admin_token.is_some() { "enabled" } else { "disabled" });
+
+    let socket = UdpSocket::bind(&config.network.addr).unwrap();
+    let sender_receiver = SenderReceiver::new(
+        socket,
+        config.reliability.enabled,
+        config.network.receive_buffer_size,
+        settings.socket
+    ).with_max_message_size(config.network.max_message_size);
+    let facilities = config.facilities().expect("Config should be validated");
+    let (reload_sender, reloads) = mpsc::channel();
+    let mut handler = Handler::new(sender_receiver, facilities).with_reloads(reloads);
+    if let Some(admin_token) = settings.admin_token {
+        handler = handler.with_admin_token(admin_token);
+    }
+    let backend = config.backend();
+    tracing::info!("Keeping bookings in {backend:?}");
+    handler = match handler.with_backend(backend) {
+        Ok(handler) => handler,
+        Err(err) => {
+            tracing::error!("Unable to open booking stores: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    if config.storage.dir.as_os_str().is_empty() {
+        tracing::warn!("No storage directory is set, so changes will be lost when the server stops");
+    } else {
+        let restored = Storage::open(
+            &config.storage.dir,
+            config.storage.snapshot_interval,
+            config.storage.sync,
+            config.dedup.persist
+        )
+            .and_then(|(storage, recovered)| handler.with_storage(storage, recovered));
+        handler = match restored {
+            Ok(handler) => handler,
+            Err(err) => {
+                tracing::error!("Unable to restore from storage: {err}");
+                return ExitCode::FAILURE;
+            }
+        };
+    }
 
-    let socket = UdpSocket::bind(&args.addr).unwrap();
-    let sender_receiver = SenderReceiver::new(socket, args.use_reliability, args.packet_drop_rate);
-    let mut handler = Handler::new(sender_receiver);
+    let mut signals = Signals::new([SIGHUP]).expect("SIGHUP handler should be registrable");
+    std::thread::spawn(move || {
+        for _ in signals.forever() {
+            reload_config(&args, &config, &level_handle, &reload_sender);
+        }
+    });
 
     handler.run();
+    ExitCode::SUCCESS
+}
+
+/// Loads the config again, applying the log level straight away and sending the other reloadable settings to the handler.
+///
+/// Keeps the current settings if the new config is invalid; changes from the running config which need a restart
+/// are logged but ignored.
+fn reload_config(
+    args: &Args,
+    running: &Config,
+    level_handle: &reload::Handle<LevelFilter, Registry>,
+    reload_sender: &Sender<ReloadableSettings>
+) {
+    tracing::info!("Got SIGHUP; reloading config");
+    let new_config = match args.load_config() {
+        Ok(new_config) => new_config,
+        Err(err) => {
+            tracing::error!("Not reloading, as the config is invalid: {err}");
+            return;
+        }
+    };
+    if new_config.needs_restart(running) {
+        tracing::warn!("Changes to the network, reliability, storage, response persistence, opening hours, blackouts or facilities need a restart; ignoring them");
+    }
+
+    let settings = new_config.reloadable();
+    if let Err(err) = level_handle.reload(settings.log_level) {
+        tracing::error!("Unable to change the log level: {err}");
+    }
+    tracing::info!("Reloaded config: log level {}, {:?}", settings.log_level, settings.socket);
+    if reload_sender.send(settings).is_err() {
+        tracing::error!("Handler has stopped; unable to apply reloaded settings");
+    }
 }
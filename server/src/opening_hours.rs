@@ -0,0 +1,75 @@
+use chrono::{Datelike, NaiveDate};
+use shared::time::{Day, Duration, Interval, Time};
+
+/// The hours a facility is open each week.
+///
+/// Each day has windows of time it's open from and until, as durations since midnight;
+/// a window may run until 24:00, so consecutive days can be open overnight.
+#[derive(Debug, Clone)]
+pub struct OpeningHours {
+    /// The windows of each day, indexed by `Day::to_u8`, as half-open `[from, until)`.
+    windows: [Vec<(Duration, Duration)>; 7]
+}
+
+impl OpeningHours {
+    /// Open all day, every day.
+    pub fn always() -> Self {
+        let all_day = vec![(Duration::ZERO, Self::day_length())];
+        Self {
+            windows: std::array::from_fn(|_| all_day.clone())
+        }
+    }
+
+    /// Closed every day, until opened with `with_hours`.
+    pub fn closed() -> Self {
+        Self {
+            windows: Default::default()
+        }
+    }
+
+    /// Opens the facility on the day from and until the given times since midnight.
+    ///
+    /// Panics if they're not in order within the day.
+    pub fn with_hours(mut self, day: Day, from: Duration, until: Duration) -> Self {
+        assert!(
+            Duration::ZERO <= from && from <= until && until <= Self::day_length(),
+            "Opening hours should be in order within the day (got {from} - {until})"
+        );
+        let windows = &mut self.windows[usize::from(day.to_u8())];
+        windows.push((from, until));
+        windows.sort();
+        self
+    }
+
+    /// Opens the facility on each of the days from and until the given times since midnight.
+    ///
+    /// Panics if they're not in order within the day.
+    pub fn with_hours_on(self, days: &[Day], from: Duration, until: Duration) -> Self {
+        days
+            .iter()
+            .fold(self, |hours, day| hours.with_hours(*day, from, until))
+    }
+
+    /// The intervals the facility is open on the date, in order, with overlapping or touching windows joined.
+    pub fn on(&self, date: NaiveDate) -> Vec<Interval> {
+        let midnight = Time::midnight(date);
+        self.windows[usize::from(Day::from(date.weekday()).to_u8())]
+            .iter()
+            .filter_map(|(from, until)| {
+                let interval = Interval::new(midnight.checked_add(*from)?, midnight.checked_add(*until)?)
+                    .expect("Opening hours should be in order");
+                (!interval.is_empty()).then_some(interval)
+            })
+            .fold(Vec::new(), |mut open: Vec<Interval>, interval| {
+                match open.last().and_then(|last| last.union(&interval)) {
+                    Some(joined) => *open.last_mut().unwrap() = joined,
+                    None => open.push(interval)
+                }
+                open
+            })
+    }
+
+    fn day_length() -> Duration {
+        Duration::from_days(1).expect("A day should be in range")
+    }
+}
@@ -1,90 +1,223 @@
-use std::net::{SocketAddr, UdpSocket};
+use std::{mem, net::{SocketAddr, UdpSocket}, time::{Duration, Instant}};
 use rand::{rngs::ThreadRng, Rng};
-use shared::{requests::RawRequest, responses::RawResponse, Byteable};
-use crate::log::Log;
+use shared::{codec::DEFAULT_MAX_LENGTH, error::Error, fragment::{self, FragmentRequest, Reassembler, Received, SentFragments}, protocol::{self, Header, UpgradeRequired, FLAG_UPGRADE_REQUIRED}, requests::RawRequest, responses::{RawResponse, ResponseType}, Byteable, Reader};
+use uuid::Uuid;
+use crate::log::{Log, RequestKey};
 
-const BUF_SIZE: usize = u16::MAX as usize;
-
-/// Wraps the `UdpSocket` and provides serialization and logging mechanisms.
+/// Wraps the `UdpSocket` and provides serialization, fragmentation and logging mechanisms.
 pub struct SenderReceiver {
     socket: UdpSocket,
     log: Log,
+    reassembler: Reassembler,
+    sent_fragments: SentFragments,
     rng: ThreadRng,
     use_reliability: bool,
-    packet_drop_rate: f64
+    /// The largest datagram which can be received, in bytes.
+    receive_buffer_size: usize,
+    /// The largest message which can be received, in bytes, which is also the longest length prefix accepted.
+    max_message_size: usize,
+    packet_drop_rate: f64,
+    send_drop_rate: f64
+}
+
+/// The settings for fault injection and the response log, which can be changed while running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SocketSettings {
+    /// The proportion of received datagrams to intentionally drop.
+    pub packet_drop_rate: f64,
+    /// The proportion of sent datagrams to intentionally drop.
+    pub send_drop_rate: f64,
+    /// The most responses kept in the log.
+    pub log_capacity: usize,
+    /// How long responses are kept in the log.
+    pub log_ttl: Duration
 }
 
 impl SenderReceiver {
-    pub fn new(socket: UdpSocket, use_reliability: bool, packet_drop_rate: f64) -> Self {
+    pub fn new(socket: UdpSocket, use_reliability: bool, receive_buffer_size: usize, settings: SocketSettings) -> Self {
         Self {
             socket,
-            log: Log::new(),
+            log: Log::new(settings.log_capacity, settings.log_ttl),
+            reassembler: Reassembler::new(),
+            sent_fragments: SentFragments::new(),
             rng: rand::rng(),
             use_reliability,
-            packet_drop_rate
+            receive_buffer_size,
+            max_message_size: DEFAULT_MAX_LENGTH,
+            packet_drop_rate: settings.packet_drop_rate,
+            send_drop_rate: settings.send_drop_rate
         }
     }
 
-    /// Attempt to receive a request from the socket.
+    /// Accepts messages up to the size, and strings and lists in them up to that length (`DEFAULT_MAX_LENGTH` otherwise).
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self.reassembler = mem::take(&mut self.reassembler).with_max_message_size(max_message_size);
+        self
+    }
+
+    /// Changes the fault injection and response log settings, evicting any responses over the log's new limits.
+    pub fn apply(&mut self, settings: SocketSettings) {
+        self.packet_drop_rate = settings.packet_drop_rate;
+        self.send_drop_rate = settings.send_drop_rate;
+        self.log.set_limits(settings.log_capacity, settings.log_ttl);
+    }
+
+    /// Attempt to receive a request from the socket, along with its header.
     /// 
     /// If the request's ID and address is found in log, the logd response is sent back
     /// and the function waits for the next message instead.
     /// 
-    /// Errors if there's an issue receiving the message or decoding it into a `RawRequest`.
-    pub fn receive(&mut self) -> Result<(RawRequest, SocketAddr), String> {
-        let mut buf = vec![0; BUF_SIZE];
+    /// If the request's protocol version isn't supported, an `UpgradeRequired` response is sent back
+    /// and the function waits for the next message instead.
+    /// 
+    /// Fragments are held until their message is complete, and requests for lost fragments of
+    /// a response are answered; either way, the function waits for the next message instead.
+    /// 
+    /// If the message's header or checksum is invalid, it's dropped like a lost packet, so the client retries.
+    /// If the body can't be decoded into a `RawRequest`, a `MalformedRequest` error is sent back (if its ID can be read).
+    /// Either way, the function waits for the next message instead.
+    /// 
+    /// Errors if there's an issue receiving the message.
+    pub fn receive(&mut self) -> Result<(RawRequest, SocketAddr, Header), Error> {
+        let mut buf = vec![0; self.receive_buffer_size];
         loop {
             let (size, source_addr) = self.socket
                 .recv_from(&mut buf)
-                .map_err(|err| format!("Failed to receive UDP data: {err}"))?;
+                .map_err(|err| Error::Io { reason: format!("Failed to receive UDP data: {err}") })?;
 
-            let roll = self.rng.random_range(0.0..1.0);
-            if roll < self.packet_drop_rate {
+            if roll(&mut self.rng, self.packet_drop_rate) {
                 tracing::debug!("Intentionally dropping a packet from {source_addr}...");
                 continue;
             }
 
-            let request = RawRequest::from_bytes(&mut buf)?;
+            let message = match self.reassembler.receive(source_addr, &buf[..size]) {
+                Ok(Received::Message(message)) => message,
+                Ok(Received::Incomplete) => continue,
+                Ok(Received::FragmentRequest(request)) => {
+                    self.resend_fragments(&request, &source_addr);
+                    continue;
+                },
+                Err(reason) => {
+                    tracing::debug!("Dropping unreadable datagram from {source_addr}: {reason}");
+                    continue;
+                }
+            };
+            let (header, mut reader) = match protocol::decode_header(&message) {
+                Ok(decoded) => decoded,
+                Err(reason) => {
+                    tracing::debug!("Dropping unreadable message from {source_addr}: {reason}");
+                    continue;
+                }
+            };
+            if !protocol::is_supported_version(header.version) {
+                tracing::debug!("Got unsupported protocol version {} from {source_addr}", header.version);
+                self.send_upgrade_required(&mut reader, &source_addr);
+                continue;
+            }
+
+            reader.set_max_length(self.max_message_size);
+            let body = reader.clone();
+            let request: RawRequest = match protocol::decode_body(&header, &mut reader) {
+                Ok(request) => request,
+                Err(reason) => {
+                    tracing::debug!("Unable to decode request from {source_addr}: {reason}");
+                    self.send_malformed_request(body, &source_addr, header, reason);
+                    continue;
+                }
+            };
             tracing::trace!("Received following message from {source_addr}: {request:?}");
             
             if self.use_reliability {
-                match self.log.check(&request.request_id) {
+                match self.log.check(&(source_addr, request.request_id)) {
                     Some(response) => {
                         tracing::debug!("Found logged response for {}, request ID: {}; returning cached response", source_addr, request.request_id);
-                        if let Err(err) = self.socket.send_to(&response, source_addr) {
+                        let response = response.clone();
+                        if let Err(err) = self.send_message(response, &source_addr) {
                             tracing::warn!("Unable to send UDP message for logged response: {err}");
                         };
                     },
                     None => {
                         tracing::debug!("No logged response for {}, request ID: {}; returning with request", source_addr, request.request_id);
-                        return Ok((request, source_addr));
+                        return Ok((request, source_addr, header));
                     }
                 }
             }
             else {
                 tracing::debug!("Logging turned off; returning with request for {}, request ID: {}", source_addr, request.request_id);
-                return Ok((request, source_addr));
+                return Ok((request, source_addr, header));
             }
         }
     }
 
-    /// Sends the response to the given address.
+    /// Sends the response to the given address, preceded by the given header.
+    /// 
+    /// If the response can't be encoded, an `EncodeError` response is sent instead.
     /// 
     /// If enabled, also adds the response to the internal log.
-    pub fn send(&mut self, response: &RawResponse, addr: &SocketAddr) -> Result<(), String> {
-        let response_bytes = response.clone().to_bytes();
+    pub fn send(&mut self, response: &RawResponse, addr: &SocketAddr, header: Header) -> Result<(), Error> {
+        let response_bytes = self.encode(response, addr, header)?;
+        self.cache(*addr, response.request_id, &response_bytes);
+        self.send_encoded(response_bytes, addr, response)
+    }
+
+    /// Sends a message which isn't a response to a request, eg. a monitor update, to the given address,
+    /// preceded by the given header.
+    /// 
+    /// Unlike `send`, it's never added to the internal log, as no request will be retried for it.
+    pub fn send_unsolicited(&mut self, response: &RawResponse, addr: &SocketAddr, header: Header) -> Result<(), Error> {
+        let response_bytes = self.encode(response, addr, header)?;
+        self.send_encoded(response_bytes, addr, response)
+    }
+
+    /// Encodes the response to the given address, preceded by the given header, to be sent with `send_encoded`.
+    /// 
+    /// If the response can't be encoded, an `EncodeError` response is encoded instead.
+    pub fn encode(&self, response: &RawResponse, addr: &SocketAddr, header: Header) -> Result<Vec<u8>, Error> {
+        match protocol::encode_message(header, response) {
+            Ok(bytes) => Ok(bytes),
+            Err(reason) => {
+                tracing::warn!("Unable to encode response to {addr}: {reason}; sending an error instead");
+                let error_response = RawResponse {
+                    request_id: response.request_id,
+                    response_type: ResponseType::Error(Error::EncodeError { reason })
+                };
+                protocol::encode_message(header, &error_response)
+                    .map_err(|reason| Error::EncodeError { reason })
+            }
+        }
+    }
 
+    /// If enabled, adds the encoded response to the request from the address to the internal log.
+    pub fn cache(&mut self, addr: SocketAddr, request_id: Uuid, response_bytes: &[u8]) {
         if self.use_reliability {
-            let id = response.request_id.clone();
-            self.log.insert(&id, &response_bytes);
-        }   
-
-        match self.socket
-            .send_to(&response_bytes, addr)
-            .map(|bytes| ())
-            .map_err(|err| format!("Unable to send UDP message: {err}"))
-        {
-            Ok(ok) => {
+            self.log.insert((addr, request_id), response_bytes);
+        }
+    }
+
+    /// Returns if responses are added to the internal log, so there's any point keeping them.
+    pub fn caches_responses(&self) -> bool {
+        self.use_reliability && self.log.is_enabled()
+    }
+
+    /// Returns every response in the internal log, along with its request and when it was added, oldest first.
+    pub fn cached_responses(&self) -> impl Iterator<Item = (RequestKey, &[u8], Instant)> {
+        self.log.responses()
+    }
+
+    /// If enabled, adds a response to the internal log as of when it was first added, eg. after a restart.
+    /// 
+    /// Responses should be restored in the order they were first added.
+    pub fn restore_cached(&mut self, request: RequestKey, response_bytes: Vec<u8>, cached: Instant) {
+        if self.use_reliability {
+            self.log.insert_at(request, response_bytes, cached);
+        }
+    }
+
+    /// Sends the response, encoded with `encode`, to the given address.
+    pub fn send_encoded(&mut self, response_bytes: Vec<u8>, addr: &SocketAddr, response: &RawResponse) -> Result<(), Error> {
+        match self.send_message(response_bytes, addr) {
+            Ok(_) => {
                 tracing::debug!("Successfully sent following message to {addr}: {response:?}");
                 Ok(())
             },
@@ -94,4 +227,90 @@ impl SenderReceiver {
             }
         }
     }
-}
\ No newline at end of file
+
+    /// Sends an encoded message to the address, split into fragments if it's too large for one datagram.
+    /// 
+    /// If enabled, also keeps the fragments, so they can be sent again if lost.
+    fn send_message(&mut self, message: Vec<u8>, addr: &SocketAddr) -> Result<(), Error> {
+        let message_id = fragment::message_id(&message);
+        let datagrams = fragment::split(message)
+            .map_err(|reason| Error::EncodeError { reason })?;
+
+        for datagram in &datagrams {
+            if roll(&mut self.rng, self.send_drop_rate) {
+                tracing::debug!("Intentionally dropping a packet to {addr}...");
+                continue;
+            }
+            self.socket
+                .send_to(datagram, addr)
+                .map_err(|err| Error::Io { reason: format!("Unable to send UDP message: {err}") })?;
+        }
+
+        if self.use_reliability && datagrams.len() > 1 {
+            tracing::debug!("Sent message {message_id} to {addr} in {} fragments", datagrams.len());
+            self.sent_fragments.insert(*addr, message_id, datagrams);
+        }
+        Ok(())
+    }
+
+    /// Sends the fragments asked for by a fragment request again, if they're still kept.
+    fn resend_fragments(&mut self, request: &FragmentRequest, addr: &SocketAddr) {
+        let Some(datagrams) = self.sent_fragments.get(*addr, request) else {
+            tracing::debug!("No fragments kept for message {} to {addr}; not resending", request.message_id);
+            return;
+        };
+        tracing::debug!("Resending {} fragments of message {} to {addr}", datagrams.len(), request.message_id);
+        for datagram in datagrams {
+            if roll(&mut self.rng, self.send_drop_rate) {
+                tracing::debug!("Intentionally dropping a resent fragment to {addr}...");
+                continue;
+            }
+            if let Err(err) = self.socket.send_to(datagram, addr) {
+                tracing::warn!("Unable to resend fragment to {addr}: {err}");
+            }
+        }
+    }
+
+    /// Replies to an undecodable request with a `MalformedRequest` error.
+    /// 
+    /// The reader should be positioned at the start of the body;
+    /// if the request ID can't be read from there, no reply is sent.
+    fn send_malformed_request(&mut self, mut reader: Reader<'_>, addr: &SocketAddr, header: Header, reason: String) {
+        let Ok(request_id) = Uuid::decode(&mut reader) else {
+            tracing::debug!("Unable to read request ID from {addr}'s request; not replying");
+            return;
+        };
+        let response = RawResponse {
+            request_id,
+            response_type: ResponseType::Error(Error::MalformedRequest { reason })
+        };
+        if let Err(err) = self.send(&response, addr, header.reply()) {
+            tracing::warn!("Unable to send malformed request error to {addr}: {err}");
+        }
+    }
+
+    /// Replies to a request of an unsupported protocol version with the versions we do support.
+    /// 
+    /// The reader should be positioned just after the header;
+    /// if the request ID can't be read from there, no reply is sent.
+    fn send_upgrade_required(&mut self, reader: &mut Reader<'_>, addr: &SocketAddr) {
+        let Ok(request_id) = Uuid::decode(reader) else {
+            tracing::debug!("Unable to read request ID from {addr}'s request; not replying");
+            return;
+        };
+        let header = Header {
+            version: protocol::PROTOCOL_VERSION,
+            flags: FLAG_UPGRADE_REQUIRED
+        };
+        let response_bytes = protocol::encode_message(header, &UpgradeRequired::new(request_id))
+            .expect("Upgrade required message should always be encodable");
+        if let Err(err) = self.socket.send_to(&response_bytes, addr) {
+            tracing::warn!("Unable to send upgrade required message to {addr}: {err}");
+        }
+    }
+}
+
+/// Returns true with the given probability, for intentionally dropping packets.
+fn roll(rng: &mut ThreadRng, probability: f64) -> bool {
+    rng.random_range(0.0..1.0) < probability
+}
@@ -0,0 +1,398 @@
+use std::{fs::{self, File, OpenOptions}, io::{ErrorKind, Read, Write}, net::SocketAddr, path::{Path, PathBuf}, time::{Instant, SystemTime, UNIX_EPOCH}};
+use derive::ByteableDerive;
+use shared::{facility::FacilityAttributes, protocol::{self, Header, PROTOCOL_VERSION}, time::Duration, Byteable, LengthEncoding};
+use uuid::Uuid;
+use crate::{facilities::{Booking, BookingId, FacilityId}, log::RequestKey};
+
+/// The write-ahead log of mutations since the last snapshot.
+const LOG_FILE: &str = "mutations.log";
+
+const SNAPSHOT_FILE: &str = "snapshot.bin";
+
+/// Where snapshots are written before being renamed over the last one, so a crash mid-write leaves the last one intact.
+const SNAPSHOT_TEMP_FILE: &str = "snapshot.bin.tmp";
+
+/// The length of the prefix before each record in the log.
+const RECORD_LENGTH_LEN: usize = 4;
+
+/// A change to the facilities or their bookings, as logged.
+///
+/// Mutations are logged after they've been checked and applied, so replaying them doesn't check them again.
+#[derive(ByteableDerive, Debug, Clone)]
+pub enum Mutation {
+    /// New bookings, from a single or recurring booking request.
+    Book {
+        facility_id: FacilityId,
+        bookings: Vec<(BookingId, Booking)>
+    },
+    /// Bookings moved by the same offset, eg. every occurrence of a series.
+    Offset {
+        booking_ids: Vec<BookingId>,
+        offset: Duration
+    },
+    Extend {
+        booking_id: BookingId,
+        extension: Duration
+    },
+    Cancel {
+        booking_ids: Vec<BookingId>
+    },
+    CreateFacility {
+        facility_id: FacilityId,
+        facility_name: String,
+        attributes: FacilityAttributes,
+        turnaround_buffer: Duration
+    },
+    RenameFacility {
+        facility_id: FacilityId,
+        new_name: String
+    },
+    /// Its bookings are moved to `moved_to` if given, or cancelled otherwise.
+    RetireFacility {
+        facility_id: FacilityId,
+        moved_to: Option<FacilityId>
+    }
+}
+
+/// A mutation in the log, numbered so those already in the snapshot can be skipped.
+#[derive(ByteableDerive, Debug, Clone)]
+struct Record {
+    sequence: u64,
+    mutation: Mutation,
+    /// The response to the request which made the mutation, logged along with it so the request isn't handled again
+    /// if it's retried after a restart.
+    response: Option<StoredResponse>
+}
+
+/// The facilities and their bookings, as of a mutation.
+#[derive(ByteableDerive, Debug, Clone, Default)]
+pub struct Snapshot {
+    /// The sequence number of the last mutation it includes.
+    pub sequence: u64,
+    pub next_facility_id: FacilityId,
+    pub facilities: Vec<StoredFacility>,
+    /// The names facilities were retired or renamed from, so facilities still listed under them in the config
+    /// aren't created again.
+    pub retired_names: Vec<String>,
+    /// The cached responses, oldest first.
+    pub responses: Vec<StoredResponse>
+}
+
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct StoredFacility {
+    pub facility_id: FacilityId,
+    pub facility_name: String,
+    pub attributes: FacilityAttributes,
+    pub turnaround_buffer: Duration,
+    pub bookings: Vec<(BookingId, Booking)>
+}
+
+/// A cached response, along with the request it's for and when it was cached.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct StoredResponse {
+    pub addr: String,
+    pub request_id: Uuid,
+    /// The encoded response, as it was sent.
+    pub message: Vec<u8>,
+    /// When it was cached, in milliseconds since the Unix epoch, so it can expire across restarts.
+    pub cached_at: u64
+}
+
+impl StoredResponse {
+    /// Stores a response which was cached at the instant.
+    pub fn new((addr, request_id): RequestKey, message: Vec<u8>, cached: Instant) -> Self {
+        let cached_at = SystemTime::now()
+            .checked_sub(cached.elapsed())
+            .and_then(|cached_at| cached_at.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since_epoch| since_epoch.as_millis() as u64);
+        Self {
+            addr: addr.to_string(),
+            request_id,
+            message,
+            cached_at
+        }
+    }
+
+    /// Returns the request the response is for, the response and the instant it was cached at, to cache it again.
+    ///
+    /// Returns `None` if the address can't be read, or it was cached too long ago to be an instant.
+    pub fn restore(self) -> Option<(RequestKey, Vec<u8>, Instant)> {
+        let addr: SocketAddr = self.addr.parse().ok()?;
+        let age = (UNIX_EPOCH + std::time::Duration::from_millis(self.cached_at))
+            .elapsed()
+            .unwrap_or_default(); // cached in the future, if the clock went back
+        let cached = Instant::now().checked_sub(age)?;
+        Some(((addr, self.request_id), self.message, cached))
+    }
+}
+
+/// The state read back when opening the storage, to be restored in order.
+pub struct Recovered {
+    /// The latest snapshot, if one has been written.
+    pub snapshot: Option<Snapshot>,
+    /// The mutations logged after the snapshot, in order.
+    pub mutations: Vec<Mutation>,
+    /// The responses logged along with the mutations, oldest first, if responses are kept.
+    pub responses: Vec<StoredResponse>
+}
+
+/// Keeps mutations durable, in a write-ahead log and periodic snapshots in a directory.
+///
+/// Each record in the log is its length as a big-endian `u32`, then the record encoded as a message
+/// (see `protocol::encode_message`), with a header, varint lengths and a checksum.
+/// A record torn by a crash mid-write fails its checksum or is cut short, and is discarded when the log is opened;
+/// as only the last record can be torn, one failing its checksum before it means the log is corrupt.
+pub struct Storage {
+    dir: PathBuf,
+    log: File,
+    /// The sequence number of the last logged mutation.
+    sequence: u64,
+    /// The number of mutations logged since the last snapshot.
+    since_snapshot: usize,
+    /// How many mutations to log between snapshots, or 0 to never take them.
+    snapshot_interval: usize,
+    /// Whether to flush each mutation to disk before returning, so it survives the machine crashing,
+    /// not just the server.
+    sync: bool,
+    /// Whether to keep the responses logged along with mutations, and the ones in snapshots.
+    keep_responses: bool
+}
+
+impl Storage {
+    /// Opens the storage in the directory, creating it if needed,
+    /// and reads back the latest snapshot and the mutations logged after it.
+    ///
+    /// A torn record at the end of the log is discarded, and cut from the file.
+    /// Responses are only read back if `keep_responses` is set.
+    ///
+    /// Errors if the files can't be read or written, the snapshot is corrupt, a record before the end of the log
+    /// is corrupt, or mutations are missing between the snapshot and the log.
+    pub fn open(dir: &Path, snapshot_interval: usize, sync: bool, keep_responses: bool) -> Result<(Self, Recovered), String> {
+        fs::create_dir_all(dir)
+            .map_err(|err| format!("Unable to create storage directory {}: {err}", dir.display()))?;
+
+        let mut snapshot = read_snapshot(&dir.join(SNAPSHOT_FILE))?;
+        if !keep_responses {
+            if let Some(snapshot) = &mut snapshot {
+                snapshot.responses.clear();
+            }
+        }
+        let mut sequence = snapshot.as_ref().map_or(0, |snapshot| snapshot.sequence);
+
+        let log_path = dir.join(LOG_FILE);
+        let mut log = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&log_path)
+            .map_err(|err| format!("Unable to open {}: {err}", log_path.display()))?;
+        let mut data = Vec::new();
+        log.read_to_end(&mut data)
+            .map_err(|err| format!("Unable to read {}: {err}", log_path.display()))?;
+
+        let (records, valid_len) = read_records(&data)
+            .map_err(|reason| format!("{} is corrupt: {reason}", log_path.display()))?;
+        if valid_len < data.len() {
+            tracing::warn!("Discarding {} bytes torn from the end of {}", data.len() - valid_len, log_path.display());
+            log.set_len(valid_len as u64)
+                .and_then(|_| log.sync_all())
+                .map_err(|err| format!("Unable to truncate {}: {err}", log_path.display()))?;
+        }
+
+        let mut mutations = Vec::new();
+        let mut responses = Vec::new();
+        for record in records {
+            if record.sequence <= sequence { // already in the snapshot, as it crashed before the log was cleared
+                continue;
+            }
+            if record.sequence != sequence + 1 {
+                return Err(format!("Mutations {} to {} are missing from {}", sequence + 1, record.sequence - 1, log_path.display()));
+            }
+            sequence = record.sequence;
+            mutations.push(record.mutation);
+            responses.extend(record.response.filter(|_| keep_responses));
+        }
+        tracing::info!(
+            "Opened storage in {}, with {} and {} mutations logged after it",
+            dir.display(),
+            if snapshot.is_some() { "a snapshot" } else { "no snapshot" },
+            mutations.len()
+        );
+
+        let storage = Self {
+            dir: dir.into(),
+            log,
+            sequence,
+            since_snapshot: mutations.len(),
+            snapshot_interval,
+            sync,
+            keep_responses
+        };
+        Ok((storage, Recovered { snapshot, mutations, responses }))
+    }
+
+    /// The sequence number of the last logged mutation.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Whether responses are kept, so they should be given along with mutations and in snapshots.
+    pub fn keeps_responses(&self) -> bool {
+        self.keep_responses
+    }
+
+    /// Appends the mutation to the log, along with the response to the request which made it if responses are kept,
+    /// flushing it to disk if `sync` is set.
+    ///
+    /// Errors if it can't be encoded or written; a partly written record is discarded when the log is next opened.
+    pub fn append(&mut self, mutation: Mutation, response: Option<StoredResponse>) -> Result<(), String> {
+        let record = Record {
+            sequence: self.sequence + 1,
+            mutation,
+            response: response.filter(|_| self.keep_responses)
+        };
+        let message = protocol::encode_message(header(), &record)?;
+        let len = u32::try_from(message.len())
+            .map_err(|_| format!("Record is too long ({} bytes)", message.len()))?;
+
+        let mut bytes = Vec::with_capacity(RECORD_LENGTH_LEN + message.len());
+        bytes.extend_from_slice(&len.to_be_bytes());
+        bytes.extend_from_slice(&message);
+        self.log
+            .write_all(&bytes)
+            .map_err(|err| format!("Unable to write to {LOG_FILE}: {err}"))?;
+        if self.sync {
+            self.log
+                .sync_data()
+                .map_err(|err| format!("Unable to sync {LOG_FILE}: {err}"))?;
+        }
+
+        self.sequence = record.sequence;
+        self.since_snapshot += 1;
+        Ok(())
+    }
+
+    /// Returns if enough mutations have been logged since the last snapshot to take another.
+    pub fn needs_snapshot(&self) -> bool {
+        self.snapshot_interval > 0 && self.since_snapshot >= self.snapshot_interval
+    }
+
+    /// Replaces the snapshot, then clears the log, as its mutations are all in the snapshot.
+    ///
+    /// The snapshot should be as of the last logged mutation (see `sequence`). It's written to a temporary file
+    /// then renamed over the last one, so a crash at any point leaves either snapshot and the log consistent.
+    ///
+    /// Errors if the files can't be written.
+    pub fn write_snapshot(&mut self, snapshot: &Snapshot) -> Result<(), String> {
+        let bytes = protocol::encode_message(header(), snapshot)?;
+        let temp_path = self.dir.join(SNAPSHOT_TEMP_FILE);
+        let snapshot_path = self.dir.join(SNAPSHOT_FILE);
+        File::create(&temp_path)
+            .and_then(|mut file| {
+                file.write_all(&bytes)?;
+                file.sync_all()
+            })
+            .and_then(|_| fs::rename(&temp_path, &snapshot_path))
+            .and_then(|_| sync_dir(&self.dir))
+            .map_err(|err| format!("Unable to write {}: {err}", snapshot_path.display()))?;
+
+        self.log
+            .set_len(0)
+            .and_then(|_| self.log.sync_all())
+            .map_err(|err| format!("Unable to clear {LOG_FILE}: {err}"))?;
+        self.since_snapshot = 0;
+        tracing::info!("Wrote snapshot as of mutation {} ({} bytes)", snapshot.sequence, bytes.len());
+        Ok(())
+    }
+}
+
+/// The header records and snapshots are encoded with; lengths are varints, as snapshots can have very long lists.
+fn header() -> Header {
+    Header::new(PROTOCOL_VERSION).with_length_encoding(LengthEncoding::Varint)
+}
+
+/// Decodes a record or snapshot, allowing any length prefix which fits in the data,
+/// as snapshots of many bookings can be far over the limit for messages.
+fn decode<T: Byteable>(data: &[u8]) -> Result<T, String> {
+    let (header, mut reader) = protocol::decode_header(data)?;
+    reader.set_max_length(usize::MAX);
+    protocol::decode_body(&header, &mut reader)
+}
+
+/// Reads the snapshot, or `None` if there isn't one yet.
+///
+/// Errors if it can't be read or decoded; it's only ever replaced whole, so it's never torn.
+fn read_snapshot(path: &Path) -> Result<Option<Snapshot>, String> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(format!("Unable to read {}: {err}", path.display()))
+    };
+    decode(&data)
+        .map(Some)
+        .map_err(|err| format!("Snapshot {} is corrupt: {err}", path.display()))
+}
+
+/// Reads records from the log until the end, or a last one which is cut short or doesn't decode, as it was torn
+/// by a crash while being appended.
+///
+/// Returns them along with the length of the data they were read from.
+///
+/// Errors if a record before the last one doesn't decode, or one is cut short but a whole record follows it
+/// (eg. as its length prefix is corrupt), as the log is corrupt rather than torn,
+/// and the records after it would be lost.
+fn read_records(data: &[u8]) -> Result<(Vec<Record>, usize), String> {
+    let mut records = Vec::new();
+    let mut position = 0;
+    while let Some(len_bytes) = data.get(position..position + RECORD_LENGTH_LEN) {
+        let len = u32::from_be_bytes(len_bytes.try_into().expect("Length should be exactly 4 bytes")) as usize;
+        let start = position + RECORD_LENGTH_LEN;
+        let Some(message) = data.get(start..start + len) else {
+            if let Some(next) = find_record(data, position + 1) {
+                return Err(format!(
+                    "Record at byte {position} runs past the end ({len} bytes), but there's another at byte {next}"
+                ));
+            }
+            break;
+        };
+        match decode(message) {
+            Ok(record) => records.push(record),
+            Err(reason) if start + len == data.len() => {
+                tracing::debug!("Stopping at unreadable last record at byte {position} of the log: {reason}");
+                break;
+            },
+            Err(reason) => {
+                return Err(format!(
+                    "Record at byte {position} doesn't decode, with {} bytes after it: {reason}",
+                    data.len() - start - len
+                ));
+            }
+        }
+        position = start + len;
+    }
+    Ok((records, position))
+}
+
+/// Returns where the first whole record at or after the position starts, if any.
+///
+/// Records are only found where their length prefix, header and checksum all line up, so never by chance in practice.
+fn find_record(data: &[u8], from: usize) -> Option<usize> {
+    (from..data.len().saturating_sub(RECORD_LENGTH_LEN)).find(|&position| {
+        let len_bytes = &data[position..position + RECORD_LENGTH_LEN];
+        let len = u32::from_be_bytes(len_bytes.try_into().expect("Length should be exactly 4 bytes")) as usize;
+        let start = position + RECORD_LENGTH_LEN;
+        data.get(start..start + len).is_some_and(|message| decode::<Record>(message).is_ok())
+    })
+}
+
+/// Flushes the directory's entries, so a rename within it survives a crash.
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened as files on other platforms, which don't need them synced after a rename.
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
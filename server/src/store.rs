@@ -0,0 +1,417 @@
+use std::{collections::{BTreeMap, BTreeSet, HashMap}, fmt, fs, path::{Path, PathBuf}, rc::Rc};
+use chrono::{DateTime, NaiveDate};
+use rusqlite::{params, types::Type, Connection, OptionalExtension, Row};
+use shared::{error::Error, time::{Interval, Time}};
+use uuid::Uuid;
+use crate::facilities::{dates, Booking, BookingId, FacilityId, SeriesId};
+
+/// Where a facility's bookings are kept, and how they're looked up.
+///
+/// The facility checks bookings against its rules before adding or changing them, so stores don't check anything.
+/// Stores which can fail, eg. on I/O, return the error, and don't need to undo anything they did first:
+/// the request's writes to every store are rolled back together (see `Stores`).
+pub trait BookingStore {
+    /// Adds the booking, replacing any with the same ID.
+    fn insert(&mut self, booking_id: BookingId, booking: Booking) -> Result<(), StoreError>;
+
+    /// Removes the booking, returning it if it existed.
+    fn remove(&mut self, booking_id: &BookingId) -> Result<Option<Booking>, StoreError>;
+
+    fn get(&self, booking_id: &BookingId) -> Result<Option<Booking>, StoreError>;
+
+    fn count(&self) -> Result<usize, StoreError>;
+
+    /// Returns every booking, in no particular order.
+    fn all(&self) -> Result<Vec<(BookingId, Booking)>, StoreError>;
+
+    /// Returns every occurrence of the series, in order of their intervals.
+    fn series(&self, series_id: &SeriesId) -> Result<Vec<(BookingId, Booking)>, StoreError>;
+
+    /// Returns every booking which overlaps with the interval, once each, in order of their start times then IDs.
+    fn overlapping(&self, interval: &Interval) -> Result<Vec<(BookingId, Booking)>, StoreError>;
+}
+
+/// A store failing, eg. as its database file can't be read or written.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StoreError(pub String);
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<rusqlite::Error> for StoreError {
+    fn from(err: rusqlite::Error) -> Self {
+        Self(format!("Booking database failed: {err}"))
+    }
+}
+
+/// Logs the error, which reaches the client as an unspecified one, without its details.
+impl From<StoreError> for Error {
+    fn from(err: StoreError) -> Self {
+        tracing::error!("{err}");
+        Error::Unspecified { message: "The server failed to access its bookings".into() }
+    }
+}
+
+/// Which kind of store facilities keep their bookings in.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Backend {
+    Memory,
+    /// Every facility's bookings in one SQLite database file, synced to disk on every commit if `sync` is set.
+    Sqlite { path: PathBuf, sync: bool }
+}
+
+impl Backend {
+    /// Connects to the backend, creating its database file (and the directory it's in) if needed.
+    ///
+    /// Errors if the file can't be opened, or isn't a database.
+    pub fn connect(&self) -> Result<Stores, StoreError> {
+        match self {
+            Backend::Memory => Ok(Stores::Memory),
+            Backend::Sqlite { path, sync } => Ok(Stores::Sqlite(SqliteDatabase::open(path, *sync)?))
+        }
+    }
+}
+
+/// A connected backend, which facilities' stores are opened from.
+///
+/// Writes to the stores are made in a transaction, from `begin` until `commit` or `rollback`, so a request which fails
+/// partway doesn't leave some of them made. Backends which keep bookings across restarts also keep the sequence number
+/// of the last mutation (see `storage::Mutation`) committed with them, so only the mutations logged after it need
+/// replaying on startup. Memory stores do neither, as they start empty, and can't fail.
+pub enum Stores {
+    Memory,
+    Sqlite(SqliteDatabase)
+}
+
+impl Stores {
+    /// Opens the store for the facility, with any bookings the backend already has for it.
+    pub fn open(&self, facility_id: FacilityId) -> Box<dyn BookingStore> {
+        match self {
+            Stores::Memory => Box::new(MemoryStore::new()),
+            Stores::Sqlite(database) => Box::new(SqliteStore { connection: Rc::clone(&database.connection), facility_id })
+        }
+    }
+
+    /// Returns the sequence number of the last mutation whose changes to bookings are in the stores,
+    /// or `None` if they don't keep any across restarts.
+    pub fn sequence(&self) -> Option<u64> {
+        match self {
+            Stores::Memory => None,
+            Stores::Sqlite(database) => database.sequence
+        }
+    }
+
+    /// Removes every facility's bookings, along with the sequence number, eg. to restore them from the log instead.
+    pub fn clear(&mut self) -> Result<(), StoreError> {
+        if let Stores::Sqlite(database) = self {
+            database.connection.execute_batch("DELETE FROM bookings; DELETE FROM applied;")?;
+            database.sequence = None;
+        }
+        Ok(())
+    }
+
+    /// Starts a transaction, unless one is already open.
+    pub fn begin(&self) -> Result<(), StoreError> {
+        if let Stores::Sqlite(database) = self {
+            if database.connection.is_autocommit() {
+                database.connection.execute_batch("BEGIN")?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Commits the open transaction, if any, along with the sequence number of the last mutation made in it if given.
+    pub fn commit(&mut self, sequence: Option<u64>) -> Result<(), StoreError> {
+        if let Stores::Sqlite(database) = self {
+            if let Some(sequence) = sequence.filter(|&sequence| database.sequence != Some(sequence)) {
+                database.connection.execute(
+                    "INSERT OR REPLACE INTO applied (id, sequence) VALUES (0, ?1)",
+                    params![sequence]
+                )?;
+            }
+            if !database.connection.is_autocommit() {
+                database.connection.execute_batch("COMMIT")?;
+            }
+            database.sequence = sequence.or(database.sequence);
+        }
+        Ok(())
+    }
+
+    /// Undoes every write since the open transaction started, if any.
+    pub fn rollback(&self) -> Result<(), StoreError> {
+        if let Stores::Sqlite(database) = self {
+            if !database.connection.is_autocommit() {
+                database.connection.execute_batch("ROLLBACK")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Keeps bookings in memory, indexed by the dates they fall on.
+#[derive(Default)]
+pub struct MemoryStore {
+    bookings: HashMap<BookingId, Booking>,
+    /// Every booking's start time and ID, under each date it falls on.
+    ///
+    /// Only the dates an interval falls on need to be searched for bookings overlapping it.
+    by_date: BTreeMap<NaiveDate, BTreeSet<(Time, BookingId)>>
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl BookingStore for MemoryStore {
+    fn insert(&mut self, booking_id: BookingId, booking: Booking) -> Result<(), StoreError> {
+        self.remove(&booking_id)?;
+        for date in booking.dates() {
+            self.by_date
+                .entry(date)
+                .or_default()
+                .insert((booking.interval().start().clone(), booking_id));
+        }
+        self.bookings.insert(booking_id, booking);
+        Ok(())
+    }
+
+    fn remove(&mut self, booking_id: &BookingId) -> Result<Option<Booking>, StoreError> {
+        let Some(booking) = self.bookings.remove(booking_id) else {
+            return Ok(None);
+        };
+        for date in booking.dates() {
+            if let Some(starts) = self.by_date.get_mut(&date) {
+                starts.remove(&(booking.interval().start().clone(), *booking_id));
+                if starts.is_empty() {
+                    self.by_date.remove(&date);
+                }
+            }
+        }
+        Ok(Some(booking))
+    }
+
+    fn get(&self, booking_id: &BookingId) -> Result<Option<Booking>, StoreError> {
+        Ok(self.bookings.get(booking_id).cloned())
+    }
+
+    fn count(&self) -> Result<usize, StoreError> {
+        Ok(self.bookings.len())
+    }
+
+    fn all(&self) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        Ok(
+            self.bookings
+                .iter()
+                .map(|(booking_id, booking)| (*booking_id, booking.clone()))
+                .collect()
+        )
+    }
+
+    fn series(&self, series_id: &SeriesId) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        let mut series: Vec<_> = self.bookings
+            .iter()
+            .filter(|(_, booking)| booking.series_id() == Some(*series_id))
+            .map(|(booking_id, booking)| (*booking_id, booking.clone()))
+            .collect();
+        series.sort_by(|(_, a), (_, b)| a.interval().cmp(b.interval()));
+        Ok(series)
+    }
+
+    fn overlapping(&self, interval: &Interval) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        let first_date = interval.start().date;
+        Ok(
+            dates(interval)
+                .flat_map(|date| {
+                    self.by_date
+                        .get(&date)
+                        .into_iter()
+                        .flat_map(|starts| starts.range(..(interval.end().clone(), Uuid::nil()))) // starting before the end
+                        .filter(move |(start, _)| start.date.max(first_date) == date) // bookings over several dates are under each
+                })
+                .map(|(_, booking_id)| (*booking_id, &self.bookings[booking_id]))
+                .filter(|(_, booking)| booking.interval().overlaps(interval))
+                .map(|(booking_id, booking)| (booking_id, booking.clone()))
+                .collect()
+        )
+    }
+}
+
+/// A SQLite database file which every facility's `SqliteStore` shares a connection to.
+///
+/// It's durable, keeping the bookings along with the sequence number of the last mutation committed with them,
+/// so on startup they're loaded from it, and only the mutations logged after that are replayed into it
+/// (see `handler::Handler::with_storage`).
+pub struct SqliteDatabase {
+    connection: Rc<Connection>,
+    /// The sequence number last committed, if any.
+    sequence: Option<u64>
+}
+
+impl SqliteDatabase {
+    /// Opens the database file, creating it and its tables if needed.
+    ///
+    /// Errors if the file can't be opened, or isn't a database.
+    fn open(path: &Path, sync: bool) -> Result<Self, StoreError> {
+        let open_error = |err: &dyn fmt::Display| StoreError(format!("Unable to open booking database {}: {err}", path.display()));
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir).map_err(|err| open_error(&err))?;
+        }
+        let open = || -> rusqlite::Result<_> {
+            let connection = Connection::open(path)?;
+            // without syncing, a commit can still be lost if the machine crashes, but not if just the server does
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.pragma_update(None, "synchronous", if sync { "FULL" } else { "NORMAL" })?;
+            connection.execute_batch(
+                "CREATE TABLE IF NOT EXISTS bookings (
+                    facility_id INTEGER NOT NULL,
+                    booking_id BLOB NOT NULL,
+                    start INTEGER NOT NULL,
+                    end INTEGER NOT NULL,
+                    seats INTEGER NOT NULL,
+                    series_id BLOB,
+                    PRIMARY KEY (facility_id, booking_id)
+                );
+                CREATE INDEX IF NOT EXISTS bookings_by_start ON bookings (facility_id, start);
+                CREATE TABLE IF NOT EXISTS applied (
+                    id INTEGER PRIMARY KEY CHECK (id = 0),
+                    sequence INTEGER NOT NULL
+                );"
+            )?;
+            let sequence = connection
+                .query_row("SELECT sequence FROM applied WHERE id = 0", [], |row| row.get(0))
+                .optional()?;
+            Ok((connection, sequence))
+        };
+        let (connection, sequence) = open().map_err(|err| open_error(&err))?;
+        Ok(Self { connection: Rc::new(connection), sequence })
+    }
+}
+
+/// Keeps a facility's bookings in a table of a SQLite database file, shared with other facilities' stores.
+///
+/// Only one store should be open for each facility. Times are stored as minutes since the Unix epoch,
+/// so intervals can be compared and indexed.
+pub struct SqliteStore {
+    connection: Rc<Connection>,
+    facility_id: FacilityId
+}
+
+impl SqliteStore {
+    /// Runs the query for this facility's bookings, which must select the columns `read_booking` expects,
+    /// and take the facility ID as `?1`.
+    fn query(&self, sql: &str, params: &[&dyn rusqlite::ToSql]) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        let mut statement = self.connection.prepare_cached(sql)?;
+        let rows = statement.query_map(params, read_booking)?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    }
+}
+
+/// The columns `read_booking` reads, in order.
+const COLUMNS: &str = "booking_id, start, end, seats, series_id";
+
+impl BookingStore for SqliteStore {
+    fn insert(&mut self, booking_id: BookingId, booking: Booking) -> Result<(), StoreError> {
+        let interval = booking.interval();
+        self.connection.execute(
+            "INSERT OR REPLACE INTO bookings (facility_id, booking_id, start, end, seats, series_id)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                self.facility_id,
+                booking_id,
+                to_minutes(interval.start()),
+                to_minutes(interval.end()),
+                booking.seats(),
+                booking.series_id()
+            ]
+        )?;
+        Ok(())
+    }
+
+    fn remove(&mut self, booking_id: &BookingId) -> Result<Option<Booking>, StoreError> {
+        let Some(booking) = self.get(booking_id)? else {
+            return Ok(None);
+        };
+        self.connection.execute(
+            "DELETE FROM bookings WHERE facility_id = ?1 AND booking_id = ?2",
+            params![self.facility_id, booking_id]
+        )?;
+        Ok(Some(booking))
+    }
+
+    fn get(&self, booking_id: &BookingId) -> Result<Option<Booking>, StoreError> {
+        let sql = format!("SELECT {COLUMNS} FROM bookings WHERE facility_id = ?1 AND booking_id = ?2");
+        let mut statement = self.connection.prepare_cached(&sql)?;
+        let booking = statement
+            .query_row(params![self.facility_id, booking_id], read_booking)
+            .optional()?;
+        Ok(booking.map(|(_, booking)| booking))
+    }
+
+    fn count(&self) -> Result<usize, StoreError> {
+        Ok(self.connection.query_row(
+            "SELECT COUNT(*) FROM bookings WHERE facility_id = ?1",
+            params![self.facility_id],
+            |row| row.get(0)
+        )?)
+    }
+
+    fn all(&self) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        self.query(&format!("SELECT {COLUMNS} FROM bookings WHERE facility_id = ?1"), params![self.facility_id])
+    }
+
+    fn series(&self, series_id: &SeriesId) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        self.query(
+            &format!("SELECT {COLUMNS} FROM bookings WHERE facility_id = ?1 AND series_id = ?2 ORDER BY start, end"),
+            params![self.facility_id, series_id]
+        )
+    }
+
+    fn overlapping(&self, interval: &Interval) -> Result<Vec<(BookingId, Booking)>, StoreError> {
+        self.query(
+            &format!(
+                "SELECT {COLUMNS} FROM bookings WHERE facility_id = ?1 AND start < ?2 AND ?3 < end ORDER BY start, booking_id"
+            ),
+            params![self.facility_id, to_minutes(interval.end()), to_minutes(interval.start())]
+        )
+    }
+}
+
+/// Reads a booking from a row of the columns in `COLUMNS`.
+///
+/// Errors if it's not a valid booking, eg. as the file was changed by something else.
+fn read_booking(row: &Row<'_>) -> rusqlite::Result<(BookingId, Booking)> {
+    let booking_id = row.get("booking_id")?;
+    let start = from_minutes(row.get("start")?)?;
+    let end = from_minutes(row.get("end")?)?;
+    let mut booking = Interval::new(start, end)
+        .map_err(invalid_booking)
+        .and_then(|interval| Booking::new(interval).map_err(|err| invalid_booking(err.to_string())))?
+        .with_seats(row.get("seats")?)
+        .map_err(|err| invalid_booking(err.to_string()))?;
+    if let Some(series_id) = row.get("series_id")? {
+        booking = booking.in_series(series_id);
+    }
+    Ok((booking_id, booking))
+}
+
+fn invalid_booking(reason: String) -> rusqlite::Error {
+    rusqlite::Error::FromSqlConversionFailure(0, Type::Integer, format!("Invalid stored booking: {reason}").into())
+}
+
+fn to_minutes(time: &Time) -> i64 {
+    let datetime = time.to_datetime().expect("Times should have a valid hour and minute, as they're checked when decoded");
+    datetime.and_utc().timestamp() / 60
+}
+
+fn from_minutes(minutes: i64) -> rusqlite::Result<Time> {
+    let datetime = minutes
+        .checked_mul(60)
+        .and_then(|seconds| DateTime::from_timestamp(seconds, 0))
+        .ok_or_else(|| invalid_booking(format!("time {minutes} is out of range")))?;
+    Ok(Time::from_datetime(datetime.naive_utc()))
+}
@@ -0,0 +1,58 @@
+//! Checks bookings can't run for over `MAX_BOOKING_DAYS`, however they're made or changed.
+
+use chrono::NaiveDate;
+use server::facilities::{Booking, Facility, MAX_BOOKING_DAYS};
+use shared::{error::Error, time::{Duration, Interval, Time}};
+use uuid::Uuid;
+
+/// An interval starting at midnight on 2026-01-01, running for the minutes.
+fn interval(minutes: i32) -> Interval {
+    let start = Time::midnight(NaiveDate::from_ymd_opt(2026, 1, 1).unwrap());
+    let end = start.checked_add(Duration::from_minutes(minutes)).unwrap();
+    Interval::new(start, end).unwrap()
+}
+
+const DAY: i32 = 24 * 60;
+
+#[test]
+fn longest_booking_is_allowed() {
+    assert!(Booking::new(interval(MAX_BOOKING_DAYS as i32 * DAY + 23 * 60)).is_ok());
+}
+
+#[test]
+fn over_long_booking_is_rejected() {
+    let result = Booking::new(interval((MAX_BOOKING_DAYS as i32 + 1) * DAY));
+    assert!(matches!(result, Err(Error::InvalidTime { .. })), "{result:?}");
+
+    // eg. thousands of years, which would otherwise be indexed under every date
+    let result = Booking::new(interval(i32::MAX / 2));
+    assert!(matches!(result, Err(Error::InvalidTime { .. })), "{result:?}");
+}
+
+#[test]
+fn over_long_extension_is_rejected() {
+    let mut booking = Booking::new(interval(60)).unwrap();
+    let result = booking.extend(Duration::from_minutes((MAX_BOOKING_DAYS as i32 + 1) * DAY));
+    assert!(matches!(result, Err(Error::InvalidTime { .. })), "{result:?}");
+    assert_eq!(booking, Booking::new(interval(60)).unwrap(), "booking changed despite the error");
+}
+
+#[test]
+fn offset_past_the_longest_is_rejected() {
+    // ends at 23:30 on the last date it can, so moving it an hour later runs it onto the next
+    let mut booking = Booking::new(interval(MAX_BOOKING_DAYS as i32 * DAY + 23 * 60 + 30)).unwrap();
+    let before = booking.clone();
+    let result = booking.offset(Duration::from_minutes(60));
+    assert!(matches!(result, Err(Error::InvalidTime { .. })), "{result:?}");
+    assert_eq!(booking, before, "booking changed despite the error");
+}
+
+#[test]
+fn facility_rejects_over_long_extension() {
+    let mut facility = Facility::new("LONG".into());
+    let booking_id = Uuid::new_v4();
+    facility.add_booking_with_id(booking_id, Booking::new(interval(60)).unwrap()).unwrap();
+    let result = facility.extend_booking(booking_id, Duration::from_minutes(1000 * 365 * DAY));
+    assert!(matches!(result, Err(Error::InvalidTime { .. })), "{result:?}");
+    assert_eq!(facility.booking_count().unwrap(), 1);
+}
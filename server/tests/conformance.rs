@@ -0,0 +1,332 @@
+//! Checks every booking store backend behaves the same, with one suite of checks each must pass.
+//!
+//! Each check runs against a store opened from every backend (the SQLite one in a temporary file of its own), then
+//! a random workload of booking, offsetting, extending and cancelling runs through a facility on each backend, whose
+//! results and final availability must match the in-memory backend's exactly.
+//!
+//! New backends should be added to `backends!`, so every check runs against them too.
+
+use std::path::{Path, PathBuf};
+use chrono::NaiveDate;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use server::{facilities::{Booking, BookingId, Facility}, store::{Backend, Stores}};
+use shared::time::{Duration, Interval, Time};
+use uuid::Uuid;
+
+/// The number of operations in the random workload.
+const WORKLOAD_OPERATIONS: usize = 500;
+
+/// The seed of the random workload, so failures can be reproduced.
+const WORKLOAD_SEED: u64 = 4051;
+
+/// Adds a module of tests for each backend, with a test for each check run against a fresh backend of that kind.
+macro_rules! backends {
+    ($($backend:ident: $open:expr;)*) => {
+        $(
+            mod $backend {
+                use super::*;
+
+                #[test]
+                fn starts_empty() {
+                    super::starts_empty(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn gets_inserted() {
+                    super::gets_inserted(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn insert_replaces_the_same_id() {
+                    super::insert_replaces_the_same_id(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn removes() {
+                    super::removes(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn lists_every_booking() {
+                    super::lists_every_booking(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn lists_a_series_in_order() {
+                    super::lists_a_series_in_order(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn finds_overlapping_in_order() {
+                    super::finds_overlapping_in_order(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn finds_bookings_over_several_days_once() {
+                    super::finds_bookings_over_several_days_once(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn keeps_facilities_apart() {
+                    super::keeps_facilities_apart(&$open(stringify!($backend)));
+                }
+
+                #[test]
+                fn random_workload_matches_memory() {
+                    assert_eq!(workload(&$open(stringify!($backend))), workload(&Stores::Memory));
+                }
+            }
+        )*
+    };
+}
+
+backends! {
+    memory: |_| TestBackend(Stores::Memory, None);
+    sqlite: sqlite;
+}
+
+/// A connected backend, along with a temporary directory for its files which is removed once it's dropped.
+struct TestBackend(Stores, Option<PathBuf>);
+
+impl std::ops::Deref for TestBackend {
+    type Target = Stores;
+
+    fn deref(&self) -> &Stores {
+        &self.0
+    }
+}
+
+impl Drop for TestBackend {
+    fn drop(&mut self) {
+        if let Some(dir) = &self.1 {
+            let _ = std::fs::remove_dir_all(dir);
+        }
+    }
+}
+
+/// A SQLite backend in a new temporary directory, as tests run in parallel.
+fn sqlite(backend_name: &str) -> TestBackend {
+    let dir = std::env::temp_dir().join(format!("conformance-{backend_name}-{}", Uuid::new_v4()));
+    TestBackend(sqlite_in(&dir).connect().expect("Database should open"), Some(dir))
+}
+
+/// The SQLite backend with its database file in the directory, which is created when it's connected to.
+fn sqlite_in(dir: &Path) -> Backend {
+    Backend::Sqlite { path: dir.join("bookings.sqlite3"), sync: false }
+}
+
+/// SQLite stores keep committed bookings, along with the sequence number they were committed with,
+/// so they're loaded again when the database is connected to again, eg. after a restart.
+#[test]
+fn sqlite_keeps_committed_bookings() {
+    let backend = sqlite("sqlite-reconnected");
+    let dir = backend.1.clone().unwrap();
+    let (booking_id, booking) = (Uuid::new_v4(), booking(0, 9 * 60, 60).with_seats(2).unwrap());
+    let mut stores = sqlite_in(&dir).connect().unwrap();
+    stores.begin().unwrap();
+    stores.open(3).insert(booking_id, booking.clone()).unwrap();
+    stores.commit(Some(7)).unwrap();
+    drop(stores);
+
+    let stores = sqlite_in(&dir).connect().unwrap();
+    assert_eq!(stores.sequence(), Some(7));
+    assert_eq!(stores.open(3).all().unwrap(), vec![(booking_id, booking)]);
+    assert_eq!(stores.open(0).count().unwrap(), 0, "another facility");
+}
+
+/// Rolling back undoes every store's writes since the transaction began, so a request which fails partway
+/// doesn't leave some of them made.
+#[test]
+fn sqlite_rolls_back_writes() {
+    let backend = sqlite("sqlite-rolled-back");
+    let kept_id = Uuid::new_v4();
+    let (mut first, mut second) = (backend.open(0), backend.open(1));
+    first.insert(kept_id, booking(0, 9 * 60, 60)).unwrap();
+
+    backend.begin().unwrap();
+    first.remove(&kept_id).unwrap();
+    second.insert(Uuid::new_v4(), booking(0, 9 * 60, 60)).unwrap();
+    backend.rollback().unwrap();
+
+    assert_eq!(ids(first.all().unwrap()), vec![kept_id]);
+    assert_eq!(second.count().unwrap(), 0);
+}
+
+fn starts_empty(stores: &Stores) {
+    let store = stores.open(0);
+    assert_eq!(store.count().unwrap(), 0);
+    assert_eq!(store.all().unwrap(), vec![]);
+    assert_eq!(store.get(&Uuid::new_v4()).unwrap(), None);
+}
+
+fn gets_inserted(stores: &Stores) {
+    let mut store = stores.open(0);
+    let (booking_id, booking) = (Uuid::new_v4(), booking(0, 9 * 60, 60).with_seats(3).unwrap());
+    store.insert(booking_id, booking.clone()).unwrap();
+    assert_eq!(store.count().unwrap(), 1);
+    assert_eq!(store.get(&booking_id).unwrap(), Some(booking));
+}
+
+fn insert_replaces_the_same_id(stores: &Stores) {
+    let mut store = stores.open(0);
+    let booking_id = Uuid::new_v4();
+    store.insert(booking_id, booking(0, 9 * 60, 60)).unwrap();
+    let moved = booking(1, 14 * 60, 90);
+    store.insert(booking_id, moved.clone()).unwrap();
+    assert_eq!(store.count().unwrap(), 1);
+    assert_eq!(store.get(&booking_id).unwrap(), Some(moved));
+    assert_eq!(store.overlapping(booking(0, 0, 24 * 60).interval()).unwrap(), vec![], "overlapping the old time");
+}
+
+fn removes(stores: &Stores) {
+    let mut store = stores.open(0);
+    let (kept_id, removed_id) = (Uuid::new_v4(), Uuid::new_v4());
+    store.insert(kept_id, booking(0, 9 * 60, 60)).unwrap();
+    store.insert(removed_id, booking(0, 11 * 60, 60)).unwrap();
+    assert_eq!(store.remove(&removed_id).unwrap(), Some(booking(0, 11 * 60, 60)));
+    assert_eq!(store.remove(&removed_id).unwrap(), None, "removed again");
+    assert_eq!(store.get(&removed_id).unwrap(), None);
+    assert_eq!(store.count().unwrap(), 1);
+    assert_eq!(ids(store.overlapping(booking(0, 0, 24 * 60).interval()).unwrap()), vec![kept_id]);
+}
+
+fn lists_every_booking(stores: &Stores) {
+    let mut store = stores.open(0);
+    let mut bookings: Vec<_> = (0..20)
+        .map(|n| (Uuid::new_v4(), booking(n % 3, n * 45, 30)))
+        .collect();
+    for (booking_id, booking) in &bookings {
+        store.insert(*booking_id, booking.clone()).unwrap();
+    }
+    let mut all = store.all().unwrap();
+    all.sort();
+    bookings.sort();
+    assert_eq!(all, bookings);
+}
+
+fn lists_a_series_in_order(stores: &Stores) {
+    let mut store = stores.open(0);
+    let series_id = Uuid::new_v4();
+    let occurrences: Vec<_> = [14, 0, 7]
+        .into_iter()
+        .map(|day| (Uuid::new_v4(), booking(day, 10 * 60, 60).in_series(series_id)))
+        .collect();
+    for (booking_id, occurrence) in &occurrences {
+        store.insert(*booking_id, occurrence.clone()).unwrap();
+    }
+    store.insert(Uuid::new_v4(), booking(3, 10 * 60, 60)).unwrap();
+    store.insert(Uuid::new_v4(), booking(4, 10 * 60, 60).in_series(Uuid::new_v4())).unwrap();
+
+    let mut expected = occurrences;
+    expected.sort_by(|(_, a), (_, b)| a.interval().cmp(b.interval()));
+    assert_eq!(store.series(&series_id).unwrap(), expected);
+    assert_eq!(store.series(&Uuid::new_v4()).unwrap(), vec![], "unknown series");
+}
+
+fn finds_overlapping_in_order(stores: &Stores) {
+    let mut store = stores.open(0);
+    let before = (Uuid::new_v4(), booking(0, 8 * 60, 60)); // ends as the search starts
+    let across_start = (Uuid::new_v4(), booking(0, 8 * 60 + 30, 60));
+    let inside = (Uuid::new_v4(), booking(0, 9 * 60 + 30, 30));
+    let same_start = (Uuid::new_v4(), booking(0, 9 * 60 + 30, 60));
+    let after = (Uuid::new_v4(), booking(0, 11 * 60, 60)); // starts as the search ends
+    let other_day = (Uuid::new_v4(), booking(1, 9 * 60, 60));
+    for (booking_id, booking) in [&before, &across_start, &inside, &same_start, &after, &other_day] {
+        store.insert(*booking_id, booking.clone()).unwrap();
+    }
+
+    let mut expected = vec![across_start, inside, same_start];
+    expected.sort_by(|(a_id, a), (b_id, b)| (a.interval().start(), a_id).cmp(&(b.interval().start(), b_id)));
+    assert_eq!(store.overlapping(booking(0, 9 * 60, 2 * 60).interval()).unwrap(), expected);
+    assert_eq!(store.overlapping(booking(5, 0, 24 * 60).interval()).unwrap(), vec![], "overlapping an empty day");
+}
+
+fn finds_bookings_over_several_days_once(stores: &Stores) {
+    let mut store = stores.open(0);
+    let long = (Uuid::new_v4(), booking(0, 20 * 60, 3 * 24 * 60)); // from day 0 to day 3
+    let short = (Uuid::new_v4(), booking(2, 12 * 60, 60));
+    store.insert(long.0, long.1.clone()).unwrap();
+    store.insert(short.0, short.1.clone()).unwrap();
+    assert_eq!(store.overlapping(booking(1, 0, 2 * 24 * 60).interval()).unwrap(), vec![long.clone(), short], "overlapping days 1 to 2");
+    assert_eq!(ids(store.overlapping(booking(3, 0, 24 * 60).interval()).unwrap()), vec![long.0], "overlapping day 3");
+}
+
+fn keeps_facilities_apart(stores: &Stores) {
+    let mut first = stores.open(0);
+    let second = stores.open(1);
+    let booking_id = Uuid::new_v4();
+    first.insert(booking_id, booking(0, 9 * 60, 60)).unwrap();
+    assert_eq!(second.count().unwrap(), 0);
+    assert_eq!(second.get(&booking_id).unwrap(), None);
+    assert_eq!(second.overlapping(booking(0, 0, 24 * 60).interval()).unwrap(), vec![]);
+}
+
+/// Runs random operations through a facility on the backend, returning a line describing the result of each,
+/// then the availability of each day.
+///
+/// Booking IDs come from the seeded generator too, so conflicts between bookings starting at the same time
+/// (which are reported in order of their IDs) are the same on every run.
+fn workload(stores: &Stores) -> Vec<String> {
+    let store = stores.open(0);
+    let mut facility = Facility::new("WORKLOAD".into())
+        .with_capacity(3)
+        .with_turnaround_buffer(Duration::from_minutes(15))
+        .with_store(store);
+    let mut rng = StdRng::seed_from_u64(WORKLOAD_SEED);
+    let mut booking_ids: Vec<BookingId> = Vec::new();
+    let mut lines = Vec::new();
+
+    for _ in 0..WORKLOAD_OPERATIONS {
+        let existing = (!booking_ids.is_empty()).then(|| booking_ids[rng.random_range(0..booking_ids.len())]);
+        let line = match (rng.random_range(0..10), existing) {
+            (0..=4, _) | (_, None) => {
+                let new_booking = booking(rng.random_range(0..5), rng.random_range(0..48) * 30, rng.random_range(1..8) * 30)
+                    .with_seats(rng.random_range(1..=3))
+                    .unwrap();
+                let booking_id = Uuid::from_u128(rng.random());
+                match facility.add_booking_with_id(booking_id, new_booking) {
+                    Ok(()) => {
+                        booking_ids.push(booking_id);
+                        format!("book {booking_id}: ok")
+                    },
+                    Err(err) => format!("book {booking_id}: {err}")
+                }
+            },
+            (5..=6, Some(booking_id)) => {
+                let offset = Duration::from_minutes(rng.random_range(-6..=6) * 30);
+                format!("offset {booking_id} by {offset}: {:?}", facility.offset_booking(booking_id, offset))
+            },
+            (7..=8, Some(booking_id)) => {
+                let extension = Duration::from_minutes(rng.random_range(-3..=6) * 30);
+                format!("extend {booking_id} by {extension}: {:?}", facility.extend_booking(booking_id, extension))
+            },
+            (_, Some(booking_id)) => {
+                booking_ids.retain(|id| *id != booking_id);
+                format!("cancel {booking_id}: {:?}", facility.remove_booking(&booking_id))
+            }
+        };
+        lines.push(line);
+    }
+    for day in 0..6 {
+        let date = booking(day, 0, 60).interval().start().date;
+        lines.push(format!("{date}: {:?}", facility.get_availabilities(date).unwrap()));
+    }
+    lines.push(format!("{} bookings", facility.booking_count().unwrap()));
+    lines
+}
+
+/// A booking for 1 seat on the day (counting from 2026-01-01), starting at the minute of the day.
+fn booking(day: i32, start_minute: i32, minutes: i32) -> Booking {
+    let start = Time::midnight(NaiveDate::from_ymd_opt(2026, 1, 1).unwrap())
+        .checked_add(Duration::from_minutes(day * 24 * 60 + start_minute))
+        .unwrap();
+    let end = start.checked_add(Duration::from_minutes(minutes)).unwrap();
+    Booking::new(Interval::new(start, end).unwrap()).unwrap()
+}
+
+fn ids(bookings: Vec<(BookingId, Booking)>) -> Vec<BookingId> {
+    bookings
+        .into_iter()
+        .map(|(booking_id, _)| booking_id)
+        .collect()
+}
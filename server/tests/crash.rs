@@ -0,0 +1,376 @@
+//! Checks that bookings the server acknowledged survive it being killed, at random points while it's handling requests,
+//! and that the log is only cut short when its last record was torn.
+//!
+//! The server's handler runs with storage in a child process (this test binary again, running the ignored `child`
+//! test), which is killed with SIGKILL after a random delay while slots are booked one after another. It keeps
+//! bookings in memory, or in the SQLite backend, which they're then loaded from on restart.
+
+use std::{
+    collections::HashSet,
+    fs::{self, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::{SocketAddr, UdpSocket},
+    path::{Path, PathBuf},
+    process::{Child, Command, Stdio},
+    thread,
+    time::Duration as StdDuration
+};
+use chrono::NaiveDate;
+use rand::Rng;
+use server::{facilities::{BookingId, Facility}, handler::Handler, socket::{SenderReceiver, SocketSettings}, storage::{Mutation, Storage}, store::Backend};
+use shared::{protocol::{self, Header, PROTOCOL_VERSION}, requests::{BookRequest, RawRequest, RequestType}, responses::{RawResponse, ResponseType}, time::{Duration, Interval, Time}};
+use uuid::Uuid;
+
+/// The number of times the child is started and killed.
+const ROUNDS: usize = 20;
+
+/// How many mutations the child logs between snapshots; low, so some kills land while one is being written.
+const SNAPSHOT_INTERVAL: usize = 25;
+
+/// The range of delays, in milliseconds, after which the child is killed.
+const KILL_AFTER_MS: std::ops::Range<u64> = 20..300;
+
+/// The single-seat facility the child starts with, which the slots are booked in.
+const FACILITY: &str = "CRASH";
+
+/// The most responses the child caches.
+const CACHE_CAPACITY: usize = 100;
+
+/// A torn record: its length prefix says 64 bytes, but only a few follow.
+const TORN_RECORD: [u8; 7] = [0, 0, 0, 64, b'B', b'K', PROTOCOL_VERSION];
+
+/// The environment variable giving the child its storage directory.
+const CHILD_DIR: &str = "CRASH_TEST_CHILD_DIR";
+
+/// The environment variable which, if set, has the child keep bookings in the SQLite backend.
+const CHILD_SQLITE: &str = "CRASH_TEST_CHILD_SQLITE";
+
+/// Runs the handler with storage in the directory on a free local port, printing the port once it's restored.
+///
+/// Only does anything when started by `spawn_child`.
+#[test]
+#[ignore = "run as a child process by the other tests"]
+fn child() -> Result<(), String> {
+    let Ok(dir) = std::env::var(CHILD_DIR) else {
+        return Ok(());
+    };
+    let socket = UdpSocket::bind("127.0.0.1:0").map_err(|err| err.to_string())?;
+    let port = socket.local_addr().map_err(|err| err.to_string())?.port();
+    let settings = SocketSettings {
+        packet_drop_rate: 0.0,
+        send_drop_rate: 0.0,
+        log_capacity: CACHE_CAPACITY,
+        log_ttl: StdDuration::from_secs(300)
+    };
+    let sender_receiver = SenderReceiver::new(socket, true, usize::from(u16::MAX), settings);
+    let mut handler = Handler::new(sender_receiver, vec![Facility::new(FACILITY.into())]);
+    if std::env::var_os(CHILD_SQLITE).is_some() {
+        handler = handler.with_backend(Backend::Sqlite { path: Path::new(&dir).join("bookings.sqlite3"), sync: true })?;
+    }
+    let (storage, recovered) = Storage::open(Path::new(&dir), SNAPSHOT_INTERVAL, true, true)?;
+    let mut handler = handler.with_storage(storage, recovered)?;
+
+    println!("port {port}");
+    std::io::stdout().flush().map_err(|err| err.to_string())?;
+    handler.run();
+    Ok(())
+}
+
+#[test]
+fn acknowledged_bookings_survive_kills() -> Result<(), String> {
+    survive_kills("kills", false)
+}
+
+#[test]
+fn acknowledged_bookings_survive_kills_with_sqlite() -> Result<(), String> {
+    survive_kills("kills-sqlite", true)
+}
+
+/// Kills the child at random points over several rounds, each restarting from what the last one left,
+/// checking every acknowledged booking is stored after each, and that a restarted server still has them all.
+fn survive_kills(test: &str, sqlite: bool) -> Result<(), String> {
+    let dir = temp_dir(test);
+    let mut acknowledged = Vec::new();
+    let mut next_slot = 0;
+    for round in 1..=ROUNDS {
+        let (child, addr) = spawn_child(&dir, sqlite)?;
+        let kill_after = StdDuration::from_millis(rand::rng().random_range(KILL_AFTER_MS));
+        let killer = thread::spawn(move || kill_later(child, kill_after));
+
+        let socket = client_socket()?;
+        loop {
+            let slot = next_slot;
+            next_slot += 1;
+            match book(&socket, addr, slot, Uuid::new_v4())? {
+                Some(ResponseType::BookingCreated { booking_id }) => acknowledged.push((booking_id, slot)),
+                Some(other) => return Err(format!("Round {round}: unexpected reply booking slot {slot}: {other}")),
+                None => break // killed
+            }
+        }
+        killer.join().map_err(|_| "Killer thread panicked")??;
+
+        let stored = stored_booking_ids(&dir)?;
+        if let Some((booking_id, slot)) = acknowledged.iter().find(|(booking_id, _)| !stored.contains(booking_id)) {
+            return Err(format!("Round {round}: acknowledged booking {booking_id} (slot {slot}) was lost"));
+        }
+    }
+
+    let (mut child, addr) = spawn_child(&dir, sqlite)?;
+    let restored = check_restored(addr, &acknowledged);
+    child.kill().and_then(|_| child.wait()).map_err(|err| err.to_string())?;
+    restored?;
+    fs::remove_dir_all(&dir).map_err(|err| err.to_string())
+}
+
+/// Books slots with the server keeping bookings in memory, then in the SQLite backend, then in memory again,
+/// checking each restart still has every booking made before it, as the SQLite database is first filled from
+/// the storage, then the storage is kept up to date alongside it.
+#[test]
+fn bookings_survive_switching_backends() -> Result<(), String> {
+    let dir = temp_dir("switching");
+    let socket = client_socket()?;
+    let mut acknowledged = Vec::new();
+    for (round, sqlite) in [false, true, false, true].into_iter().enumerate() {
+        let (mut child, addr) = spawn_child(&dir, sqlite)?;
+        let mut booked = || {
+            check_restored(addr, &acknowledged)?;
+            for slot in (0..5).map(|n| round as i32 * 5 + n) {
+                match book(&socket, addr, slot, Uuid::new_v4())? {
+                    Some(ResponseType::BookingCreated { booking_id }) => acknowledged.push((booking_id, slot)),
+                    other => return Err(format!("Round {round}: unexpected reply booking slot {slot}: {other:?}"))
+                }
+            }
+            Ok(())
+        };
+        let result = booked();
+        child.kill().and_then(|_| child.wait()).map_err(|err| err.to_string())?;
+        result?;
+    }
+    fs::remove_dir_all(&dir).map_err(|err| err.to_string())
+}
+
+/// Books a slot, kills the child straight after it replies, then checks retrying the request from the same address
+/// after restarting it gets the same reply, rather than the slot being taken (by the first attempt).
+#[test]
+fn retried_request_gets_original_reply_after_restart() -> Result<(), String> {
+    let dir = temp_dir("retry");
+    let socket = client_socket()?;
+    let request_id = Uuid::new_v4();
+    let (mut child, addr) = spawn_child(&dir, false)?;
+    let first = book(&socket, addr, 0, request_id)?;
+    child.kill().and_then(|_| child.wait()).map_err(|err| err.to_string())?;
+    let Some(ResponseType::BookingCreated { booking_id }) = first else {
+        return Err(format!("Unexpected reply booking slot 0: {first:?}"));
+    };
+
+    let (mut child, addr) = spawn_child(&dir, false)?;
+    let retried = book(&socket, addr, 0, request_id)?;
+    child.kill().and_then(|_| child.wait()).map_err(|err| err.to_string())?;
+    match retried {
+        Some(ResponseType::BookingCreated { booking_id: retried_id }) if retried_id == booking_id => {
+            fs::remove_dir_all(&dir).map_err(|err| err.to_string())
+        },
+        other => Err(format!("Retried booking request got {other:?}, not booking {booking_id} again"))
+    }
+}
+
+/// Appends a torn record to the log, then checks opening the storage cuts it off and keeps every mutation.
+#[test]
+fn torn_record_is_discarded() -> Result<(), String> {
+    let dir = temp_dir("torn");
+    let log_path = write_log(&dir, 3)?;
+    let len = fs::metadata(&log_path).map_err(|err| err.to_string())?.len();
+    OpenOptions::new()
+        .append(true)
+        .open(&log_path)
+        .and_then(|mut log| log.write_all(&TORN_RECORD))
+        .map_err(|err| err.to_string())?;
+
+    let (_, recovered) = Storage::open(&dir, SNAPSHOT_INTERVAL, false, false)?;
+    assert_eq!(recovered.mutations.len(), 3);
+    assert_eq!(fs::metadata(&log_path).map_err(|err| err.to_string())?.len(), len);
+    fs::remove_dir_all(&dir).map_err(|err| err.to_string())
+}
+
+/// Corrupts the last record without changing its length, then checks opening the storage discards it like a torn one.
+#[test]
+fn corrupt_last_record_is_discarded() -> Result<(), String> {
+    let dir = temp_dir("corrupt-last");
+    let log_path = write_log(&dir, 3)?;
+    let mut data = fs::read(&log_path).map_err(|err| err.to_string())?;
+    let last = record_starts(&data)[2];
+    data[last + 4] = 0; // the first magic byte of its header
+    fs::write(&log_path, &data).map_err(|err| err.to_string())?;
+
+    let (_, recovered) = Storage::open(&dir, SNAPSHOT_INTERVAL, false, false)?;
+    assert_eq!(recovered.mutations.len(), 2);
+    assert_eq!(fs::metadata(&log_path).map_err(|err| err.to_string())?.len(), last as u64);
+    fs::remove_dir_all(&dir).map_err(|err| err.to_string())
+}
+
+/// Corrupts a record in the middle of the log, then checks opening the storage fails, leaving the log as it was,
+/// rather than discarding the records after it.
+#[test]
+fn corrupt_record_before_the_end_is_refused() -> Result<(), String> {
+    let dir = temp_dir("corrupt-middle");
+    let log_path = write_log(&dir, 3)?;
+    let mut data = fs::read(&log_path).map_err(|err| err.to_string())?;
+    let middle = record_starts(&data)[1];
+    data[middle + 4] = 0;
+    fs::write(&log_path, &data).map_err(|err| err.to_string())?;
+
+    assert!(Storage::open(&dir, SNAPSHOT_INTERVAL, false, false).is_err());
+    assert_eq!(fs::read(&log_path).map_err(|err| err.to_string())?, data);
+    fs::remove_dir_all(&dir).map_err(|err| err.to_string())
+}
+
+/// Corrupts the length prefix of a record in the middle of the log so it seems to run past the end, then checks
+/// opening the storage fails, leaving the log as it was, rather than taking it as torn and discarding the records
+/// after it.
+#[test]
+fn corrupt_length_before_the_end_is_refused() -> Result<(), String> {
+    let dir = temp_dir("corrupt-length");
+    let log_path = write_log(&dir, 3)?;
+    let mut data = fs::read(&log_path).map_err(|err| err.to_string())?;
+    let middle = record_starts(&data)[1];
+    data[middle..middle + 4].copy_from_slice(&u32::MAX.to_be_bytes());
+    fs::write(&log_path, &data).map_err(|err| err.to_string())?;
+
+    assert!(Storage::open(&dir, SNAPSHOT_INTERVAL, false, false).is_err());
+    assert_eq!(fs::read(&log_path).map_err(|err| err.to_string())?, data);
+    fs::remove_dir_all(&dir).map_err(|err| err.to_string())
+}
+
+/// A fresh directory for a test's storage.
+fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("crash-test-{test}-{}", std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    dir
+}
+
+/// Logs the given number of mutations to storage in the directory, returning the log's path.
+fn write_log(dir: &Path, mutations: usize) -> Result<PathBuf, String> {
+    let (mut storage, _) = Storage::open(dir, 0, false, false)?;
+    for _ in 0..mutations {
+        storage.append(Mutation::Cancel { booking_ids: vec![Uuid::new_v4()] }, None)?;
+    }
+    let path = dir.join("mutations.log");
+    match path.exists() {
+        true => Ok(path),
+        false => Err(format!("{} doesn't exist", path.display()))
+    }
+}
+
+/// Where each record in the log starts, at its length prefix.
+fn record_starts(data: &[u8]) -> Vec<usize> {
+    let mut starts = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        starts.push(position);
+        let len = u32::from_be_bytes(data[position..position + 4].try_into().unwrap()) as usize;
+        position += 4 + len;
+    }
+    starts
+}
+
+/// Starts the child, keeping bookings in the SQLite backend if `sqlite` is set, returning it along with its address
+/// once it's restored from storage.
+///
+/// Errors if it exits instead, eg. as it couldn't restore.
+fn spawn_child(dir: &Path, sqlite: bool) -> Result<(Child, SocketAddr), String> {
+    let exe = std::env::current_exe().map_err(|err| err.to_string())?;
+    let mut command = Command::new(exe);
+    command
+        .args(["child", "--exact", "--ignored", "--nocapture"])
+        .env(CHILD_DIR, dir);
+    if sqlite {
+        command.env(CHILD_SQLITE, "1");
+    }
+    let mut child = command
+        .stdout(Stdio::piped())
+        .spawn()
+        .map_err(|err| format!("Unable to start child: {err}"))?;
+
+    let stdout = child.stdout.take().ok_or("Child should have a stdout")?;
+    let mut lines = BufReader::new(stdout).lines();
+    let port: u16 = loop {
+        match lines.next() {
+            // after the test harness's own output, on the same line
+            Some(Ok(line)) => if let Some((_, port)) = line.split_once("port ") {
+                break port.parse().map_err(|err| format!("Child printed a bad port {port}: {err}"))?;
+            },
+            _ => return Err(format!("Child failed to start: {:?}", child.wait()))
+        }
+    };
+    Ok((child, SocketAddr::from(([127, 0, 0, 1], port))))
+}
+
+fn kill_later(mut child: Child, delay: StdDuration) -> Result<(), String> {
+    thread::sleep(delay);
+    child.kill().and_then(|_| child.wait()).map_err(|err| format!("Unable to kill child: {err}"))?;
+    Ok(())
+}
+
+/// Checks the server has every booking, as booking their slots again is refused.
+fn check_restored(addr: SocketAddr, bookings: &[(BookingId, i32)]) -> Result<(), String> {
+    let socket = client_socket()?;
+    for (booking_id, slot) in bookings {
+        match book(&socket, addr, *slot, Uuid::new_v4())? {
+            Some(ResponseType::Error(_)) => {}, // taken, by the restored booking
+            other => return Err(format!("Restarted server didn't restore booking {booking_id} (slot {slot}): got {other:?}"))
+        }
+    }
+    Ok(())
+}
+
+fn client_socket() -> Result<UdpSocket, String> {
+    let socket = UdpSocket::bind("127.0.0.1:0").map_err(|err| err.to_string())?;
+    socket.set_read_timeout(Some(StdDuration::from_millis(500))).map_err(|err| err.to_string())?;
+    Ok(socket)
+}
+
+/// Books the slot with a request of the ID, returning the reply, or `None` if there's none before the timeout.
+fn book(socket: &UdpSocket, addr: SocketAddr, slot: i32, request_id: Uuid) -> Result<Option<ResponseType>, String> {
+    let request = RawRequest {
+        request_id,
+        request_type: RequestType::Book(BookRequest { facility_name: FACILITY.into(), interval: interval(slot), seats: 1 })
+    };
+    let bytes = protocol::encode_message(Header::new(PROTOCOL_VERSION), &request)?;
+    socket.send_to(&bytes, addr).map_err(|err| err.to_string())?;
+
+    let mut buf = vec![0; usize::from(u16::MAX)];
+    let Ok(size) = socket.recv(&mut buf) else {
+        return Ok(None);
+    };
+    let response: RawResponse = protocol::decode_header(&buf[..size])
+        .and_then(|(header, mut reader)| protocol::decode_body(&header, &mut reader))?;
+    if response.request_id != request.request_id {
+        return Err(format!("Reply is for request {}, not {}", response.request_id, request.request_id));
+    }
+    Ok(Some(response.response_type))
+}
+
+/// The interval of the nth slot, which is an hour long, starting every 2 hours from 2026-01-01.
+fn interval(slot: i32) -> Interval {
+    let start = Time::midnight(NaiveDate::from_ymd_opt(2026, 1, 1).unwrap())
+        .checked_add(Duration::from_minutes(slot * 120))
+        .unwrap();
+    let end = start.checked_add(Duration::from_minutes(60)).unwrap();
+    Interval::new(start, end).unwrap()
+}
+
+/// Opens the storage, returning the IDs of every booking in its snapshot or booked by its logged mutations.
+fn stored_booking_ids(dir: &Path) -> Result<HashSet<BookingId>, String> {
+    let (_, recovered) = Storage::open(dir, SNAPSHOT_INTERVAL, false, false)?;
+    let mut booking_ids: HashSet<_> = recovered.snapshot
+        .iter()
+        .flat_map(|snapshot| &snapshot.facilities)
+        .flat_map(|facility| facility.bookings.iter().map(|(booking_id, _)| *booking_id))
+        .collect();
+    for mutation in recovered.mutations {
+        if let Mutation::Book { bookings, .. } = mutation {
+            booking_ids.extend(bookings.into_iter().map(|(booking_id, _)| booking_id));
+        }
+    }
+    Ok(booking_ids)
+}
@@ -0,0 +1,323 @@
+//! Generates or checks the golden wire encodings in `shared/fixtures/messages.txt`.
+//!
+//! Each line of the fixture file is `<name> <hex bytes>`, with a fixed message for every
+//! `RawRequest`/`RawResponse` variant and fragmentation message, so implementations in other languages can check their encoders against it.
+//! Messages include their header and checksum, encoded at the current protocol version.
+//!
+//! ```sh
+//! cargo run -p shared --example golden            # regenerate
+//! cargo run -p shared --example golden -- --check # verify, exiting with an error on any mismatch
+//! ```
+
+use std::{fs, path::PathBuf, process::ExitCode};
+use shared::{error::Error, facility::{FacilityAttributes, FacilitySummary}, fragment::{Fragment, FragmentRequest}, protocol::*, recurrence::*, requests::*, responses::*, time::*, Byteable, LengthEncoding};
+use chrono::NaiveDate;
+use uuid::Uuid;
+
+const REQUEST_ID: Uuid = Uuid::from_u128(0x0011_2233_4455_6677_8899_aabb_ccdd_eeff);
+const BOOKING_ID: Uuid = Uuid::from_u128(0xffee_ddcc_bbaa_9988_7766_5544_3322_1100);
+
+fn main() -> ExitCode {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("fixtures/messages.txt");
+    let check = std::env::args().any(|arg| arg == "--check");
+
+    let mut expected = String::from("# Golden wire encodings; regenerate with `cargo run -p shared --example golden`.\n");
+    expected.push_str("# <name> <hex bytes>\n");
+    for (name, bytes) in fixtures() {
+        expected.push_str(&format!("{name} {}\n", hex(&bytes)));
+    }
+
+    if !check {
+        fs::write(&path, expected).expect("Should be able to write fixtures");
+        println!("Wrote fixtures to {}", path.display());
+        return ExitCode::SUCCESS;
+    }
+
+    let actual = fs::read_to_string(&path).unwrap_or_default();
+    if actual != expected {
+        eprintln!("Fixtures at {} are out of date; the wire format has changed", path.display());
+        return ExitCode::FAILURE;
+    }
+    println!("Fixtures match");
+    ExitCode::SUCCESS
+}
+
+/// A fixed message for every variant, along with its encoding.
+///
+/// Also checks that each encoding decodes back to the same bytes.
+fn fixtures() -> Vec<(&'static str, Vec<u8>)> {
+    let requests = [
+        ("request.availability", RequestType::Availability(AvailabilityRequest {
+            facility_name: "MR1".into(),
+            start_date: date(10),
+            end_date: date(16),
+            days: vec![Day::Monday, Day::Sunday]
+        })),
+        ("request.book", RequestType::Book(BookRequest {
+            facility_name: "MR1".into(),
+            interval: interval(time(11, 9, 30), time(11, 11, 0)),
+            seats: 2
+        })),
+        ("request.offset", RequestType::Offset(OffsetBookingRequest {
+            booking_id: BOOKING_ID,
+            offset: Duration::from_minutes(-75),
+            whole_series: false
+        })),
+        ("request.monitor", RequestType::Monitor(MonitorFacilityRequest {
+            facility_name: "MR1".into(),
+            seconds_to_monitor: 60
+        })),
+        ("request.cancel", RequestType::Cancel(CancelBookingRequest {
+            booking_id: BOOKING_ID,
+            whole_series: true
+        })),
+        ("request.extend", RequestType::Extend(ExtendBookingRequest {
+            booking_id: BOOKING_ID,
+            extension: Duration::from_minutes(45)
+        })),
+        ("request.recurring_book", RequestType::RecurringBook(RecurringBookRequest {
+            facility_name: "MR1".into(),
+            interval: interval(time(10, 9, 0), time(10, 10, 0)),
+            rule: RecurrenceRule {
+                days: vec![Day::Monday, Day::Wednesday],
+                interval_weeks: 2,
+                end: RecurrenceEnd::Count(6)
+            },
+            seats: 1
+        })),
+        ("request.search_facilities", RequestType::SearchFacilities(SearchFacilitiesRequest {
+            min_capacity: 4,
+            building: Some("North".into()),
+            floor: None,
+            equipment: vec!["projector".into()],
+            accessible: true,
+            free_during: Some(interval(time(11, 9, 0), time(11, 10, 0)))
+        })),
+        ("request.admin.create_facility", admin(AdminAction::CreateFacility {
+            facility_name: "MR6".into(),
+            attributes: lab_attributes(),
+            turnaround_buffer: Duration::from_minutes(15)
+        })),
+        ("request.admin.rename_facility", admin(AdminAction::RenameFacility {
+            facility_name: "MR6".into(),
+            new_name: "LAB2".into()
+        })),
+        ("request.admin.retire_facility", admin(AdminAction::RetireFacility {
+            facility_name: "LAB2".into(),
+            policy: RetirePolicy::MoveTo { facility_name: "LAB1".into() }
+        })),
+        ("request.admin.list_facilities", admin(AdminAction::ListFacilities)),
+    ];
+    let slots = vec![
+        Slot { interval: interval(time(10, 0, 0), time(10, 9, 0)), free_seats: 1 },
+        Slot { interval: interval(time(10, 10, 0), time(11, 0, 0)), free_seats: 3 }
+    ];
+    let availability = DayAvailability { date: date(10), slots };
+    let responses = [
+        ("response.availability", ResponseType::Availability {
+            facility_name: "MR1".into(),
+            availabilities: vec![availability.clone()],
+            turnaround_buffer: Duration::from_minutes(15)
+        }),
+        ("response.booking_created", ResponseType::BookingCreated { booking_id: BOOKING_ID }),
+        ("response.booking_offset", ResponseType::BookingOffset { booking_id: BOOKING_ID }),
+        ("response.monitor_registered", ResponseType::MonitorRegistered {
+            facility_name: "MR1".into(),
+            seconds_to_monitor: 60
+        }),
+        ("response.booking_cancelled", ResponseType::BookingCancelled { booking_id: BOOKING_ID }),
+        ("response.booking_extended", ResponseType::BookingExtended { booking_id: BOOKING_ID }),
+        ("response.monitor_update", ResponseType::MonitorUpdate {
+            facility_name: "MR1".into(),
+            availability
+        }),
+        ("response.text", ResponseType::Text { message: "Booking cancelled".into() }),
+        ("response.recurring_booking_created", ResponseType::RecurringBookingCreated {
+            series_id: REQUEST_ID,
+            occurrences: vec![
+                (BOOKING_ID, interval(time(10, 9, 0), time(10, 10, 0))),
+                (REQUEST_ID, interval(time(12, 9, 0), time(12, 10, 0)))
+            ]
+        }),
+        ("response.series_offset", ResponseType::SeriesOffset { series_id: REQUEST_ID, occurrences: 6 }),
+        ("response.series_cancelled", ResponseType::SeriesCancelled { series_id: REQUEST_ID, occurrences: 6 }),
+        ("response.search_results", ResponseType::SearchResults {
+            facilities: vec![("LAB1".into(), lab_attributes())]
+        }),
+        ("response.facility_created", ResponseType::FacilityCreated { facility_name: "MR6".into() }),
+        ("response.facility_renamed", ResponseType::FacilityRenamed {
+            facility_name: "MR6".into(),
+            new_name: "LAB2".into()
+        }),
+        ("response.facility_retired", ResponseType::FacilityRetired {
+            facility_name: "LAB2".into(),
+            cancelled: 0,
+            moved: 4
+        }),
+        ("response.facility_list", ResponseType::FacilityList {
+            facilities: vec![FacilitySummary {
+                facility_name: "LAB1".into(),
+                attributes: lab_attributes(),
+                turnaround_buffer: Duration::from_minutes(15),
+                bookings: 4
+            }]
+        }),
+    ];
+    // older protocol versions which are still supported;
+    // weekdays in requests before version 5 decode to their next occurrence, but re-encode the same
+    let legacy_requests = [
+        ("request.v4.availability", 4, requests[0].1.clone()),
+        ("request.v4.book", 4, requests[1].1.clone()),
+        ("request.v5.cancel", 5, RequestType::Cancel(CancelBookingRequest { booking_id: BOOKING_ID, whole_series: false })),
+        ("request.v6.offset", 6, requests[2].1.clone()),
+        ("request.v6.extend", 6, requests[5].1.clone()),
+        ("request.v8.book", 8, requests[1].1.clone()),
+    ];
+    let legacy_responses = [
+        ("response.v1.ok", 1, ResponseType::Text { message: "Booking cancelled".into() }),
+        ("response.v1.error", 1, ResponseType::Error(Error::Unspecified { message: "No such facility found".into() })),
+        ("response.v2.booking_created", 2, ResponseType::BookingCreated { booking_id: BOOKING_ID }),
+        ("response.v7.availability", 7, responses[0].1.clone()),
+        ("response.v8.availability", 8, responses[0].1.clone()),
+    ];
+
+    let errors = [
+        ("response.error.unspecified", Error::Unspecified { message: "Something went wrong".into() }),
+        ("response.error.facility_not_found", Error::FacilityNotFound { facility_name: "MR9".into() }),
+        ("response.error.booking_not_found", Error::BookingNotFound { booking_id: BOOKING_ID }),
+        ("response.error.overlap", Error::Overlap { conflicting: BOOKING_ID }),
+        ("response.error.crosses_day_boundary", Error::CrossesDayBoundary),
+        ("response.error.invalid_time", Error::InvalidTime { reason: "Start is after end".into() }),
+        ("response.error.duplicate_booking_id", Error::DuplicateBookingId { booking_id: BOOKING_ID }),
+        ("response.error.decode_error", Error::DecodeError { reason: "Not enough bytes".into() }),
+        ("response.error.unsupported_version", Error::UnsupportedVersion { min_version: 1, max_version: 2 }),
+        ("response.error.io", Error::Io { reason: "Connection refused".into() }),
+        ("response.error.timeout", Error::Timeout { attempts: 10 }),
+        ("response.error.encode_error", Error::EncodeError { reason: "String is too long".into() }),
+        ("response.error.malformed_request", Error::MalformedRequest { reason: "Unknown RequestType discriminant: 9".into() }),
+        ("response.error.recurrence_conflict", Error::RecurrenceConflict { conflicts: vec![(date(12), BOOKING_ID)] }),
+        ("response.error.turnaround_conflict", Error::TurnaroundConflict { conflicting: BOOKING_ID, buffer: Duration::from_minutes(15) }),
+        ("response.error.facility_closed", Error::FacilityClosed { closed: interval(time(10, 3, 0), time(10, 8, 0)) }),
+        ("response.error.insufficient_capacity", Error::InsufficientCapacity { requested: 4, available: 3 }),
+        ("response.error.invalid_seats", Error::InvalidSeats { reason: "Bookings must take at least 1 seat".into() }),
+        ("response.error.unauthorized", Error::Unauthorized),
+        ("response.error.facility_exists", Error::FacilityExists { facility_name: "MR1".into() }),
+        ("response.error.facility_has_bookings", Error::FacilityHasBookings { facility_name: "MR1".into(), bookings: 4 }),
+    ];
+
+    let header = Header::new(PROTOCOL_VERSION);
+    let mut fixtures = Vec::new();
+    for (name, request_type) in requests {
+        let request = RawRequest { request_id: REQUEST_ID, request_type };
+        fixtures.push((name, roundtrip(header, &request)));
+    }
+    for (name, response_type) in responses {
+        let response = RawResponse { request_id: REQUEST_ID, response_type };
+        fixtures.push((name, roundtrip(header, &response)));
+    }
+    for (name, error) in errors {
+        let response = RawResponse { request_id: REQUEST_ID, response_type: ResponseType::Error(error) };
+        fixtures.push((name, roundtrip(header, &response)));
+    }
+    for (name, version, request_type) in legacy_requests {
+        let request = RawRequest { request_id: REQUEST_ID, request_type };
+        fixtures.push((name, roundtrip(Header::new(version), &request)));
+    }
+    for (name, version, response_type) in legacy_responses {
+        let response = RawResponse { request_id: REQUEST_ID, response_type };
+        fixtures.push((name, roundtrip(Header::new(version), &response)));
+    }
+
+    // lengths as varints instead of u16s
+    let varint_header = header.with_length_encoding(LengthEncoding::Varint);
+    let request = RawRequest {
+        request_id: REQUEST_ID,
+        request_type: RequestType::Availability(AvailabilityRequest {
+            facility_name: "MR1".into(),
+            start_date: date(10),
+            end_date: date(16),
+            days: vec![Day::Monday, Day::Sunday]
+        })
+    };
+    fixtures.push(("request.varint.availability", roundtrip(varint_header, &request)));
+
+    let fragment_header = Header {
+        version: PROTOCOL_VERSION,
+        flags: FLAG_FRAGMENT
+    };
+    let fragment = Fragment {
+        message_id: 0x0102_0304,
+        index: 1,
+        count: 3,
+        data: vec![0xde, 0xad, 0xbe, 0xef]
+    };
+    fixtures.push(("fragment", roundtrip(fragment_header, &fragment)));
+
+    let fragment_request_header = Header {
+        version: PROTOCOL_VERSION,
+        flags: FLAG_FRAGMENT_REQUEST
+    };
+    let fragment_request = FragmentRequest {
+        message_id: 0x0102_0304,
+        missing: vec![0, 2]
+    };
+    fixtures.push(("fragment_request", roundtrip(fragment_request_header, &fragment_request)));
+
+    let upgrade_header = Header {
+        version: PROTOCOL_VERSION,
+        flags: FLAG_UPGRADE_REQUIRED
+    };
+    let upgrade = UpgradeRequired {
+        request_id: REQUEST_ID,
+        min_version: MIN_PROTOCOL_VERSION,
+        max_version: PROTOCOL_VERSION
+    };
+    fixtures.push(("response.upgrade_required", roundtrip(upgrade_header, &upgrade)));
+
+    fixtures
+}
+
+fn roundtrip<T: Byteable>(header: Header, val: &T) -> Vec<u8> {
+    let bytes = encode_message(header, val).expect("Fixture should encode");
+
+    let (decoded_header, mut reader) = decode_header(&bytes).expect("Fixture header should decode");
+    let decoded: T = decode_body(&decoded_header, &mut reader).expect("Fixture should decode");
+    assert_eq!(encode_message(decoded_header, &decoded).as_ref(), Ok(&bytes), "Fixture should re-encode to the same bytes");
+
+    bytes
+}
+
+/// A date in the week of Monday 2025-03-10.
+fn admin(action: AdminAction) -> RequestType {
+    RequestType::Admin(AdminRequest { admin_token: "secret".into(), action })
+}
+
+fn lab_attributes() -> FacilityAttributes {
+    FacilityAttributes {
+        capacity: 30,
+        building: "South".into(),
+        floor: -1,
+        equipment: vec!["computers".into(), "projector".into()],
+        accessible: true
+    }
+}
+
+fn date(day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(2025, 3, day).unwrap()
+}
+
+fn time(day: u32, hour: u8, minute: u8) -> Time {
+    Time {
+        date: date(day),
+        hour: Hour::new(hour).unwrap(),
+        minute: Minute::new(minute).unwrap()
+    }
+}
+
+fn interval(start: Time, end: Time) -> Interval {
+    Interval::new(start, end).unwrap()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{byte:02x}")).collect()
+}
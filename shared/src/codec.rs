@@ -0,0 +1,327 @@
+use crate::protocol::PROTOCOL_VERSION;
+
+/// The default maximum length a `Reader` accepts in a length prefix.
+pub const DEFAULT_MAX_LENGTH: usize = u16::MAX as usize;
+
+/// How the length prefixes of variable-length data are encoded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LengthEncoding {
+    /// A single byte, so lengths can't be over `u8::MAX`.
+    /// Only used for single fields (see `ByteableDerive`'s `len` attribute).
+    U8,
+    /// A big-endian `u16`, so lengths can't be over `u16::MAX`.
+    #[default]
+    U16,
+    /// A big-endian `u32`, so lengths can't be over `u32::MAX`.
+    /// Only used for single fields (see `ByteableDerive`'s `len` attribute).
+    U32,
+    /// An unsigned LEB128 varint: 7 bits per byte, least significant first,
+    /// with the top bit set on every byte except the last.
+    Varint
+}
+
+/// A cursor over a borrowed slice of bytes, which `Byteable` types are decoded from.
+///
+/// Reading never copies or shifts the underlying data; it only advances the position.
+///
+/// The reader also carries the protocol version and length encoding the data was encoded with,
+/// and the maximum length it accepts in a length prefix.
+#[derive(Debug, Clone)]
+pub struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+    version: u8,
+    length_encoding: LengthEncoding,
+    max_length: usize
+}
+
+impl<'a> Reader<'a> {
+    /// Create a reader positioned at the start of `data`, at the current protocol version.
+    pub fn new(data: &'a [u8]) -> Self {
+        Self {
+            data,
+            pos: 0,
+            version: PROTOCOL_VERSION,
+            length_encoding: LengthEncoding::default(),
+            max_length: DEFAULT_MAX_LENGTH
+        }
+    }
+
+    /// The protocol version being decoded.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// Set the protocol version being decoded.
+    pub fn set_version(&mut self, version: u8) {
+        self.version = version;
+    }
+
+    /// How length prefixes are decoded.
+    pub fn length_encoding(&self) -> LengthEncoding {
+        self.length_encoding
+    }
+
+    /// Set how length prefixes are decoded.
+    pub fn set_length_encoding(&mut self, length_encoding: LengthEncoding) {
+        self.length_encoding = length_encoding;
+    }
+
+    /// Runs `f` with length prefixes decoded in the given encoding, then restores the previous encoding.
+    pub fn with_length_encoding<T>(
+        &mut self, 
+        length_encoding: LengthEncoding, 
+        f: impl FnOnce(&mut Self) -> Result<T, String>
+    ) -> Result<T, String> {
+        let previous = std::mem::replace(&mut self.length_encoding, length_encoding);
+        let result = f(self);
+        self.length_encoding = previous;
+        result
+    }
+
+    /// Set the maximum length accepted in a length prefix.
+    pub fn set_max_length(&mut self, max_length: usize) {
+        self.max_length = max_length;
+    }
+
+    /// The number of bytes read so far.
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+
+    /// The number of bytes left to read.
+    pub fn remaining(&self) -> usize {
+        self.data.len() - self.pos
+    }
+
+    /// Returns if there's nothing left to read.
+    pub fn is_empty(&self) -> bool {
+        self.remaining() == 0
+    }
+
+    /// Reads a single byte.
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        let [byte] = self.read_array::<1>()?;
+        Ok(byte)
+    }
+
+    /// Reads the next `len` bytes as a borrowed slice.
+    ///
+    /// Errors if there are less than `len` bytes left.
+    pub fn read_bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        if self.remaining() < len {
+            return Err(format!("Not enough bytes (needed: {len}, remaining: {})", self.remaining()));
+        }
+        let bytes = &self.data[self.pos..self.pos + len];
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    /// Reads the next `N` bytes into an array.
+    pub fn read_array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        let bytes = self.read_bytes(N)?;
+        Ok(bytes.try_into().expect("Slice should have exactly N bytes"))
+    }
+
+    /// Reads a length-prefixed string, borrowing it from the underlying data.
+    pub fn read_str(&mut self) -> Result<&'a str, String> {
+        let length = self.read_length()?;
+        let bytes = self.read_bytes(length)?;
+        std::str::from_utf8(bytes)
+            .map_err(|err| format!("Unable to parse bytes to string: {err}"))
+    }
+
+    /// Reads a length prefix.
+    ///
+    /// Errors if the length is over the maximum length, or more than the bytes remaining.
+    pub fn read_length(&mut self) -> Result<usize, String> {
+        let length = match self.length_encoding {
+            LengthEncoding::U8 => self.read_u8()? as usize,
+            LengthEncoding::U16 => u16::from_be_bytes(self.read_array()?) as usize,
+            LengthEncoding::U32 => u32::from_be_bytes(self.read_array()?) as usize,
+            LengthEncoding::Varint => self.read_varint()?
+        };
+        if length > self.max_length {
+            return Err(format!("Length prefix {length} is over the maximum of {}", self.max_length));
+        }
+        if length > self.remaining() {
+            return Err(format!("Length prefix {length} is more than the remaining {} bytes", self.remaining()));
+        }
+        Ok(length)
+    }
+
+    /// Reads an unsigned LEB128 varint.
+    fn read_varint(&mut self) -> Result<usize, String> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.read_u8()?;
+            let bits = (byte & 0x7f) as u64;
+            if shift == 63 && bits > 1 {
+                return Err("Varint overflows 64 bits".into());
+            }
+            value |= bits << shift;
+            if byte & 0x80 == 0 {
+                return usize::try_from(value)
+                    .map_err(|_| format!("Varint {value} doesn't fit in a usize"));
+            }
+        }
+        Err("Varint is longer than 10 bytes".into())
+    }
+
+    /// Splits off the next `len` bytes into their own reader, advancing past them.
+    ///
+    /// Useful for length-prefixed data, where decoding must stop at the end of the prefixed length.
+    pub fn sub_reader(&mut self, len: usize) -> Result<Reader<'a>, String> {
+        let bytes = self.read_bytes(len)?;
+        Ok(Reader {
+            data: bytes,
+            pos: 0,
+            ..*self
+        })
+    }
+
+    /// Checks that all bytes have been read.
+    ///
+    /// Errors if there are trailing bytes.
+    pub fn finish(&self) -> Result<(), String> {
+        if !self.is_empty() {
+            return Err(format!("Found {} trailing bytes after decoding", self.remaining()));
+        }
+        Ok(())
+    }
+}
+
+/// A growable buffer which `Byteable` types are encoded into.
+///
+/// Like `Reader`, it carries the protocol version and length encoding being encoded with.
+#[derive(Debug, Clone)]
+pub struct Writer {
+    buf: Vec<u8>,
+    version: u8,
+    length_encoding: LengthEncoding
+}
+
+impl Writer {
+    /// Create an empty writer at the current protocol version.
+    pub fn new() -> Self {
+        Self::with_version(PROTOCOL_VERSION)
+    }
+
+    /// Create an empty writer at the given protocol version.
+    pub fn with_version(version: u8) -> Self {
+        Self {
+            buf: Vec::new(),
+            version,
+            length_encoding: LengthEncoding::default()
+        }
+    }
+
+    /// The protocol version being encoded.
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    /// How length prefixes are encoded.
+    pub fn length_encoding(&self) -> LengthEncoding {
+        self.length_encoding
+    }
+
+    /// Set how length prefixes are encoded.
+    pub fn set_length_encoding(&mut self, length_encoding: LengthEncoding) {
+        self.length_encoding = length_encoding;
+    }
+
+    /// Runs `f` with length prefixes encoded in the given encoding, then restores the previous encoding.
+    pub fn with_length_encoding(
+        &mut self, 
+        length_encoding: LengthEncoding, 
+        f: impl FnOnce(&mut Self) -> Result<(), String>
+    ) -> Result<(), String> {
+        let previous = std::mem::replace(&mut self.length_encoding, length_encoding);
+        let result = f(self);
+        self.length_encoding = previous;
+        result
+    }
+
+    /// The number of bytes written so far.
+    pub fn len(&self) -> usize {
+        self.buf.len()
+    }
+
+    /// Returns if nothing has been written yet.
+    pub fn is_empty(&self) -> bool {
+        self.buf.is_empty()
+    }
+
+    /// Writes a single byte.
+    pub fn write_u8(&mut self, byte: u8) {
+        self.buf.push(byte);
+    }
+
+    /// Writes the bytes as-is.
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.buf.extend_from_slice(bytes);
+    }
+
+    /// Writes a length prefix.
+    ///
+    /// Errors if the length can't be represented in the length encoding.
+    pub fn write_length(&mut self, length: usize) -> Result<(), String> {
+        match self.length_encoding {
+            LengthEncoding::U8 => {
+                let length = u8::try_from(length)
+                    .map_err(|_| format!("Length {length} is over the maximum of {}", u8::MAX))?;
+                self.write_u8(length);
+            },
+            LengthEncoding::U16 => {
+                let length = u16::try_from(length)
+                    .map_err(|_| format!("Length {length} is over the maximum of {}; use varint lengths instead", u16::MAX))?;
+                self.write_bytes(&length.to_be_bytes());
+            },
+            LengthEncoding::U32 => {
+                let length = u32::try_from(length)
+                    .map_err(|_| format!("Length {length} is over the maximum of {}", u32::MAX))?;
+                self.write_bytes(&length.to_be_bytes());
+            },
+            LengthEncoding::Varint => {
+                let mut remaining = length as u64;
+                while remaining >= 0x80 {
+                    self.write_u8((remaining as u8 & 0x7f) | 0x80);
+                    remaining >>= 7;
+                }
+                self.write_u8(remaining as u8);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a length-prefixed string.
+    pub fn write_str(&mut self, s: &str) -> Result<(), String> {
+        self.write_length(s.len())?;
+        self.write_bytes(s.as_bytes());
+        Ok(())
+    }
+
+    /// Writes a length prefix for the bytes written by `f`, followed by those bytes.
+    pub fn write_length_prefixed(&mut self, f: impl FnOnce(&mut Writer) -> Result<(), String>) -> Result<(), String> {
+        let mut inner = Writer {
+            buf: Vec::new(),
+            ..*self
+        };
+        f(&mut inner)?;
+        self.write_length(inner.len())?;
+        self.write_bytes(&inner.buf);
+        Ok(())
+    }
+
+    /// Consumes the writer, returning the written bytes.
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.buf
+    }
+}
+
+impl Default for Writer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
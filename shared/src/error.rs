@@ -0,0 +1,234 @@
+use std::fmt::Display;
+use chrono::NaiveDate;
+use derive::ByteableDerive;
+use uuid::Uuid;
+use crate::{protocol, time::{Duration, Interval}, Byteable, Reader, Writer};
+
+/// Errors from the server or the protocol, with stable numeric codes.
+///
+/// Each variant's discriminant is its error code (see `code`), which is sent on the wire.
+/// Codes must never be changed or reused, so clients can always branch on them.
+/// Codes newer than a response's protocol version are sent as `Unspecified` instead (see `for_version`).
+///
+/// New variants need a minimum version in `since_version`.
+#[derive(ByteableDerive, Debug, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// An error without a more specific code, eg. from a protocol version 1 response.
+    #[byteable(tag = 0)]
+    Unspecified {
+        message: String
+    },
+    #[byteable(tag = 1)]
+    FacilityNotFound {
+        facility_name: String
+    },
+    #[byteable(tag = 2)]
+    BookingNotFound {
+        booking_id: Uuid
+    },
+    /// The booking overlaps with an existing booking.
+    #[byteable(tag = 3)]
+    Overlap {
+        conflicting: Uuid
+    },
+    /// The booking would start and end on different days.
+    ///
+    /// No longer sent, as bookings can span several days; kept so its code isn't reused.
+    #[byteable(tag = 4)]
+    CrossesDayBoundary,
+    /// The times given are invalid, eg. the start is after the end.
+    #[byteable(tag = 5)]
+    InvalidTime {
+        reason: String
+    },
+    /// A booking with the ID already exists.
+    #[byteable(tag = 6)]
+    DuplicateBookingId {
+        booking_id: Uuid
+    },
+    /// A message couldn't be decoded.
+    #[byteable(tag = 7)]
+    DecodeError {
+        reason: String
+    },
+    /// The other side doesn't speak a protocol version we do.
+    #[byteable(tag = 8)]
+    UnsupportedVersion {
+        min_version: u8,
+        max_version: u8
+    },
+    /// Sending or receiving on the socket failed.
+    #[byteable(tag = 9)]
+    Io {
+        reason: String
+    },
+    /// No response was received in time.
+    #[byteable(tag = 10)]
+    Timeout {
+        attempts: u8
+    },
+    /// A message couldn't be encoded, eg. as it's too long.
+    #[byteable(tag = 11)]
+    EncodeError {
+        reason: String
+    },
+    /// The server couldn't decode the request, although it could read its ID.
+    #[byteable(tag = 12)]
+    MalformedRequest {
+        reason: String
+    },
+    /// Occurrences of a recurring booking overlap with existing bookings, as (date, conflicting booking ID).
+    #[byteable(tag = 13)]
+    RecurrenceConflict {
+        conflicts: Vec<(NaiveDate, Uuid)>
+    },
+    /// The booking doesn't overlap with an existing booking, but is within the facility's turnaround buffer of it.
+    #[byteable(tag = 14)]
+    TurnaroundConflict {
+        conflicting: Uuid,
+        buffer: Duration
+    },
+    /// The booking falls outside the facility's opening hours, or in one of its blackout periods.
+    #[byteable(tag = 15)]
+    FacilityClosed {
+        /// The first part of the booking when the facility is closed.
+        closed: Interval
+    },
+    /// Not enough seats are free in the facility during the booking.
+    #[byteable(tag = 16)]
+    InsufficientCapacity {
+        requested: u16,
+        /// The most seats free throughout the booking.
+        available: u16
+    },
+    /// The number of seats given is invalid, eg. it's 0.
+    #[byteable(tag = 17)]
+    InvalidSeats {
+        reason: String
+    },
+    /// The request needs the server's admin token, and it wasn't given.
+    #[byteable(tag = 18)]
+    Unauthorized,
+    /// A facility with the name already exists.
+    #[byteable(tag = 19)]
+    FacilityExists {
+        facility_name: String
+    },
+    /// The facility can't be retired as it has bookings.
+    #[byteable(tag = 20)]
+    FacilityHasBookings {
+        facility_name: String,
+        bookings: u32
+    }
+}
+
+impl Error {
+    /// The stable numeric code of the error.
+    pub fn code(&self) -> u8 {
+        match self {
+            Error::Unspecified { .. } => 0,
+            Error::FacilityNotFound { .. } => 1,
+            Error::BookingNotFound { .. } => 2,
+            Error::Overlap { .. } => 3,
+            Error::CrossesDayBoundary => 4,
+            Error::InvalidTime { .. } => 5,
+            Error::DuplicateBookingId { .. } => 6,
+            Error::DecodeError { .. } => 7,
+            Error::UnsupportedVersion { .. } => 8,
+            Error::Io { .. } => 9,
+            Error::Timeout { .. } => 10,
+            Error::EncodeError { .. } => 11,
+            Error::MalformedRequest { .. } => 12,
+            Error::RecurrenceConflict { .. } => 13,
+            Error::TurnaroundConflict { .. } => 14,
+            Error::FacilityClosed { .. } => 15,
+            Error::InsufficientCapacity { .. } => 16,
+            Error::InvalidSeats { .. } => 17,
+            Error::Unauthorized => 18,
+            Error::FacilityExists { .. } => 19,
+            Error::FacilityHasBookings { .. } => 20,
+        }
+    }
+
+    /// The oldest protocol version whose clients know the error's code.
+    ///
+    /// Codes added without a version bump (eg. `FacilityClosed`, within version 8) count from the next version,
+    /// as clients of the version they were added in may not know them.
+    pub fn since_version(&self) -> u8 {
+        match self.code() {
+            0..=11 => protocol::MIN_PROTOCOL_VERSION,
+            12 => protocol::CHECKSUM_VERSION,
+            13 => protocol::RECURRENCE_VERSION,
+            14 => protocol::TURNAROUND_VERSION,
+            15..=17 => protocol::CAPACITY_VERSION,
+            _ => protocol::ADMIN_VERSION
+        }
+    }
+
+    /// Returns the error as clients of the protocol version can decode it: as it is if they know its code,
+    /// or as `Unspecified` with its message otherwise.
+    pub fn for_version(&self, version: u8) -> Error {
+        match version >= self.since_version() {
+            true => self.clone(),
+            false => Error::Unspecified { message: self.to_string() }
+        }
+    }
+}
+
+/// For `ByteableDerive`'s `with` attribute; encodes errors with codes newer than the protocol version
+/// as `Unspecified` (see `Error::for_version`).
+pub mod for_version {
+    use super::*;
+
+    pub fn decode(reader: &mut Reader<'_>) -> Result<Error, String> {
+        Error::decode(reader)
+    }
+
+    pub fn encode(error: &Error, writer: &mut Writer) -> Result<(), String> {
+        error.for_version(writer.version()).encode(writer)
+    }
+}
+
+impl Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Unspecified { message } => write!(f, "{message}"),
+            Error::FacilityNotFound { facility_name } => write!(f, "No facility {facility_name} found"),
+            Error::BookingNotFound { booking_id } => write!(f, "No booking ID {booking_id} found in any facility"),
+            Error::Overlap { conflicting } => write!(f, "Booking overlaps with current booking {conflicting}"),
+            Error::CrossesDayBoundary => write!(f, "Booking must start and end on the same day"),
+            Error::InvalidTime { reason } => write!(f, "Invalid time: {reason}"),
+            Error::DuplicateBookingId { booking_id } => write!(f, "Booking {booking_id} already exists"),
+            Error::DecodeError { reason } => write!(f, "Unable to decode message: {reason}"),
+            Error::UnsupportedVersion { min_version, max_version } => {
+                write!(f, "Only protocol versions {min_version}-{max_version} are supported")
+            },
+            Error::Io { reason } => write!(f, "Socket error: {reason}"),
+            Error::Timeout { attempts } => write!(f, "Timeout occurred; no response after {attempts} attempts"),
+            Error::EncodeError { reason } => write!(f, "Unable to encode message: {reason}"),
+            Error::MalformedRequest { reason } => write!(f, "Server couldn't decode the request: {reason}"),
+            Error::RecurrenceConflict { conflicts } => {
+                write!(f, "{} occurrences overlap with current bookings:", conflicts.len())?;
+                for (date, conflicting) in conflicts {
+                    write!(f, "\n{date}: booking {conflicting}")?;
+                }
+                Ok(())
+            },
+            Error::TurnaroundConflict { conflicting, buffer } => {
+                write!(f, "Booking must be at least {buffer} (H:MM) before or after current booking {conflicting}")
+            },
+            Error::FacilityClosed { closed } => write!(f, "Facility is closed during {closed}"),
+            Error::InsufficientCapacity { requested, available } => {
+                write!(f, "Only {available} seats are free throughout the booking, but {requested} were requested")
+            },
+            Error::InvalidSeats { reason } => write!(f, "Invalid seats: {reason}"),
+            Error::Unauthorized => write!(f, "Admin token is missing or wrong"),
+            Error::FacilityExists { facility_name } => write!(f, "Facility {facility_name} already exists"),
+            Error::FacilityHasBookings { facility_name, bookings } => {
+                write!(f, "Facility {facility_name} still has {bookings} bookings")
+            },
+        }
+    }
+}
+
+impl std::error::Error for Error {}
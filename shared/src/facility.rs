@@ -0,0 +1,77 @@
+use std::fmt::Display;
+use derive::ByteableDerive;
+use crate::time::Duration;
+
+/// What a facility is like, which facilities can be searched by.
+#[derive(ByteableDerive, Debug, Clone, PartialEq, Eq)]
+pub struct FacilityAttributes {
+    /// The number of seats, which bookings can take at the same time.
+    pub capacity: u16,
+    pub building: String,
+    /// The floor of the building, where 0 is the ground floor and basements are negative.
+    pub floor: i16,
+    /// Tags for the equipment in it, eg. "projector".
+    pub equipment: Vec<String>,
+    /// Whether it has step-free access.
+    pub accessible: bool
+}
+
+impl Default for FacilityAttributes {
+    /// A single seat on the ground floor of an unnamed building, without any equipment or step-free access.
+    fn default() -> Self {
+        Self {
+            capacity: 1,
+            building: String::new(),
+            floor: 0,
+            equipment: Vec::new(),
+            accessible: false
+        }
+    }
+}
+
+impl FacilityAttributes {
+    /// Returns if it has the equipment, ignoring case.
+    pub fn has_equipment(&self, tag: &str) -> bool {
+        self.equipment
+            .iter()
+            .any(|equipment| equipment.eq_ignore_ascii_case(tag))
+    }
+}
+
+impl Display for FacilityAttributes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.capacity {
+            1 => write!(f, "1 seat")?,
+            capacity => write!(f, "{capacity} seats")?
+        }
+        write!(f, ", {} floor {}", self.building, self.floor)?;
+        if !self.equipment.is_empty() {
+            write!(f, ", with {}", self.equipment.join(", "))?;
+        }
+        if self.accessible {
+            write!(f, ", step-free access")?;
+        }
+        Ok(())
+    }
+}
+
+/// A facility as listed for administrators.
+#[derive(ByteableDerive, Debug, Clone, PartialEq, Eq)]
+pub struct FacilitySummary {
+    pub facility_name: String,
+    pub attributes: FacilityAttributes,
+    /// The time which must be left free between consecutive bookings.
+    pub turnaround_buffer: Duration,
+    /// The number of current bookings.
+    pub bookings: u32
+}
+
+impl Display for FacilitySummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}", self.facility_name, self.attributes)?;
+        if self.turnaround_buffer != Duration::ZERO {
+            write!(f, ", {} (H:MM) between bookings", self.turnaround_buffer)?;
+        }
+        write!(f, "; {} bookings", self.bookings)
+    }
+}
@@ -0,0 +1,320 @@
+use std::{collections::HashMap, net::SocketAddr, time::{Duration, Instant}};
+use derive::ByteableDerive;
+use crate::{codec::DEFAULT_MAX_LENGTH, protocol::{self, Header, FLAG_FRAGMENT, FLAG_FRAGMENT_REQUEST}, Byteable, Reader};
+
+/// The most bytes of a message carried by one fragment, keeping datagrams under the usual path MTU.
+pub const MAX_FRAGMENT_DATA: usize = 1024;
+
+/// The most fragments a message can be split into, which bounds the memory used to reassemble it.
+pub const MAX_FRAGMENTS: u16 = 4096;
+
+/// The most partially received messages kept from each address.
+pub const MAX_PARTIAL_PER_ADDRESS: usize = 8;
+
+/// The most partially received messages kept from every address together.
+pub const MAX_PARTIAL_MESSAGES: usize = 1024;
+
+/// The most bytes of fragments kept for partially received messages from every address together.
+pub const MAX_BUFFERED_BYTES: usize = 16 * 1024 * 1024;
+
+/// How long a partially received message is kept after its latest fragment arrived.
+pub const REASSEMBLY_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long sent fragments are kept for retransmission.
+pub const SENT_FRAGMENTS_TTL: Duration = Duration::from_secs(30);
+
+/// The body of a datagram carrying part of a message, sent with `FLAG_FRAGMENT` set.
+///
+/// The message is the full encoded message, including its own header and checksum.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct Fragment {
+    pub message_id: u32,
+    pub index: u16,
+    pub count: u16,
+    pub data: Vec<u8>
+}
+
+/// The body of a datagram asking for fragments of a message to be sent again, sent with `FLAG_FRAGMENT_REQUEST` set.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct FragmentRequest {
+    pub message_id: u32,
+    pub missing: Vec<u16>
+}
+
+/// The ID of an encoded message, which is the CRC32 of its bytes.
+///
+/// As it only depends on the bytes, sending the same message again reuses the ID,
+/// so its fragments fill in the gaps of an earlier attempt.
+pub fn message_id(message: &[u8]) -> u32 {
+    crc32fast::hash(message)
+}
+
+/// Splits an encoded message into the datagrams to send it in.
+///
+/// The message is sent as-is if it fits in one fragment, or its version predates fragmentation.
+///
+/// Errors if the message's header can't be decoded, or it needs more than `MAX_FRAGMENTS` fragments.
+pub fn split(message: Vec<u8>) -> Result<Vec<Vec<u8>>, String> {
+    let header = Header::decode(&mut Reader::new(&message))?;
+    if !protocol::has_fragmentation(header.version) || message.len() <= MAX_FRAGMENT_DATA {
+        return Ok(vec![message]);
+    }
+
+    let chunks = message.chunks(MAX_FRAGMENT_DATA);
+    let count = u16::try_from(chunks.len())
+        .ok()
+        .filter(|&count| count <= MAX_FRAGMENTS)
+        .ok_or_else(|| format!("Message of {} bytes is over the maximum of {MAX_FRAGMENTS} fragments", message.len()))?;
+    let fragment_header = Header {
+        version: header.version,
+        flags: FLAG_FRAGMENT
+    };
+    let message_id = message_id(&message);
+
+    chunks
+        .enumerate()
+        .map(|(index, data)| {
+            let fragment = Fragment {
+                message_id,
+                index: index as u16,
+                count,
+                data: data.to_vec()
+            };
+            protocol::encode_message(fragment_header, &fragment)
+        })
+        .collect()
+}
+
+/// Encodes a request for the missing fragments of a message.
+pub fn encode_fragment_request(version: u8, request: &FragmentRequest) -> Result<Vec<u8>, String> {
+    let header = Header {
+        version,
+        flags: FLAG_FRAGMENT_REQUEST
+    };
+    protocol::encode_message(header, request)
+}
+
+/// What a received datagram turned out to be.
+#[derive(Debug)]
+pub enum Received {
+    /// A whole message, either unfragmented or just reassembled.
+    Message(Vec<u8>),
+    /// A fragment of a message which is still missing fragments.
+    Incomplete,
+    /// A request to send fragments of a message again.
+    FragmentRequest(FragmentRequest)
+}
+
+/// Reassembles fragmented messages from each address.
+///
+/// The memory it uses is bounded: messages can't be over the max message size, and fragments of new messages
+/// are rejected once there are `MAX_PARTIAL_PER_ADDRESS` from their address, `MAX_PARTIAL_MESSAGES` in total,
+/// or `MAX_BUFFERED_BYTES` of fragments kept.
+pub struct Reassembler {
+    partial: HashMap<(SocketAddr, u32), PartialMessage>,
+    /// The total length of the fragments kept in `partial`.
+    buffered: usize,
+    /// The largest message which can be received, in bytes, whether in one datagram or reassembled.
+    max_message_size: usize
+}
+
+/// A message whose fragments are still arriving.
+struct PartialMessage {
+    fragments: Vec<Option<Vec<u8>>>,
+    received: usize,
+    /// The total length of the fragments received.
+    bytes: usize,
+    last_received: Instant
+}
+
+impl Reassembler {
+    pub fn new() -> Self {
+        Self {
+            partial: HashMap::new(),
+            buffered: 0,
+            max_message_size: DEFAULT_MAX_LENGTH
+        }
+    }
+
+    /// Accepts messages up to the size (`DEFAULT_MAX_LENGTH` otherwise).
+    pub fn with_max_message_size(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = max_message_size;
+        self
+    }
+
+    /// Handles a datagram from the address.
+    ///
+    /// Datagrams of versions this build doesn't speak are returned as-is, so they can be replied to.
+    ///
+    /// Errors if the datagram's header, checksum or fragment is invalid, its message is over the max message size,
+    /// or it's the first fragment of a message and no more partial messages can be kept.
+    pub fn receive(&mut self, addr: SocketAddr, datagram: &[u8]) -> Result<Received, String> {
+        self.prune();
+        if datagram.len() > self.max_message_size {
+            return Err(format!("Datagram of {} bytes is over the max message size of {}", datagram.len(), self.max_message_size));
+        }
+
+        let (header, mut reader) = protocol::decode_header(datagram)?;
+        if !protocol::is_supported_version(header.version) {
+            return Ok(Received::Message(datagram.to_vec()));
+        }
+        if header.has_flag(FLAG_FRAGMENT_REQUEST) {
+            let request = protocol::decode_body(&header, &mut reader)?;
+            return Ok(Received::FragmentRequest(request));
+        }
+        if !header.has_flag(FLAG_FRAGMENT) {
+            return Ok(Received::Message(datagram.to_vec()));
+        }
+
+        let fragment: Fragment = protocol::decode_body(&header, &mut reader)?;
+        self.insert(addr, fragment)
+    }
+
+    /// The fragments still missing from each partially received message, along with who's sending it.
+    pub fn missing(&self) -> Vec<(SocketAddr, FragmentRequest)> {
+        self.partial
+            .iter()
+            .map(|((addr, message_id), partial)| {
+                let missing = partial.fragments
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, fragment)| fragment.is_none())
+                    .map(|(index, _)| index as u16)
+                    .collect();
+                (*addr, FragmentRequest { message_id: *message_id, missing })
+            })
+            .collect()
+    }
+
+    /// Drops all partially received messages.
+    pub fn clear(&mut self) {
+        self.partial.clear();
+        self.buffered = 0;
+    }
+
+    /// Adds a fragment, returning the message if it's now complete.
+    fn insert(&mut self, addr: SocketAddr, fragment: Fragment) -> Result<Received, String> {
+        let Fragment { message_id, index, count, data } = fragment;
+        let max_count = self.max_message_size
+            .div_ceil(MAX_FRAGMENT_DATA)
+            .min(MAX_FRAGMENTS.into());
+        if count == 0 || usize::from(count) > max_count {
+            return Err(format!("Fragment count must be between 1 and {max_count} (got {count})"));
+        }
+        if index >= count {
+            return Err(format!("Fragment index {index} is out of range for {count} fragments"));
+        }
+        if data.len() > MAX_FRAGMENT_DATA {
+            return Err(format!("Fragment of {} bytes is over the maximum of {MAX_FRAGMENT_DATA}", data.len()));
+        }
+        if self.buffered + data.len() > MAX_BUFFERED_BYTES {
+            return Err(format!("Already keeping {} bytes of fragments; dropping fragment from {addr}", self.buffered));
+        }
+
+        let key = (addr, message_id);
+        if !self.partial.contains_key(&key) {
+            if self.partial.len() >= MAX_PARTIAL_MESSAGES {
+                return Err(format!("Already keeping {MAX_PARTIAL_MESSAGES} partial messages; dropping fragment from {addr}"));
+            }
+            if self.partial.keys().filter(|(from, _)| *from == addr).count() >= MAX_PARTIAL_PER_ADDRESS {
+                return Err(format!("Already keeping {MAX_PARTIAL_PER_ADDRESS} partial messages from {addr}; dropping fragment"));
+            }
+        }
+        let partial = self.partial
+            .entry(key)
+            .or_insert_with(|| PartialMessage {
+                fragments: vec![None; count as usize],
+                received: 0,
+                bytes: 0,
+                last_received: Instant::now()
+            });
+        if partial.fragments.len() != count as usize {
+            return Err(format!("Fragment count {count} doesn't match the earlier count of {}", partial.fragments.len()));
+        }
+
+        partial.last_received = Instant::now();
+        let slot = &mut partial.fragments[index as usize];
+        if slot.is_none() {
+            partial.bytes += data.len();
+            self.buffered += data.len();
+            *slot = Some(data);
+            partial.received += 1;
+        }
+        if partial.received < partial.fragments.len() {
+            return Ok(Received::Incomplete);
+        }
+
+        let partial = self.partial
+            .remove(&key)
+            .expect("Partial message should exist");
+        self.buffered -= partial.bytes;
+        if partial.bytes > self.max_message_size {
+            return Err(format!("Reassembled message of {} bytes is over the max message size of {}", partial.bytes, self.max_message_size));
+        }
+        let message = partial.fragments
+            .into_iter()
+            .flatten()
+            .flatten()
+            .collect();
+        Ok(Received::Message(message))
+    }
+
+    /// Drops partial messages which haven't had a fragment in a while.
+    fn prune(&mut self) {
+        self.partial.retain(|_, partial| partial.last_received.elapsed() < REASSEMBLY_TIMEOUT);
+        self.buffered = self.partial
+            .values()
+            .map(|partial| partial.bytes)
+            .sum();
+    }
+}
+
+impl Default for Reassembler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Recently sent fragmented messages, kept so lost fragments can be sent again.
+pub struct SentFragments {
+    sent: HashMap<(SocketAddr, u32), SentMessage>
+}
+
+/// The datagrams a message was sent in.
+struct SentMessage {
+    datagrams: Vec<Vec<u8>>,
+    sent_at: Instant
+}
+
+impl SentFragments {
+    pub fn new() -> Self {
+        Self {
+            sent: HashMap::new()
+        }
+    }
+
+    /// Keeps the datagrams of a message sent to the address, dropping any older than `SENT_FRAGMENTS_TTL`.
+    pub fn insert(&mut self, addr: SocketAddr, message_id: u32, datagrams: Vec<Vec<u8>>) {
+        self.sent.retain(|_, sent| sent.sent_at.elapsed() < SENT_FRAGMENTS_TTL);
+        self.sent.insert((addr, message_id), SentMessage { datagrams, sent_at: Instant::now() });
+    }
+
+    /// The datagrams asked for by a fragment request from the address.
+    ///
+    /// Returns `None` if the message isn't kept (anymore).
+    pub fn get(&self, addr: SocketAddr, request: &FragmentRequest) -> Option<Vec<&[u8]>> {
+        let sent = self.sent.get(&(addr, request.message_id))?;
+        let requested = request.missing
+            .iter()
+            .filter_map(|&index| sent.datagrams.get(index as usize))
+            .map(|datagram| datagram.as_slice())
+            .collect();
+        Some(requested)
+    }
+}
+
+impl Default for SentFragments {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -1,158 +1,312 @@
+use std::collections::BTreeMap;
+use chrono::Datelike;
 use uuid::Uuid;
 
+// lets `ByteableDerive`'s generated code refer to `::shared` from within this crate too
+extern crate self as shared;
+
+pub mod codec;
+pub mod error;
+pub mod facility;
+pub mod fragment;
+pub mod protocol;
+pub mod recurrence;
 pub mod requests;
 pub mod responses;
 pub mod time;
 
+pub use codec::{LengthEncoding, Reader, Writer};
+
 /// Trait for things that are serializable to/from bytes.
-/// 
+///
 /// ## Implementation
 /// - For structs, the conversion must be in top-to-bottom order of struct fields,
-/// where each struct field is also `Byteable`.
-/// 
-/// - For variable-length fields, the first byte (or 2) should be a `u8`/`u16` for the data's bytelength, 
-/// followed by the actual data.
-/// 
-/// - For enums, the the first byte should be a discriminant for the actual variant, 
-/// followed by the actual data. 
-/// 
+///   where each struct field is also `Byteable`.
+///
+/// - For variable-length fields, there should be a length prefix for the data's bytelength
+///   (see `Writer::write_length`), followed by the actual data.
+///
+/// - For enums, the the first byte should be a discriminant for the actual variant,
+///   followed by the actual data.
+///
 /// - For static-sized fields, it should just be the bytes.
-/// 
+///
+/// - Multi-byte integers (including length prefixes) are always big-endian (network byte order),
+///   so the encoding is the same regardless of the host's architecture.
+///
+/// Implementors only need `decode` and `encode`;
+/// `from_bytes` and `to_bytes` are provided for (de)serializing whole messages.
+///
 /// ## Derive
-/// If a struct's fields are all `Byteable`, you can use `ByteableDerive` to quickly get an implementation.
+/// If a struct's or enum's fields are all `Byteable`, you can use `ByteableDerive` to quickly get an implementation.
+/// Its field attributes cover skipped, versioned and custom-encoded fields (see its docs).
 pub trait Byteable where Self: Sized {
-    /// Deserialize the type from bytes in a Vec of bytes.
-    /// 
+    /// Decode the type from the reader's current position, advancing past it.
+    ///
+    /// Errors if unable to.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String>;
+
+    /// Encode the type onto the end of the writer.
+    ///
+    /// Errors if unable to, eg. if the type is too long for its length prefix.
+    fn encode(&self, writer: &mut Writer) -> Result<(), String>;
+
+    /// Deserialize the type from a slice of bytes.
+    ///
+    /// Errors if unable to, or if there are bytes left over afterwards.
+    fn from_bytes(data: &[u8]) -> Result<Self, String> {
+        let mut reader = Reader::new(data);
+        let val = Self::decode(&mut reader)?;
+        reader.finish()?;
+        Ok(val)
+    }
+
+    /// Serialize the type to a Vec of bytes.
+    ///
     /// Errors if unable to.
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String>;
-    /// Deserializes the type to a Vec of bytes.
-    /// 
-    /// TODO: use `&mut Vec<u8>` here too for optimization?
-    fn to_bytes(self) -> Vec<u8>;
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let mut writer = Writer::new();
+        self.encode(&mut writer)?;
+        Ok(writer.into_bytes())
+    }
 }
 
 impl Byteable for bool {
     /// From a single `u8` where `0` is `false` and everything else is `true`.
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-        if data.len() >= 1 {
-            return Ok(data.remove(0) >= 1);
-        }
-        Err("0 bytes found".to_string())
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        Ok(reader.read_u8()? >= 1)
     }
-    
-    fn to_bytes(self) -> Vec<u8> {
-        match self {
-            true => vec![1],
-            false => vec![0]
-        }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        writer.write_u8(*self as u8);
+        Ok(())
     }
 }
 
 impl Byteable for u8 {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-        if data.len() >= 1 {
-            return Ok(data.remove(0));
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        reader.read_u8()
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        writer.write_u8(*self);
+        Ok(())
+    }
+}
+
+/// Implements `Byteable` for integers as their big-endian bytes.
+macro_rules! impl_byteable_int {
+    ($($int:ty),*) => {
+        $(
+            impl Byteable for $int {
+                fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+                    Ok(<$int>::from_be_bytes(reader.read_array()?))
+                }
+
+                fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+                    writer.write_bytes(&self.to_be_bytes());
+                    Ok(())
+                }
+            }
+        )*
+    };
+}
+
+impl_byteable_int!(u16, u32, u64, u128, i8, i16, i32, i64, i128);
+
+impl Byteable for Uuid {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        Ok(Uuid::from_bytes(reader.read_array()?))
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        writer.write_bytes(self.as_bytes());
+        Ok(())
+    }
+}
+
+impl Byteable for String {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        Ok(reader.read_str()?.to_owned())
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        writer.write_str(self)
+    }
+}
+
+impl<T: Byteable> Byteable for Vec<T> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let length = reader.read_length()?;
+        let mut items_reader = reader.sub_reader(length)?;
+
+        let mut items = Vec::new();
+        while !items_reader.is_empty() {
+            let item = T::decode(&mut items_reader)?;
+            items.push(item);
         }
-        Err("0 bytes found".to_string())
+
+        Ok(items)
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        vec![self]
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        writer.write_length_prefixed(|writer| {
+            for item in self {
+                item.encode(writer)?;
+            }
+            Ok(())
+        })
     }
 }
 
-impl Byteable for u16 {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-        if data.len() >= 2 {
-            let bytes = data
-                    .drain(..2)
-                    .collect::<Vec<_>>()
-                    .try_into()
-                    .map_err(|err| "Somehow got an error though enough bytes".to_string())?;
-            return Ok(
-                u16::from_ne_bytes(bytes)
-            );
+impl<T: Byteable> Byteable for Option<T> {
+    /// From a `u8` tag of `0` for `None` or `1` for `Some`, followed by the value if it's `Some`.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        match reader.read_u8()? {
+            0 => Ok(None),
+            1 => Ok(Some(T::decode(reader)?)),
+            other => Err(format!("Unknown Option tag: {other}"))
         }
-        Err("<2 bytes found".to_string())
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        self.to_ne_bytes().to_vec()
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        match self {
+            None => {
+                writer.write_u8(0);
+                Ok(())
+            },
+            Some(val) => {
+                writer.write_u8(1);
+                val.encode(writer)
+            }
+        }
     }
 }
 
-impl Byteable for Uuid {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-        if data.len() >= 16 {
-            let bytes = data
-                .drain(..16)
-                .collect::<Vec<_>>()
-                .try_into()
-                .map_err(|err| "Somehow got an error though enough bytes".to_string())?;
-            return Ok(
-                Uuid::from_bytes(bytes)
-            );
+impl<T: Byteable, const N: usize> Byteable for [T; N] {
+    /// From the `N` items back-to-back, without a length prefix.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let mut items = Vec::with_capacity(N);
+        for _ in 0..N {
+            items.push(T::decode(reader)?);
         }
-        Err(format!("Not enough bytes (len: {})", data.len()))
+        items.try_into()
+            .map_err(|_| format!("Expected exactly {N} items"))
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        self.as_bytes().to_vec()
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        for item in self {
+            item.encode(writer)?;
+        }
+        Ok(())
     }
 }
 
-impl Byteable for String {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-        let length = u16::from_bytes(data)?;
-
-        if data.len() >= length as usize {
-            let bytes = data
-                .drain(..length as usize)
-                .collect::<Vec<_>>();
-
-            return Ok(
-                String::from_utf8(bytes)
-                    .map_err(|err| format!("Unable to parse bytes to string: {err}"))?
-            )
+impl<T: Byteable> Byteable for Box<T> {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        Ok(Box::new(T::decode(reader)?))
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        (**self).encode(writer)
+    }
+}
+
+impl<K: Byteable + Ord, V: Byteable> Byteable for BTreeMap<K, V> {
+    /// Like `Vec`, from the length-prefixed (key, value) pairs in key order.
+    ///
+    /// Errors if a key is repeated.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let length = reader.read_length()?;
+        let mut entries_reader = reader.sub_reader(length)?;
+
+        let mut map = BTreeMap::new();
+        while !entries_reader.is_empty() {
+            let key = K::decode(&mut entries_reader)?;
+            let value = V::decode(&mut entries_reader)?;
+            if map.insert(key, value).is_some() {
+                return Err("Found a repeated key in map".into());
+            }
         }
 
-        Err(format!("Not enough bytes (len: {})", data.len()))
+        Ok(map)
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        let mut bytes = (self.len() as u16).to_bytes();
-        bytes.extend(self.bytes());
-        bytes
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        writer.write_length_prefixed(|writer| {
+            for (key, value) in self {
+                key.encode(writer)?;
+                value.encode(writer)?;
+            }
+            Ok(())
+        })
     }
 }
 
-impl<T: Byteable> Byteable for Vec<T> {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-        let length = u16::from_bytes(data)?;
-
-        if data.len() >= length as usize {
-            let mut bytes = data
-                .drain(..length as usize)
-                .collect::<Vec<_>>();
-
-            let mut items = Vec::new();
-            while bytes.len() > 0 {
-                let item = T::from_bytes(&mut bytes)?;
-                items.push(item);
+/// Implements `Byteable` for tuples as their items in order.
+macro_rules! impl_byteable_tuple {
+    ($(($($name:ident),+)),*) => {
+        $(
+            impl<$($name: Byteable),+> Byteable for ($($name,)+) {
+                fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+                    Ok(($($name::decode(reader)?,)+))
+                }
+
+                #[allow(non_snake_case)]
+                fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+                    let ($($name,)+) = self;
+                    $($name.encode(writer)?;)+
+                    Ok(())
+                }
             }
+        )*
+    };
+}
 
-            return Ok(items)
-        }
-        Err(format!("Not enough bytes (len: {})", data.len()))
+impl_byteable_tuple!(
+    (A),
+    (A, B),
+    (A, B, C),
+    (A, B, C, D),
+    (A, B, C, D, E),
+    (A, B, C, D, E, F)
+);
+
+impl Byteable for chrono::NaiveDate {
+    /// From a `u16` year, followed by a `u8` month and day.
+    ///
+    /// Errors if the date doesn't exist.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let year = u16::decode(reader)?;
+        let month = u8::decode(reader)?;
+        let day = u8::decode(reader)?;
+        chrono::NaiveDate::from_ymd_opt(year.into(), month.into(), day.into())
+            .ok_or_else(|| format!("Invalid date: {year:04}-{month:02}-{day:02}"))
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        let year = u16::try_from(self.year())
+            .map_err(|_| format!("Year {} is out of range", self.year()))?;
+        year.encode(writer)?;
+        (self.month() as u8).encode(writer)?;
+        (self.day() as u8).encode(writer)
+    }
+}
+
+#[cfg(feature = "chrono-datetime")]
+impl Byteable for chrono::DateTime<chrono::Utc> {
+    /// From an `i64` of seconds since the Unix epoch, followed by a `u32` of nanoseconds.
+    ///
+    /// Errors if the timestamp is out of range.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let secs = i64::decode(reader)?;
+        let nanos = u32::decode(reader)?;
+        chrono::DateTime::from_timestamp(secs, nanos)
+            .ok_or_else(|| format!("Timestamp {secs}s {nanos}ns is out of range"))
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        let data_bytes: Vec<u8> = self
-            .into_iter()
-            .flat_map(|t| t.to_bytes())
-            .collect();
-        let mut bytes = (data_bytes.len() as u16).to_bytes();
-        bytes.extend(data_bytes);
-        bytes
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        self.timestamp().encode(writer)?;
+        self.timestamp_subsec_nanos().encode(writer)
     }
 }
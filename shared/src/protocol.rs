@@ -0,0 +1,300 @@
+use derive::ByteableDerive;
+use uuid::Uuid;
+use crate::{Byteable, LengthEncoding, Reader, Writer};
+
+/// The bytes every message starts with, to tell our messages apart from stray datagrams.
+pub const MAGIC: [u8; 2] = *b"BK";
+
+/// The newest protocol version this build speaks.
+///
+/// ## History
+/// - 1: responses are an `is_error` flag and a message.
+/// - 2: responses are a typed `ResponseType`.
+/// - 3: every message ends with a CRC32 checksum (see `CHECKSUM_VERSION`).
+/// - 4: messages over `fragment::MAX_FRAGMENT_DATA` bytes are split into fragments (see `FRAGMENTATION_VERSION`).
+/// - 5: times carry calendar dates instead of weekdays, and availability requests take a date range (see `DATE_VERSION`).
+/// - 6: recurring bookings, and cancelling or offsetting a whole series (see `RECURRENCE_VERSION`).
+/// - 7: offsets and extensions are a signed `time::Duration` in minutes (see `DURATION_VERSION`).
+/// - 8: availability responses carry the facility's turnaround buffer (see `TURNAROUND_VERSION`).
+/// - 9: bookings take a number of seats, and availability slots carry how many are free (see `CAPACITY_VERSION`).
+/// - 10: searching for facilities by their attributes (see `SEARCH_VERSION`).
+/// - 11: creating, renaming, retiring and listing facilities, with an admin token (see `ADMIN_VERSION`).
+pub const PROTOCOL_VERSION: u8 = 11;
+
+/// The oldest protocol version this build still speaks.
+///
+/// During a rollout this trails `PROTOCOL_VERSION`, so old and new binaries can talk to each other.
+pub const MIN_PROTOCOL_VERSION: u8 = 1;
+
+/// The first protocol version where every message ends with a checksum.
+///
+/// The checksum is a big-endian CRC32 (IEEE) of all bytes before it, including the header.
+pub const CHECKSUM_VERSION: u8 = 3;
+
+/// The length of the checksum trailer.
+pub const CHECKSUM_LEN: usize = 4;
+
+/// The first protocol version where large messages are split into fragments.
+///
+/// Older versions always send a message in a single datagram.
+pub const FRAGMENTATION_VERSION: u8 = 4;
+
+/// The first protocol version where times carry calendar dates.
+///
+/// Older versions send a weekday instead, which is taken to be its next occurrence (including today).
+pub const DATE_VERSION: u8 = 5;
+
+/// The first protocol version with recurring bookings.
+///
+/// Older versions can only act on one booking at a time.
+pub const RECURRENCE_VERSION: u8 = 6;
+
+/// The first protocol version where offsets and extensions are a `time::Duration`.
+///
+/// Older versions send an `Hour` and `Minute` instead (and whether it's negative, for offsets),
+/// so can't express 24 hours or more.
+pub const DURATION_VERSION: u8 = 7;
+
+/// The first protocol version where availability responses carry the facility's turnaround buffer.
+///
+/// Older versions only get the slots, which already leave room for the buffer.
+pub const TURNAROUND_VERSION: u8 = 8;
+
+/// The first protocol version where bookings take a number of seats, and availability slots carry how many are free.
+///
+/// Older versions always book 1 seat, and only get the intervals of slots with a seat free.
+pub const CAPACITY_VERSION: u8 = 9;
+
+/// The first protocol version with facility searches.
+///
+/// Older versions can only refer to facilities by name.
+pub const SEARCH_VERSION: u8 = 10;
+
+/// The first protocol version with admin requests.
+///
+/// Older versions can only use the facilities the server starts with.
+pub const ADMIN_VERSION: u8 = 11;
+
+/// Set on a response when the request's protocol version isn't supported.
+/// The body is then an `UpgradeRequired` instead of a `RawResponse`.
+pub const FLAG_UPGRADE_REQUIRED: u8 = 1 << 0;
+
+/// Set when the body's length prefixes are LEB128 varints instead of `u16`s.
+///
+/// The server replies with the same length encoding as the request.
+pub const FLAG_VARINT_LENGTHS: u8 = 1 << 1;
+
+/// Set when the body is a `fragment::Fragment` of a larger message.
+pub const FLAG_FRAGMENT: u8 = 1 << 2;
+
+/// Set when the body is a `fragment::FragmentRequest`, asking for lost fragments to be sent again.
+pub const FLAG_FRAGMENT_REQUEST: u8 = 1 << 3;
+
+/// Returns if this build can speak the given protocol version.
+pub fn is_supported_version(version: u8) -> bool {
+    (MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&version)
+}
+
+/// Returns if messages of the given protocol version end with a checksum.
+pub fn has_checksum(version: u8) -> bool {
+    version >= CHECKSUM_VERSION
+}
+
+/// Returns if large messages of the given protocol version are split into fragments.
+pub fn has_fragmentation(version: u8) -> bool {
+    version >= FRAGMENTATION_VERSION
+}
+
+/// Returns if times in the given protocol version carry calendar dates.
+pub fn has_dates(version: u8) -> bool {
+    version >= DATE_VERSION
+}
+
+/// Returns if the given protocol version has recurring bookings.
+pub fn has_recurrence(version: u8) -> bool {
+    version >= RECURRENCE_VERSION
+}
+
+/// Returns if offsets and extensions in the given protocol version are a `time::Duration`.
+pub fn has_durations(version: u8) -> bool {
+    version >= DURATION_VERSION
+}
+
+/// Returns if availability responses in the given protocol version carry the turnaround buffer.
+pub fn has_turnaround(version: u8) -> bool {
+    version >= TURNAROUND_VERSION
+}
+
+/// Returns if bookings in the given protocol version take a number of seats.
+pub fn has_capacity(version: u8) -> bool {
+    version >= CAPACITY_VERSION
+}
+
+/// Returns if the given protocol version has facility searches.
+pub fn has_search(version: u8) -> bool {
+    version >= SEARCH_VERSION
+}
+
+/// Returns if the given protocol version has admin requests.
+pub fn has_admin(version: u8) -> bool {
+    version >= ADMIN_VERSION
+}
+
+/// The header at the start of every message.
+///
+/// Its layout is the same in every protocol version: the magic bytes, the version and the flags.
+/// Every request body also starts with its request ID, so it can be read even if the version isn't supported.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Header {
+    pub version: u8,
+    pub flags: u8
+}
+
+impl Header {
+    /// Create a header for the given version with no flags set.
+    pub fn new(version: u8) -> Self {
+        Self {
+            version,
+            flags: 0
+        }
+    }
+
+    /// Sets the flags for the given length encoding of the body.
+    ///
+    /// Panics if it's not `U16` or `Varint`, as only those can be set in the header.
+    pub fn with_length_encoding(mut self, length_encoding: LengthEncoding) -> Self {
+        match length_encoding {
+            LengthEncoding::U16 => self.flags &= !FLAG_VARINT_LENGTHS,
+            LengthEncoding::Varint => self.flags |= FLAG_VARINT_LENGTHS,
+            other => panic!("{other:?} lengths can't be set in the header")
+        }
+        self
+    }
+
+    /// Returns if the given flag is set.
+    pub fn has_flag(&self, flag: u8) -> bool {
+        self.flags & flag != 0
+    }
+
+    /// The length encoding of the body.
+    pub fn length_encoding(&self) -> LengthEncoding {
+        match self.has_flag(FLAG_VARINT_LENGTHS) {
+            true => LengthEncoding::Varint,
+            false => LengthEncoding::U16
+        }
+    }
+
+    /// The header for replying to a message with this header,
+    /// which uses the same version and length encoding.
+    pub fn reply(&self) -> Header {
+        Header {
+            version: self.version,
+            flags: self.flags & FLAG_VARINT_LENGTHS
+        }
+    }
+}
+
+impl Byteable for Header {
+    /// Errors if the magic bytes don't match.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let magic = reader.read_array::<2>()?;
+        if magic != MAGIC {
+            return Err(format!("Invalid magic bytes: {magic:?}"));
+        }
+        let version = u8::decode(reader)?;
+        let flags = u8::decode(reader)?;
+        Ok(Self { version, flags })
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        writer.write_bytes(&MAGIC);
+        self.version.encode(writer)?;
+        self.flags.encode(writer)
+    }
+}
+
+/// The body of a response to a request whose protocol version isn't supported.
+///
+/// Like `Header`, its layout is the same in every protocol version.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct UpgradeRequired {
+    pub request_id: Uuid,
+    pub min_version: u8,
+    pub max_version: u8
+}
+
+impl UpgradeRequired {
+    /// Create the body for the given request, with the versions this build supports.
+    pub fn new(request_id: Uuid) -> Self {
+        Self {
+            request_id,
+            min_version: MIN_PROTOCOL_VERSION,
+            max_version: PROTOCOL_VERSION
+        }
+    }
+
+    /// Returns the newest version supported by both this build and the other side, if any.
+    pub fn negotiate(&self) -> Option<u8> {
+        let newest = self.max_version.min(PROTOCOL_VERSION);
+        let oldest = self.min_version.max(MIN_PROTOCOL_VERSION);
+        (oldest <= newest).then_some(newest)
+    }
+}
+
+/// Encodes a message, preceded by the header and followed by the checksum if the version has one.
+///
+/// The body is encoded at the header's protocol version and length encoding.
+pub fn encode_message<T: Byteable>(header: Header, message: &T) -> Result<Vec<u8>, String> {
+    let mut writer = Writer::with_version(header.version);
+    header.encode(&mut writer)?;
+    writer.set_length_encoding(header.length_encoding());
+    message.encode(&mut writer)?;
+
+    let mut bytes = writer.into_bytes();
+    if has_checksum(header.version) {
+        let checksum = crc32fast::hash(&bytes);
+        bytes.extend_from_slice(&checksum.to_be_bytes());
+    }
+    Ok(bytes)
+}
+
+/// Decodes the header of a message, returning it along with a reader over the body.
+///
+/// If the version is supported and has a checksum, it's verified and left out of the reader.
+/// Otherwise, the reader covers everything after the header.
+///
+/// Errors if the header can't be decoded, or the checksum doesn't match.
+pub fn decode_header(data: &[u8]) -> Result<(Header, Reader<'_>), String> {
+    let mut reader = Reader::new(data);
+    let header = Header::decode(&mut reader)?;
+    let header_len = reader.position();
+
+    let body = match is_supported_version(header.version) && has_checksum(header.version) {
+        true => {
+            let Some(checksum_start) = data.len().checked_sub(CHECKSUM_LEN).filter(|&start| start >= header_len) else {
+                return Err("Message is too short to have a checksum".into());
+            };
+            let (contents, trailer) = data.split_at(checksum_start);
+            let expected = u32::from_be_bytes(trailer.try_into().expect("Trailer should be exactly 4 bytes"));
+            let actual = crc32fast::hash(contents);
+            if actual != expected {
+                return Err(format!("Checksum mismatch (expected: {expected:08x}, actual: {actual:08x})"));
+            }
+            &contents[header_len..]
+        },
+        false => &data[header_len..]
+    };
+    Ok((header, Reader::new(body)))
+}
+
+/// Decodes a message body at the header's protocol version and length encoding, erroring on trailing bytes.
+///
+/// The reader should be positioned just after the header. Length prefixes over the reader's max length are rejected;
+/// it's `DEFAULT_MAX_LENGTH` unless raised with `Reader::set_max_length`, eg. to accept longer varint lengths.
+pub fn decode_body<T: Byteable>(header: &Header, reader: &mut Reader<'_>) -> Result<T, String> {
+    reader.set_version(header.version);
+    reader.set_length_encoding(header.length_encoding());
+    let message = T::decode(reader)?;
+    reader.finish()?;
+    Ok(message)
+}
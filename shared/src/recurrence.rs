@@ -0,0 +1,54 @@
+use chrono::{Datelike, Days, NaiveDate};
+use derive::ByteableDerive;
+use crate::time::Day;
+
+/// A rule for repeating a booking: weekly on the given days, every `interval_weeks` weeks, until it ends.
+///
+/// Weeks run from Monday, counting from the week of the first occurrence.
+#[derive(ByteableDerive, Debug, Clone, PartialEq, Eq)]
+pub struct RecurrenceRule {
+    /// The days to repeat on; if empty, the day of the first occurrence.
+    pub days: Vec<Day>,
+    pub interval_weeks: u8,
+    pub end: RecurrenceEnd
+}
+
+/// When a recurrence ends.
+#[derive(ByteableDerive, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecurrenceEnd {
+    /// After the given date (inclusive).
+    Until(NaiveDate),
+    /// After the given number of occurrences.
+    Count(u16)
+}
+
+impl RecurrenceRule {
+    /// The dates the rule repeats on, in order, starting from `start` (which is included if it's one of the days).
+    ///
+    /// An interval of 0 weeks is taken as 1.
+    pub fn dates(&self, start: NaiveDate) -> impl Iterator<Item = NaiveDate> {
+        let mut days = match self.days.is_empty() {
+            true => vec![Day::from(start.weekday())],
+            false => self.days.clone()
+        };
+        days.sort();
+        days.dedup();
+
+        let (until, count) = match self.end {
+            RecurrenceEnd::Until(until) => (until, usize::MAX),
+            RecurrenceEnd::Count(count) => (NaiveDate::MAX, count.into())
+        };
+        let step = Days::new(7 * u64::from(self.interval_weeks.max(1)));
+        let first_monday = start - Days::new(start.weekday().num_days_from_monday().into());
+
+        std::iter::successors(Some(first_monday), move |monday| monday.checked_add_days(step))
+            .flat_map(move |monday| {
+                days.iter()
+                    .filter_map(|day| monday.checked_add_days(Days::new(day.to_u8().into())))
+                    .collect::<Vec<_>>()
+            })
+            .skip_while(move |date| date < &start)
+            .take_while(move |date| date <= &until)
+            .take(count)
+    }
+}
@@ -1,5 +1,6 @@
+use chrono::{Datelike, Days, NaiveDate};
 use uuid::Uuid;
-use crate::{time::{Day, Hour, Minute, Time}, Byteable};
+use crate::{facility::FacilityAttributes, protocol, recurrence::RecurrenceRule, time::{duration_or_hours_minutes, signed_duration_or_hours_minutes, today, Day, Duration, Interval}, Byteable, Reader, Writer};
 use derive::ByteableDerive;
 
 /// Structure of a raw request to the server.
@@ -9,42 +10,124 @@ pub struct RawRequest {
     pub request_type: RequestType,
 }
 
-/// For requesting facility availability.
-#[derive(ByteableDerive, Debug, Clone)]
+/// For requesting facility availability on each date from `start_date` to `end_date` (inclusive).
+///
+/// ## Versions
+/// Before protocol version 5, a list of days was requested instead.
+/// Such requests are decoded as the week starting today, filtered to those days,
+/// which gives the next occurrence of each day.
+#[derive(Debug, Clone)]
 pub struct AvailabilityRequest {
     pub facility_name: String,
+    pub start_date: NaiveDate,
+    pub end_date: NaiveDate,
+    /// If not empty, only dates falling on these days are included.
     pub days: Vec<Day>
 }
 
+impl AvailabilityRequest {
+    /// The requested dates, in order.
+    pub fn dates(&self) -> impl Iterator<Item = NaiveDate> + '_ {
+        self.start_date
+            .iter_days()
+            .take_while(|date| date <= &self.end_date)
+            .filter(|date| self.days.is_empty() || self.days.contains(&Day::from(date.weekday())))
+    }
+}
+
+impl Byteable for AvailabilityRequest {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let facility_name = String::decode(reader)?;
+        if !protocol::has_dates(reader.version()) {
+            let days = Vec::decode(reader)?;
+            let start_date = today();
+            return Ok(Self {
+                facility_name,
+                start_date,
+                end_date: start_date + Days::new(6),
+                days
+            });
+        }
+        let start_date = NaiveDate::decode(reader)?;
+        let end_date = NaiveDate::decode(reader)?;
+        let days = Vec::decode(reader)?;
+        Ok(Self { facility_name, start_date, end_date, days })
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        self.facility_name.encode(writer)?;
+        if !protocol::has_dates(writer.version()) {
+            let mut days: Vec<Day> = self.dates()
+                .take(7) // every day after the first week repeats
+                .map(|date| date.weekday().into())
+                .collect();
+            days.sort();
+            days.dedup();
+            return days.encode(writer);
+        }
+        self.start_date.encode(writer)?;
+        self.end_date.encode(writer)?;
+        self.days.encode(writer)
+    }
+}
+
 /// For booking a facility.
 #[derive(ByteableDerive, Debug, Clone)]
 pub struct BookRequest {
     pub facility_name: String,
-    pub start_time: Time,
-    pub end_time: Time
+    pub interval: Interval,
+    /// The number of seats to book, which is 1 in older versions.
+    #[byteable(since_version = protocol::CAPACITY_VERSION, default = one_seat)]
+    pub seats: u16
+}
+
+/// For booking a facility repeatedly, with `interval` as the first occurrence,
+/// moved to each date of the rule (starting from its start date) for the rest.
+///
+/// Either every occurrence is booked, or none are.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct RecurringBookRequest {
+    pub facility_name: String,
+    pub interval: Interval,
+    pub rule: RecurrenceRule,
+    /// The number of seats to book for every occurrence, which is 1 in older versions.
+    #[byteable(since_version = protocol::CAPACITY_VERSION, default = one_seat)]
+    pub seats: u16
+}
+
+/// The number of seats booked in protocol versions before capacity was added.
+fn one_seat() -> u16 {
+    1
 }
 
 /// For modifying a booking.
 #[derive(ByteableDerive, Debug, Clone)]
 pub struct OffsetBookingRequest {
     pub booking_id: Uuid,
-    pub offset_hours: Hour,
-    pub offset_min: Minute,
-    pub negative: bool
+    /// Negative to move the booking earlier.
+    #[byteable(with = signed_duration_or_hours_minutes)]
+    pub offset: Duration,
+    /// If the booking is an occurrence of a recurring booking, offset every occurrence instead.
+    #[byteable(since_version = protocol::RECURRENCE_VERSION)]
+    pub whole_series: bool
 }
 
 /// For cancelling a booking.
 #[derive(ByteableDerive, Debug, Clone)]
 pub struct CancelBookingRequest {
-    pub booking_id: Uuid
+    pub booking_id: Uuid,
+    /// If the booking is an occurrence of a recurring booking, cancel every occurrence instead.
+    #[byteable(since_version = protocol::RECURRENCE_VERSION)]
+    pub whole_series: bool
 }
 
 /// For extending a booking.
 #[derive(ByteableDerive, Debug, Clone)]
 pub struct ExtendBookingRequest {
     pub booking_id: Uuid,
-    pub extend_hours: Hour,
-    pub extend_min: Minute
+    /// Negative to shorten the booking.
+    #[byteable(with = duration_or_hours_minutes)]
+    pub extension: Duration
 }
 
 /// For registering a monitor callback.
@@ -54,82 +137,89 @@ pub struct MonitorFacilityRequest {
     pub seconds_to_monitor: u8
 }
 
+/// For finding facilities by their attributes, and optionally by being free throughout an interval.
+///
+/// Filters which are left empty match every facility.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct SearchFacilitiesRequest {
+    /// The fewest seats the facility can have.
+    pub min_capacity: u16,
+    /// The building it must be in, ignoring case.
+    pub building: Option<String>,
+    pub floor: Option<i16>,
+    /// Tags of equipment it must all have, ignoring case.
+    pub equipment: Vec<String>,
+    /// Whether it must have step-free access.
+    pub accessible: bool,
+    /// An interval it must be open and have `min_capacity` seats (or at least 1) free throughout.
+    pub free_during: Option<Interval>
+}
+
+impl SearchFacilitiesRequest {
+    /// Returns if the facility's attributes match the filters, leaving out `free_during`.
+    pub fn matches(&self, attributes: &FacilityAttributes) -> bool {
+        attributes.capacity >= self.min_capacity
+            && self.building
+                .as_ref()
+                .is_none_or(|building| attributes.building.eq_ignore_ascii_case(building))
+            && self.floor.is_none_or(|floor| attributes.floor == floor)
+            && self.equipment
+                .iter()
+                .all(|tag| attributes.has_equipment(tag))
+            && (!self.accessible || attributes.accessible)
+    }
+}
+
+/// For administering facilities, which needs the server's admin token.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct AdminRequest {
+    pub admin_token: String,
+    pub action: AdminAction
+}
+
+/// The ways facilities can be administered.
+#[derive(ByteableDerive, Debug, Clone)]
+pub enum AdminAction {
+    /// Add a facility, which is always open.
+    CreateFacility {
+        facility_name: String,
+        attributes: FacilityAttributes,
+        turnaround_buffer: Duration
+    },
+    RenameFacility {
+        facility_name: String,
+        new_name: String
+    },
+    /// Remove a facility, dealing with its bookings by the policy.
+    RetireFacility {
+        facility_name: String,
+        policy: RetirePolicy
+    },
+    ListFacilities
+}
+
+/// What to do with a retired facility's bookings.
+#[derive(ByteableDerive, Debug, Clone, PartialEq, Eq)]
+pub enum RetirePolicy {
+    /// Don't retire the facility if it has any bookings.
+    Reject,
+    Cancel,
+    /// Move them to another facility, keeping their IDs; if any of them don't fit, the facility isn't retired.
+    MoveTo {
+        facility_name: String
+    }
+}
+
 /// The possible requests to the server.
-#[derive(Debug, Clone)]
+#[derive(ByteableDerive, Debug, Clone)]
 pub enum RequestType {
     Availability(AvailabilityRequest),
     Book(BookRequest),
     Offset(OffsetBookingRequest),
     Monitor(MonitorFacilityRequest),
     Cancel(CancelBookingRequest),
-    Extend(ExtendBookingRequest)
-}
-
-impl Byteable for RequestType {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> {
-        let discriminant = u8::from_bytes(data)?;
-        let val = match discriminant {
-            0 => {  
-                let request = AvailabilityRequest::from_bytes(data)?;
-                Self::Availability(request)
-            },
-            1 => {
-                let request = BookRequest::from_bytes(data)?;
-                Self::Book(request)
-            },
-            2 => {
-                let request = OffsetBookingRequest::from_bytes(data)?;
-                Self::Offset(request)
-            },
-            3 => {
-                let request = MonitorFacilityRequest::from_bytes(data)?;
-                Self::Monitor(request)
-            },
-            4 => {
-                let request = CancelBookingRequest::from_bytes(data)?;
-                Self::Cancel(request)
-            },
-            5 => {
-                let request = ExtendBookingRequest::from_bytes(data)?;
-                Self::Extend(request)
-            }
-            other => Err(format!("Unsupported request type discriminant: {other}"))?
-        };
-        Ok(val)
-    }
-
-    fn to_bytes(self) -> Vec<u8> {
-        match self {
-            RequestType::Availability(request) => {
-                let mut request_bytes = request.to_bytes();
-                request_bytes.insert(0, 0);
-                request_bytes
-            },
-            RequestType::Book(request) => {
-                let mut request_bytes = request.to_bytes();
-                request_bytes.insert(0, 1);
-                request_bytes
-            },
-            RequestType::Offset(request) => {
-                let mut request_bytes = request.to_bytes();
-                request_bytes.insert(0, 2);
-                request_bytes
-            },
-            RequestType::Monitor(request) => {
-                let mut request_bytes = request.to_bytes();
-                request_bytes.insert(0, 3);
-                request_bytes
-            },
-            RequestType::Cancel(request) => {
-                let mut request_bytes = request.to_bytes();
-                request_bytes.insert(0, 4);
-                request_bytes
-            },
-            RequestType::Extend(request) => {
-                let mut request_bytes = request.to_bytes();
-                request_bytes.insert(0, 5);
-                request_bytes
-            },
-        }
-    }
-}
\ No newline at end of file
+    Extend(ExtendBookingRequest),
+    RecurringBook(RecurringBookRequest),
+    SearchFacilities(SearchFacilitiesRequest),
+    Admin(AdminRequest)
+}
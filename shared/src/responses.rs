@@ -1,11 +1,282 @@
+use std::fmt::Display;
 use derive::ByteableDerive;
-use crate::Byteable;
+use chrono::{Datelike, NaiveDate};
+use crate::{error::{self, Error}, protocol, facility::{FacilityAttributes, FacilitySummary}, time::{date_or_day, Day, Duration, Interval}, Byteable, Reader, Writer};
 use uuid::Uuid;
 
 /// Structure of a raw response from the server.
-#[derive(ByteableDerive, Debug, Clone)]
+///
+/// ## Versions
+/// In protocol version 1, the response type was sent as an `is_error` flag and its rendered text.
+/// Such responses are decoded into `ResponseType::Error` (with `Error::Unspecified`) or `ResponseType::Text`.
+#[derive(Debug, Clone)]
 pub struct RawResponse {
     pub request_id: Uuid,
-    pub is_error: bool,
-    pub message: String
-}
\ No newline at end of file
+    pub response_type: ResponseType
+}
+
+impl Byteable for RawResponse {
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let request_id = Uuid::decode(reader)?;
+        let response_type = match reader.version() {
+            1 => {
+                let is_error = bool::decode(reader)?;
+                let message = String::decode(reader)?;
+                match is_error {
+                    true => ResponseType::Error(Error::Unspecified { message }),
+                    false => ResponseType::Text { message }
+                }
+            },
+            _ => ResponseType::decode(reader)?
+        };
+        Ok(Self { request_id, response_type })
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        self.request_id.encode(writer)?;
+        match writer.version() {
+            1 => {
+                self.response_type.is_error().encode(writer)?;
+                self.response_type.to_string().encode(writer)
+            },
+            _ => self.response_type.encode(writer)
+        }
+    }
+}
+
+/// The possible responses from the server.
+#[derive(ByteableDerive, Debug, Clone)]
+pub enum ResponseType {
+    Availability {
+        facility_name: String,
+        availabilities: Vec<DayAvailability>,
+        /// The time which must be left free between consecutive bookings.
+        #[byteable(since_version = protocol::TURNAROUND_VERSION)]
+        turnaround_buffer: Duration
+    },
+    BookingCreated {
+        booking_id: Uuid
+    },
+    BookingOffset {
+        booking_id: Uuid
+    },
+    MonitorRegistered {
+        facility_name: String,
+        seconds_to_monitor: u8
+    },
+    BookingCancelled {
+        booking_id: Uuid
+    },
+    BookingExtended {
+        booking_id: Uuid
+    },
+    /// Sent to monitoring addresses when a booking for the facility changes.
+    MonitorUpdate {
+        facility_name: String,
+        availability: DayAvailability
+    },
+    Error(#[byteable(with = error::for_version)] Error),
+    /// Free text, only produced when decoding a successful protocol version 1 response.
+    Text {
+        message: String
+    },
+    /// Every occurrence of a recurring booking, along with its booking ID.
+    RecurringBookingCreated {
+        series_id: Uuid,
+        occurrences: Vec<(Uuid, Interval)>
+    },
+    SeriesOffset {
+        series_id: Uuid,
+        occurrences: u16
+    },
+    SeriesCancelled {
+        series_id: Uuid,
+        occurrences: u16
+    },
+    /// The facilities matching a search, by name.
+    SearchResults {
+        facilities: Vec<(String, FacilityAttributes)>
+    },
+    FacilityCreated {
+        facility_name: String
+    },
+    /// Also sent to addresses monitoring the facility, if their protocol version has it.
+    FacilityRenamed {
+        facility_name: String,
+        new_name: String
+    },
+    /// Also sent to addresses monitoring the facility, if their protocol version has it, which then stop monitoring it.
+    FacilityRetired {
+        facility_name: String,
+        /// The number of its bookings which were cancelled.
+        cancelled: u32,
+        /// The number of its bookings which were moved to another facility.
+        moved: u32
+    },
+    FacilityList {
+        facilities: Vec<FacilitySummary>
+    }
+}
+
+impl ResponseType {
+    /// Returns if the response is an error.
+    pub fn is_error(&self) -> bool {
+        matches!(self, ResponseType::Error(_))
+    }
+
+    /// The oldest protocol version which has the response, so it can't be sent to clients of older versions.
+    pub fn since_version(&self) -> u8 {
+        match self {
+            ResponseType::RecurringBookingCreated { .. }
+                | ResponseType::SeriesOffset { .. }
+                | ResponseType::SeriesCancelled { .. } => protocol::RECURRENCE_VERSION,
+            ResponseType::SearchResults { .. } => protocol::SEARCH_VERSION,
+            ResponseType::FacilityCreated { .. }
+                | ResponseType::FacilityRenamed { .. }
+                | ResponseType::FacilityRetired { .. }
+                | ResponseType::FacilityList { .. } => protocol::ADMIN_VERSION,
+            _ => protocol::MIN_PROTOCOL_VERSION
+        }
+    }
+}
+
+impl Display for ResponseType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResponseType::Availability { facility_name, availabilities, turnaround_buffer } => {
+                match turnaround_buffer == &Duration::ZERO {
+                    true => writeln!(f, "Availabilities for {facility_name}:")?,
+                    false => writeln!(f, "Availabilities for {facility_name} ({turnaround_buffer} (H:MM) left free between bookings):")?
+                }
+                for availability in availabilities {
+                    write!(f, "-----\n{availability}-----\n")?;
+                }
+                Ok(())
+            },
+            ResponseType::BookingCreated { booking_id } => write!(f, "Successfully added new booking with ID: {booking_id}"),
+            ResponseType::BookingOffset { booking_id } => write!(f, "Booking {booking_id} successfully offsetted"),
+            ResponseType::MonitorRegistered { facility_name, seconds_to_monitor } => {
+                write!(f, "Successfully registered for monitoring facility {facility_name} for {seconds_to_monitor}s")
+            },
+            ResponseType::BookingCancelled { booking_id } => write!(f, "Booking {booking_id} successfully cancelled"),
+            ResponseType::BookingExtended { booking_id } => write!(f, "Booking {booking_id} successfully extended"),
+            ResponseType::RecurringBookingCreated { series_id, occurrences } => {
+                writeln!(f, "Successfully added {} occurrences of recurring booking {series_id}:", occurrences.len())?;
+                for (booking_id, interval) in occurrences {
+                    writeln!(f, "{booking_id}: {interval}")?;
+                }
+                Ok(())
+            },
+            ResponseType::SeriesOffset { series_id, occurrences } => {
+                write!(f, "All {occurrences} occurrences of recurring booking {series_id} successfully offsetted")
+            },
+            ResponseType::SeriesCancelled { series_id, occurrences } => {
+                write!(f, "All {occurrences} occurrences of recurring booking {series_id} successfully cancelled")
+            },
+            ResponseType::SearchResults { facilities } => {
+                write!(f, "Found {} matching facilities:", facilities.len())?;
+                for (facility_name, attributes) in facilities {
+                    write!(f, "\n{facility_name}: {attributes}")?;
+                }
+                Ok(())
+            },
+            ResponseType::FacilityCreated { facility_name } => write!(f, "Successfully created facility {facility_name}"),
+            ResponseType::FacilityRenamed { facility_name, new_name } => write!(f, "Facility {facility_name} was renamed to {new_name}"),
+            ResponseType::FacilityRetired { facility_name, cancelled, moved } => {
+                write!(f, "Facility {facility_name} was retired; {cancelled} of its bookings were cancelled and {moved} were moved")
+            },
+            ResponseType::FacilityList { facilities } => {
+                write!(f, "{} facilities:", facilities.len())?;
+                for facility in facilities {
+                    write!(f, "\n{facility}")?;
+                }
+                Ok(())
+            },
+            ResponseType::MonitorUpdate { facility_name, availability } => {
+                write!(f, "-----\nA booking for {facility_name} was updated on {}; new availabilities:\n{availability}-----", availability.date)
+            },
+            ResponseType::Error(error) => write!(f, "{error}"),
+            ResponseType::Text { message } => write!(f, "{message}"),
+        }
+    }
+}
+
+/// The slots of a facility with seats free on a date.
+#[derive(ByteableDerive, Debug, Clone)]
+pub struct DayAvailability {
+    #[byteable(with = date_or_day)]
+    pub date: NaiveDate,
+    #[byteable(with = slots_or_intervals)]
+    pub slots: Vec<Slot>
+}
+
+impl Display for DayAvailability {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{} {}", Day::from(self.date.weekday()), self.date)?;
+        for (i, slot) in self.slots.iter().enumerate() {
+            writeln!(f, "{}. {}", i + 1, slot)?;
+        }
+        Ok(())
+    }
+}
+
+/// A time range when some seats of a facility are free.
+#[derive(ByteableDerive, Debug, Clone, PartialEq, Eq)]
+pub struct Slot {
+    pub interval: Interval,
+    pub free_seats: u16
+}
+
+impl Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.free_seats {
+            1 => write!(f, "{} (1 seat free)", self.interval),
+            free_seats => write!(f, "{} ({free_seats} seats free)", self.interval)
+        }
+    }
+}
+
+/// For `ByteableDerive`'s `with` attribute; encodes slots as just their intervals
+/// in protocol versions before capacity was added (see `protocol::CAPACITY_VERSION`),
+/// joining touching slots as those versions only tell free from busy.
+///
+/// Such slots decode as having 1 seat free.
+mod slots_or_intervals {
+    use crate::{protocol, time::Interval, Byteable, Reader, Writer};
+    use super::Slot;
+
+    pub fn decode(reader: &mut Reader<'_>) -> Result<Vec<Slot>, String> {
+        if protocol::has_capacity(reader.version()) {
+            return Vec::<Slot>::decode(reader);
+        }
+        let intervals = Vec::<Interval>::decode(reader)?;
+        Ok(
+            intervals
+                .into_iter()
+                .map(|interval| Slot { interval, free_seats: 1 })
+                .collect()
+        )
+    }
+
+    pub fn encode(slots: &[Slot], writer: &mut Writer) -> Result<(), String> {
+        if protocol::has_capacity(writer.version()) {
+            return writer.write_length_prefixed(|writer| {
+                for slot in slots {
+                    slot.encode(writer)?;
+                }
+                Ok(())
+            });
+        }
+
+        let intervals = slots
+            .iter()
+            .fold(Vec::new(), |mut intervals: Vec<Interval>, slot| {
+                match intervals.last().and_then(|last| last.union(&slot.interval)) {
+                    Some(joined) => *intervals.last_mut().unwrap() = joined,
+                    None => intervals.push(slot.interval.clone())
+                }
+                intervals
+            });
+        intervals.encode(writer)
+    }
+}
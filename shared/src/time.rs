@@ -1,104 +1,85 @@
-use std::{fmt::Display, ops::{Add, Sub}, str::FromStr};
+use std::{fmt::Display, str::FromStr};
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, Timelike, Weekday};
 use derive::ByteableDerive;
 use strum::{Display, EnumIter};
-use crate::Byteable;
+use crate::{protocol, Byteable, Reader, Writer};
 
 /// Representation of time for a booking.
 #[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq, ByteableDerive)]
 pub struct Time {
-    pub day: Day,
+    #[byteable(with = date_or_day)]
+    pub date: NaiveDate,
     pub hour: Hour,
     pub minute: Minute
 }
 
 impl Time {
-    /// Offsets by the given time. 
-    pub fn offset(
-        &mut self, 
-        hours: Hour,
-        minutes: Minute,
-        negative: bool
-    ) {
-        if negative {
-            // Handle negative offset
-            let mut new_minute = self.minute.0;
-            let mut new_hour = self.hour.0;
-            let mut new_day = self.day.clone();
-            
-            // Handle minute subtraction
-            if new_minute < minutes.0 {
-                new_minute = new_minute + 60 - minutes.0;
-                new_hour = new_hour.checked_sub(1).unwrap_or(23);
-            } else {
-                new_minute = new_minute - minutes.0;
-            }
-            
-            // Track days to subtract
-            let days_to_subtract = if new_hour < hours.0 {
-                new_hour = new_hour + 24 - hours.0;
-                1
-            } else {
-                new_hour = new_hour - hours.0;
-                0
-            };
-            
-            // Apply day change
-            for _ in 0..days_to_subtract {
-                new_day = match new_day {
-                    Day::Monday => Day::Sunday,
-                    Day::Tuesday => Day::Monday,
-                    Day::Wednesday => Day::Tuesday,
-                    Day::Thursday => Day::Wednesday,
-                    Day::Friday => Day::Thursday,
-                    Day::Saturday => Day::Friday,
-                    Day::Sunday => Day::Saturday,
-                };
-            }
-            
-            self.minute = Minute(new_minute);
-            self.hour = Hour(new_hour);
-            self.day = new_day;
+    /// The start of the date.
+    pub fn midnight(date: NaiveDate) -> Self {
+        Self {
+            date,
+            hour: Hour(0),
+            minute: Minute(0)
         }
-        else {
-            // Handle positive offset
-            let mut new_minute = self.minute.0 + minutes.0;
-            let mut new_hour = self.hour.0;
-            let mut new_day = self.day.clone();
-            
-            // Handle minute carry
-            if new_minute >= 60 {
-                new_hour = new_hour + 1;
-                new_minute = new_minute % 60;
-            }
-            
-            // Handle hour addition and carry
-            new_hour = new_hour + hours.0;
-            let days_to_add = new_hour / 24;
-            new_hour = new_hour % 24;
-            
-            // Apply day change
-            for _ in 0..days_to_add {
-                new_day = match new_day {
-                    Day::Monday => Day::Tuesday,
-                    Day::Tuesday => Day::Wednesday,
-                    Day::Wednesday => Day::Thursday,
-                    Day::Thursday => Day::Friday,
-                    Day::Friday => Day::Saturday,
-                    Day::Saturday => Day::Sunday,
-                    Day::Sunday => Day::Monday,
-                };
-            }
-            
-            self.minute = Minute(new_minute);
-            self.hour = Hour(new_hour);
-            self.day = new_day;
+    }
+
+    /// The day of the week.
+    pub fn day(&self) -> Day {
+        self.date.weekday().into()
+    }
+
+    /// Returns the time offset by the duration, or `None` if it's out of range.
+    pub fn checked_add(&self, duration: Duration) -> Option<Self> {
+        self.to_datetime()?
+            .checked_add_signed(chrono::Duration::minutes(duration.0.into()))
+            .map(Self::from_datetime)
+    }
+
+    /// Converts to a chrono `NaiveDateTime`, or `None` if the hour or minute is out of range.
+    pub fn to_datetime(&self) -> Option<NaiveDateTime> {
+        self.date.and_hms_opt(self.hour.0.into(), self.minute.0.into(), 0)
+    }
+
+    /// Converts from a chrono `NaiveDateTime`, dropping any seconds.
+    pub fn from_datetime(datetime: NaiveDateTime) -> Self {
+        Self {
+            date: datetime.date(),
+            hour: Hour(datetime.hour() as u8),
+            minute: Minute(datetime.minute() as u8)
         }
     }
 }
 
 impl Display for Time {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}, {}:{}", self.day, self.hour, self.minute)
+        write!(f, "{} {}, {}:{}", self.day(), self.date, self.hour, self.minute)
+    }
+}
+
+/// Today's date, in the local time zone.
+pub fn today() -> NaiveDate {
+    chrono::Local::now().date_naive()
+}
+
+/// For `ByteableDerive`'s `with` attribute; encodes a date as its `Day` in protocol versions before dates were added
+/// (see `protocol::DATE_VERSION`), where it's decoded as the next occurrence of that day.
+pub mod date_or_day {
+    use chrono::{Datelike, NaiveDate};
+    use crate::{protocol, Byteable, Reader, Writer};
+    use super::{today, Day};
+
+    pub fn decode(reader: &mut Reader<'_>) -> Result<NaiveDate, String> {
+        match protocol::has_dates(reader.version()) {
+            true => NaiveDate::decode(reader),
+            false => Ok(Day::decode(reader)?.next_occurrence(today()))
+        }
+    }
+
+    pub fn encode(date: &NaiveDate, writer: &mut Writer) -> Result<(), String> {
+        match protocol::has_dates(writer.version()) {
+            true => date.encode(writer),
+            false => Day::from(date.weekday()).encode(writer)
+        }
     }
 }
 
@@ -143,6 +124,27 @@ impl Day {
             Day::Sunday => 6,
         }
     }
+
+    /// The first date on or after `from` which falls on this day.
+    pub fn next_occurrence(&self, from: NaiveDate) -> NaiveDate {
+        let from_day = Day::from(from.weekday());
+        let days_ahead = (self.to_u8() + 7 - from_day.to_u8()) % 7;
+        from + Days::new(days_ahead.into())
+    }
+}
+
+impl From<Weekday> for Day {
+    fn from(weekday: Weekday) -> Self {
+        match weekday {
+            Weekday::Mon => Day::Monday,
+            Weekday::Tue => Day::Tuesday,
+            Weekday::Wed => Day::Wednesday,
+            Weekday::Thu => Day::Thursday,
+            Weekday::Fri => Day::Friday,
+            Weekday::Sat => Day::Saturday,
+            Weekday::Sun => Day::Sunday,
+        }
+    }
 }
 
 
@@ -164,13 +166,269 @@ impl FromStr for Day {
 }
 
 impl Byteable for Day {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> where Self: Sized {
-        let val = u8::from_bytes(data)?;
-        Ok(Day::from_u8(val)?)
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let val = u8::decode(reader)?;
+        Day::from_u8(val)
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        self.to_u8().to_bytes()
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        self.to_u8().encode(writer)
+    }
+}
+
+/// A signed length of time, in minutes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Ord, Eq, ByteableDerive)]
+pub struct Duration(i32);
+
+impl Duration {
+    pub const ZERO: Duration = Duration(0);
+
+    pub fn from_minutes(minutes: i32) -> Self {
+        Self(minutes)
+    }
+
+    pub fn from_hours_minutes(hours: Hour, minutes: Minute) -> Self {
+        Self(i32::from(hours.0) * 60 + i32::from(minutes.0))
+    }
+
+    /// Returns `None` if it's out of range.
+    pub fn from_days(days: i32) -> Option<Self> {
+        days.checked_mul(24 * 60).map(Self)
+    }
+
+    pub fn num_minutes(&self) -> i32 {
+        self.0
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    /// Returns `None` on overflow.
+    pub fn checked_add(&self, rhs: Duration) -> Option<Self> {
+        self.0.checked_add(rhs.0).map(Self)
+    }
+
+    /// Returns `None` on overflow.
+    pub fn checked_sub(&self, rhs: Duration) -> Option<Self> {
+        self.0.checked_sub(rhs.0).map(Self)
+    }
+
+    /// Returns `None` on overflow.
+    pub fn checked_neg(&self) -> Option<Self> {
+        self.0.checked_neg().map(Self)
+    }
+
+    /// Splits the duration's magnitude into hours and minutes, for protocol versions before durations were added.
+    ///
+    /// Errors if it's 24 hours or more.
+    fn to_hours_minutes(self) -> Result<(Hour, Minute), String> {
+        let minutes = self.0.unsigned_abs();
+        let hours = u8::try_from(minutes / 60)
+            .map_err(|err| err.to_string())
+            .and_then(Hour::new)
+            .map_err(|_| format!("Durations of 24 hours or more need protocol version {} (got {self})", protocol::DURATION_VERSION))?;
+        Ok((hours, Minute((minutes % 60) as u8)))
+    }
+}
+
+impl Display for Duration {
+    /// Formats as `H:MM`, preceded by `-` if negative.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let minutes = self.0.unsigned_abs();
+        write!(f, "{sign}{}:{:02}", minutes / 60, minutes % 60)
+    }
+}
+
+impl FromStr for Duration {
+    type Err = String;
+
+    /// Parses `H:MM`, optionally preceded by `-`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (negative, unsigned) = match s.strip_prefix('-') {
+            Some(unsigned) => (true, unsigned),
+            None => (false, s)
+        };
+        let (hours, minutes) = unsigned
+            .split_once(':')
+            .ok_or_else(|| format!("Expected H:MM (got {s})"))?;
+        let hours = u16::from_str(hours).map_err(|err| err.to_string())?;
+        let minutes = Minute::from_str(minutes)?;
+
+        let duration = Self(i32::from(hours) * 60 + i32::from(minutes.0));
+        match negative {
+            true => Ok(Self(-duration.0)),
+            false => Ok(duration)
+        }
+    }
+}
+
+/// For `ByteableDerive`'s `with` attribute; encodes a non-negative duration as an `Hour` and `Minute`
+/// in protocol versions before durations were added (see `protocol::DURATION_VERSION`).
+///
+/// Encoding errors if the duration is negative, or 24 hours or more.
+pub mod duration_or_hours_minutes {
+    use crate::{protocol, Byteable, Reader, Writer};
+    use super::{Duration, Hour, Minute};
+
+    pub fn decode(reader: &mut Reader<'_>) -> Result<Duration, String> {
+        match protocol::has_durations(reader.version()) {
+            true => Duration::decode(reader),
+            false => Ok(Duration::from_hours_minutes(Hour::decode(reader)?, Minute::decode(reader)?))
+        }
+    }
+
+    pub fn encode(duration: &Duration, writer: &mut Writer) -> Result<(), String> {
+        if protocol::has_durations(writer.version()) {
+            return duration.encode(writer);
+        }
+        if duration.is_negative() {
+            return Err(format!("Negative durations need protocol version {} (got {duration})", protocol::DURATION_VERSION));
+        }
+        let (hours, minutes) = duration.to_hours_minutes()?;
+        hours.encode(writer)?;
+        minutes.encode(writer)
+    }
+}
+
+/// For `ByteableDerive`'s `with` attribute; encodes a duration as an `Hour`, a `Minute` and whether it's negative
+/// in protocol versions before durations were added (see `protocol::DURATION_VERSION`).
+///
+/// Encoding errors if the duration is 24 hours or more either way.
+pub mod signed_duration_or_hours_minutes {
+    use crate::{protocol, Byteable, Reader, Writer};
+    use super::{Duration, Hour, Minute};
+
+    pub fn decode(reader: &mut Reader<'_>) -> Result<Duration, String> {
+        if protocol::has_durations(reader.version()) {
+            return Duration::decode(reader);
+        }
+        let duration = Duration::from_hours_minutes(Hour::decode(reader)?, Minute::decode(reader)?);
+        match bool::decode(reader)? {
+            true => Ok(Duration(-duration.0)),
+            false => Ok(duration)
+        }
+    }
+
+    pub fn encode(duration: &Duration, writer: &mut Writer) -> Result<(), String> {
+        if protocol::has_durations(writer.version()) {
+            return duration.encode(writer);
+        }
+        let (hours, minutes) = duration.to_hours_minutes()?;
+        hours.encode(writer)?;
+        minutes.encode(writer)?;
+        duration.is_negative().encode(writer)
+    }
+}
+
+/// A half-open interval of time, `[start, end)`, which contains its start but not its end.
+///
+/// It's empty if the start and end are the same.
+#[derive(Debug, Clone, PartialEq, PartialOrd, Ord, Eq)]
+pub struct Interval {
+    start: Time,
+    end: Time
+}
+
+impl Interval {
+    /// Errors if `start` is after `end`.
+    pub fn new(start: Time, end: Time) -> Result<Self, String> {
+        if start > end {
+            return Err(format!("Start time ({start}) is after end time ({end})"));
+        }
+        Ok(Self { start, end })
+    }
+
+    pub fn start(&self) -> &Time {
+        &self.start
+    }
+
+    pub fn end(&self) -> &Time {
+        &self.end
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.start == self.end
+    }
+
+    /// Returns if the time is within the interval.
+    pub fn contains(&self, time: &Time) -> bool {
+        &self.start <= time && time < &self.end
+    }
+
+    /// Returns if the intervals share any time; intervals which only touch don't overlap.
+    pub fn overlaps(&self, other: &Interval) -> bool {
+        self.start < other.end && other.start < self.end
+    }
+
+    /// The time in both intervals, or `None` if they don't overlap.
+    pub fn intersection(&self, other: &Interval) -> Option<Interval> {
+        self.overlaps(other).then(|| Interval {
+            start: self.start.clone().max(other.start.clone()),
+            end: self.end.clone().min(other.end.clone())
+        })
+    }
+
+    /// The time in either interval, or `None` if there's a gap between them, so it's not a single interval.
+    pub fn union(&self, other: &Interval) -> Option<Interval> {
+        (self.start <= other.end && other.start <= self.end).then(|| Interval {
+            start: self.start.clone().min(other.start.clone()),
+            end: self.end.clone().max(other.end.clone())
+        })
+    }
+
+    /// The time in this interval but not the other, as up to 2 non-empty intervals in order.
+    pub fn difference(&self, other: &Interval) -> Vec<Interval> {
+        if !self.overlaps(other) {
+            return match self.is_empty() {
+                true => Vec::new(),
+                false => vec![self.clone()]
+            };
+        }
+        [
+            Interval { start: self.start.clone(), end: other.start.clone() },
+            Interval { start: other.end.clone(), end: self.end.clone() }
+        ]
+            .into_iter()
+            .filter(|part| part.start < part.end)
+            .collect()
+    }
+
+    /// Returns the interval moved by the duration, or `None` if it's out of range.
+    pub fn checked_add(&self, duration: Duration) -> Option<Interval> {
+        Some(Interval {
+            start: self.start.checked_add(duration)?,
+            end: self.end.checked_add(duration)?
+        })
+    }
+
+    /// Returns the interval with its start moved earlier and its end later by the duration,
+    /// or `None` if it's out of range or would end before it starts.
+    pub fn checked_expand(&self, duration: Duration) -> Option<Interval> {
+        let start = self.start.checked_add(duration.checked_neg()?)?;
+        let end = self.end.checked_add(duration)?;
+        Interval::new(start, end).ok()
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}", self.start, self.end)
+    }
+}
+
+impl Byteable for Interval {
+    /// Errors if the start is after the end.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        let start = Time::decode(reader)?;
+        let end = Time::decode(reader)?;
+        Interval::new(start, end)
+    }
+
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        self.start.encode(writer)?;
+        self.end.encode(writer)
     }
 }
 
@@ -188,22 +446,22 @@ impl Hour {
 }
 
 impl Byteable for Hour {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> where Self: Sized {
-        let val = u8::from_bytes(data)?;
-        Ok(Self(val))
+    /// Errors if it's out of range.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        Self::new(u8::decode(reader)?)
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        u8::to_bytes(self.0)
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        self.0.encode(writer)
     }
 }
 
 impl Display for Hour {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.0 < 10 {
-            return write!(f, "0{}", self.0);
+            write!(f, "0{}", self.0)
         } else {
-            return write!(f, "{}", self.0);
+            write!(f, "{}", self.0)
         }
         
     }
@@ -219,22 +477,6 @@ impl FromStr for Hour {
     }
 }
 
-impl Add<Hour> for Hour {
-    type Output = Hour;
-
-    fn add(self, rhs: Hour) -> Self::Output {
-        Hour((self.0 + rhs.0) % 24)
-    }
-}
-
-impl Sub<Hour> for Hour {
-    type Output = Hour;
-
-    fn sub(self, rhs: Hour) -> Self::Output {
-        Hour((self.0 - rhs.0) % 24)
-    }
-}
-
 /// A u8 between 0 and 60.
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Ord, Eq)]
 pub struct Minute(u8);
@@ -251,22 +493,22 @@ impl Minute {
 }
 
 impl Byteable for Minute {
-    fn from_bytes(data: &mut Vec<u8>) -> Result<Self, String> where Self: Sized {
-        let val = u8::from_bytes(data)?;
-        Ok(Self(val))
+    /// Errors if it's out of range.
+    fn decode(reader: &mut Reader<'_>) -> Result<Self, String> {
+        Self::new(u8::decode(reader)?)
     }
 
-    fn to_bytes(self) -> Vec<u8> {
-        u8::to_bytes(self.0)
+    fn encode(&self, writer: &mut Writer) -> Result<(), String> {
+        self.0.encode(writer)
     }
 }
 
 impl Display for Minute {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         if self.0 < 10 {
-            return write!(f, "0{}", self.0);
+            write!(f, "0{}", self.0)
         } else {
-            return write!(f, "{}", self.0);
+            write!(f, "{}", self.0)
         }
         
     }
@@ -281,19 +523,3 @@ impl FromStr for Minute {
         Self::new(val)
     }
 }
-
-impl Add<Minute> for Minute {
-    type Output = Minute;
-
-    fn add(self, rhs: Minute) -> Self::Output {
-        Minute((self.0 + rhs.0) % 60)
-    }
-}
-
-impl Sub<Minute> for Minute {
-    type Output = Minute;
-
-    fn sub(self, rhs: Minute) -> Self::Output {
-        Minute((self.0 - rhs.0) % 60)
-    }
-}
\ No newline at end of file
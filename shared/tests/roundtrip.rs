@@ -0,0 +1,155 @@
+//! Round-trip properties of the `Byteable` impls for primitive and container types:
+//! decoding what was encoded gives back the same value, and consumes every byte.
+//!
+//! `DateTime<Utc>` is only `Byteable` with the `chrono-datetime` feature, which the crate's tests enable.
+
+use std::{collections::BTreeMap, fmt::Debug};
+#[cfg(feature = "chrono-datetime")]
+use chrono::{DateTime, Utc};
+use proptest::prelude::*;
+use shared::{time::Time, Byteable, LengthEncoding, Reader, Writer};
+
+/// Encodes the value in the length encoding, then decodes it back.
+fn roundtrip<T: Byteable + PartialEq + Debug>(value: &T, length_encoding: LengthEncoding) -> Result<(), TestCaseError> {
+    let mut writer = Writer::new();
+    writer.set_length_encoding(length_encoding);
+    value.encode(&mut writer).map_err(TestCaseError::fail)?;
+    let bytes = writer.into_bytes();
+
+    let mut reader = Reader::new(&bytes);
+    reader.set_length_encoding(length_encoding);
+    let decoded = T::decode(&mut reader).map_err(TestCaseError::fail)?;
+    prop_assert_eq!(&decoded, value);
+    prop_assert!(reader.is_empty(), "{} bytes left over", reader.remaining());
+    Ok(())
+}
+
+/// Round-trips the value in both length encodings used by messages.
+fn check<T: Byteable + PartialEq + Debug>(value: T) -> Result<(), TestCaseError> {
+    roundtrip(&value, LengthEncoding::U16)?;
+    roundtrip(&value, LengthEncoding::Varint)
+}
+
+fn decode<T: Byteable>(bytes: &[u8]) -> Result<T, String> {
+    T::decode(&mut Reader::new(bytes))
+}
+
+#[cfg(feature = "chrono-datetime")]
+fn datetime() -> impl Strategy<Value = DateTime<Utc>> {
+    let min = DateTime::<Utc>::MIN_UTC.timestamp();
+    let max = DateTime::<Utc>::MAX_UTC.timestamp();
+    (min + 1..max, 0..1_000_000_000u32)
+        .prop_map(|(secs, nanos)| DateTime::from_timestamp(secs, nanos).expect("Timestamp should be in range"))
+}
+
+proptest! {
+    #[test]
+    fn ints(a: u16, b: u32, c: u64, d: u128, e: i8, f: i16, g: i32, h: i64, i: i128) {
+        check(a)?;
+        check(b)?;
+        check(c)?;
+        check(d)?;
+        check(e)?;
+        check(f)?;
+        check(g)?;
+        check(h)?;
+        check(i)?;
+    }
+
+    #[test]
+    fn ints_are_big_endian(value: u32) {
+        let mut writer = Writer::new();
+        value.encode(&mut writer).unwrap();
+        prop_assert_eq!(writer.into_bytes(), value.to_be_bytes().to_vec());
+    }
+
+    #[test]
+    fn options(number: Option<u64>, text: Option<String>, nested: Option<Option<i16>>) {
+        check(number)?;
+        check(text)?;
+        check(nested)?;
+    }
+
+    #[test]
+    fn invalid_option_tags(tag in 2..=u8::MAX, value: u32) {
+        let mut bytes = vec![tag];
+        bytes.extend_from_slice(&value.to_be_bytes());
+        prop_assert!(decode::<Option<u32>>(&bytes).is_err());
+    }
+
+    #[test]
+    fn arrays(numbers: [u16; 4], texts: [String; 2], empty: [u64; 0]) {
+        check(numbers)?;
+        check(texts)?;
+        check(empty)?;
+    }
+
+    #[test]
+    fn truncated_arrays(numbers: [u32; 3], cut in 1..12usize) {
+        let mut writer = Writer::new();
+        numbers.encode(&mut writer).unwrap();
+        let bytes = writer.into_bytes();
+        prop_assert!(decode::<[u32; 3]>(&bytes[..bytes.len() - cut]).is_err());
+    }
+
+    #[test]
+    fn tuples(a: (u8,), b: (u16, String), c: (i32, bool, Option<u8>), d: (u8, u16, u32, u64, i64, String)) {
+        check(a)?;
+        check(b)?;
+        check(c)?;
+        check(d)?;
+    }
+
+    #[test]
+    fn boxes(number: u64, text: String) {
+        check(Box::new(number))?;
+        check(Box::new(text))?;
+    }
+
+    #[test]
+    fn maps(map: BTreeMap<u16, String>, nested: BTreeMap<String, Vec<i32>>) {
+        check(map)?;
+        check(nested)?;
+    }
+
+    #[test]
+    fn repeated_map_keys(key: u16, first: u32, second: u32) {
+        let mut writer = Writer::new();
+        writer.write_length_prefixed(|writer| {
+            for value in [first, second] {
+                key.encode(writer)?;
+                value.encode(writer)?;
+            }
+            Ok(())
+        }).unwrap();
+        prop_assert!(decode::<BTreeMap<u16, u32>>(&writer.into_bytes()).is_err());
+    }
+
+    #[cfg(feature = "chrono-datetime")]
+    #[test]
+    fn datetimes(datetime in datetime()) {
+        check(datetime)?;
+    }
+
+    #[cfg(feature = "chrono-datetime")]
+    #[test]
+    fn out_of_range_datetimes(secs in DateTime::<Utc>::MAX_UTC.timestamp() + 1.., nanos in 0..1_000_000_000u32) {
+        let mut writer = Writer::new();
+        secs.encode(&mut writer).unwrap();
+        nanos.encode(&mut writer).unwrap();
+        prop_assert!(decode::<DateTime<Utc>>(&writer.into_bytes()).is_err());
+    }
+
+    #[test]
+    fn out_of_range_times(hour in 0..=u8::MAX, minute in 0..=u8::MAX) {
+        let mut writer = Writer::new();
+        chrono::NaiveDate::from_ymd_opt(2026, 10, 20).unwrap().encode(&mut writer).unwrap();
+        hour.encode(&mut writer).unwrap();
+        minute.encode(&mut writer).unwrap();
+        let decoded = decode::<Time>(&writer.into_bytes());
+        prop_assert_eq!(decoded.is_ok(), hour < 24 && minute < 60);
+        if let Ok(time) = decoded {
+            prop_assert!(time.to_datetime().is_some());
+        }
+    }
+}